@@ -0,0 +1,595 @@
+//! Getting clearance for a URL: picking a mirror that serves a solvable challenge, reusing a
+//! clearance that still works, and otherwise solving rounds until the page comes through. This is
+//! what `clear` and `fetch` run, and what the long-running commands reuse.
+
+use crate::{cache, clearance, config, cookies, html_parser, network_client, pow_solver, report, utils};
+use log::{debug, info, warn};
+use reqwest::header::{HeaderMap, HeaderValue, ORIGIN, REFERER};
+use cookie_store::CookieExpiration;
+use reqwest_cookie_store::CookieStoreMutex;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+#[derive(Debug)]
+pub enum AcquireError {
+    Network(network_client::NetworkError),
+    Parse(html_parser::ParseError),
+    Solve(pow_solver::SolveError),
+    Io(std::io::Error),
+    UrlParse(url::ParseError),
+    Config(config::ConfigError),
+    /// The page still presented a challenge after this many rounds of solving.
+    NotCleared { rounds: u32 },
+    Internal(String),
+    /// The failure of a clearance another caller was already obtaining (see `single_flight`).
+    Shared(Arc<AcquireError>),
+}
+
+impl std::fmt::Display for AcquireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcquireError::Network(err) => write!(f, "Network error: {}", err),
+            AcquireError::Parse(err) => write!(f, "Parsing error: {}", err),
+            AcquireError::Solve(err) => write!(f, "Solver error: {}", err),
+            AcquireError::Io(err) => write!(f, "IO error: {}", err),
+            AcquireError::UrlParse(err) => write!(f, "URL parsing error: {}", err),
+            AcquireError::Config(err) => write!(f, "Config error: {}", err),
+            AcquireError::NotCleared { rounds } => write!(f, "Page still presents a challenge after {} round(s) of solving", rounds),
+            AcquireError::Internal(message) => write!(f, "Error: {}", message),
+            AcquireError::Shared(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for AcquireError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AcquireError::Network(err) => Some(err),
+            AcquireError::Parse(err) => Some(err),
+            AcquireError::Solve(err) => Some(err),
+            AcquireError::Io(err) => Some(err),
+            AcquireError::UrlParse(err) => Some(err),
+            AcquireError::Config(err) => Some(err),
+            AcquireError::NotCleared { .. } | AcquireError::Internal(_) => None,
+            AcquireError::Shared(err) => err.source(),
+        }
+    }
+}
+
+impl From<network_client::NetworkError> for AcquireError {
+    fn from(err: network_client::NetworkError) -> Self {
+        AcquireError::Network(err)
+    }
+}
+
+impl From<html_parser::ParseError> for AcquireError {
+    fn from(err: html_parser::ParseError) -> Self {
+        AcquireError::Parse(err)
+    }
+}
+
+impl From<pow_solver::SolveError> for AcquireError {
+    fn from(err: pow_solver::SolveError) -> Self {
+        AcquireError::Solve(err)
+    }
+}
+
+impl From<std::io::Error> for AcquireError {
+    fn from(err: std::io::Error) -> Self {
+        AcquireError::Io(err)
+    }
+}
+
+impl From<url::ParseError> for AcquireError {
+    fn from(err: url::ParseError) -> Self {
+        AcquireError::UrlParse(err)
+    }
+}
+
+impl From<config::ConfigError> for AcquireError {
+    fn from(err: config::ConfigError) -> Self {
+        AcquireError::Config(err)
+    }
+}
+
+/// How far `acquire` goes.
+#[derive(Debug, Clone, Copy)]
+pub struct AcquireOptions {
+    /// Fetch the page with the new clearance, and solve again while it is still challenged.
+    pub fetch_page: bool,
+    /// Challenge rounds before giving up when `fetch_page` keeps getting a challenge.
+    pub max_rounds: u32,
+}
+
+impl Default for AcquireOptions {
+    fn default() -> Self {
+        AcquireOptions { fetch_page: false, max_rounds: 3 }
+    }
+}
+
+/// What `acquire` ended up with.
+pub struct Acquired {
+    /// The target that was used: the first one that served a usable page.
+    pub target: Target,
+    /// The targets tried before it, in the order they were given.
+    pub failed_mirrors: Vec<report::MirrorFailure>,
+    /// The existing clearance, when it still got the real page and nothing was solved.
+    pub reused: Option<String>,
+    /// True if the page came without a challenge and there was no clearance to reuse, so there
+    /// was nothing to clear.
+    pub nothing_to_clear: bool,
+    pub rounds: Vec<report::RoundReport>,
+    /// The clearance from the last round, if one was solved.
+    pub clearance: Option<clearance::Clearance>,
+    /// The page behind the clearance, with `fetch_page`, when it was reused or when there was nothing to clear.
+    pub page: Option<network_client::FetchedPage>,
+}
+
+/// Gets clearance from the first of `targets` that serves a usable page (racing them when the
+/// first target's settings say so), reusing the clearance in its jar if the page comes through
+/// with it. A new clearance is written to the cache for targets that use it once it has got the
+/// page, or straight away without `fetch_page`.
+pub async fn acquire(targets: Vec<Target>, options: AcquireOptions) -> Result<Acquired, AcquireError> {
+    // 1. Fetch initial page, failing over to the mirrors until one serves a solvable challenge
+    info!("Fetching initial page...");
+    let race = targets.len() > 1 && targets[0].settings.race.unwrap_or(false);
+    let mut failed_mirrors = Vec::new();
+    let (target, initial_page) = if race {
+        race_mirrors(targets, &mut failed_mirrors).await?
+    } else {
+        try_mirrors(targets, &mut failed_mirrors).await?
+    };
+    let mut acquired = Acquired { target, failed_mirrors, reused: None, nothing_to_clear: false, rounds: Vec::new(), clearance: None, page: None };
+
+    // The real page means there is nothing to solve: an existing clearance still works, or the page is not protected
+    let content = html_parser::classify_page(&initial_page.body) == html_parser::PageKind::Content;
+    match &acquired.target.cached_clearance {
+        Some(token) if content => {
+            info!("SSSG Clearance still valid (from cookie jar): {}", token);
+            acquired.reused = Some(token.clone());
+            acquired.page = Some(initial_page);
+            return Ok(acquired);
+        }
+        None if content => {
+            info!("{} served the page without a challenge, nothing to clear", acquired.target.origin);
+            acquired.nothing_to_clear = true;
+            acquired.page = Some(initial_page);
+            return Ok(acquired);
+        }
+        Some(_) => info!("Existing clearance was answered with a challenge, solving..."),
+        None => {}
+    }
+
+    // 2. Solve and submit, re-running the cycle if the cleared page still presents a challenge
+    let mut html_content = initial_page.body;
+    let mut round = 1;
+    loop {
+        let target = &acquired.target;
+        info!("Challenge round {}/{}", round, options.max_rounds);
+        let outcome = solve_with_salt_refresh(&target.session, &target.url, &mut html_content, &target.settings).await?;
+        acquired.rounds.push(report::RoundReport { round, challenge: outcome.challenge, solve: outcome.solve, check: outcome.check });
+        let clearance = outcome.clearance;
+        match &clearance.cookie {
+            Some(cookie) => info!("Set-Cookie attributes: {:?}", cookie),
+            None => warn!("{} did not set an sssg_clearance cookie; only the auth token is known", clearance.source),
+        }
+        acquired.clearance = Some(clearance);
+
+        if !options.fetch_page {
+            // Nothing to confirm it with; cache it as /answer (or /check) handed it out
+            if target.use_cache {
+                save_cache(target);
+            }
+            return Ok(acquired);
+        }
+
+        info!("\nFetching final page HTML with current sssg_clearance cookie...");
+        // The client now has the sssg_clearance cookie in its jar; the /check policy may already have fetched the page with it
+        let final_page = match outcome.page {
+            Some(page) => page,
+            None => network_client::fetch_page_html_with_cookies(&target.session, target.url.as_str()).await?,
+        };
+        match html_parser::classify_page(&final_page.body) {
+            html_parser::PageKind::Content => {
+                // Only a clearance that got us the page is worth trying first next time
+                if target.use_cache {
+                    save_cache(target);
+                }
+                acquired.page = Some(final_page);
+                return Ok(acquired);
+            }
+            html_parser::PageKind::Challenge => {
+                if round >= options.max_rounds {
+                    return Err(AcquireError::NotCleared { rounds: round });
+                }
+                warn!("Round {}: page still presents a challenge after clearance, solving again", round);
+                html_content = final_page.body;
+                round += 1;
+            }
+        }
+    }
+}
+
+/// Writes `target`'s cookie jar to the clearance cache. Failures are only logged: the clearance
+/// itself is still good.
+pub fn save_cache(target: &Target) {
+    let cache_dir = cache::cache_dir(target.settings.cache_dir.as_deref());
+    let saved = match target.cookie_store.lock() {
+        Ok(store) => cache::save(&cache_dir, &cache::CacheEntry::new(&target.origin, &store)),
+        Err(e) => Err(std::io::Error::other(e.to_string())),
+    };
+    match saved {
+        Ok(path) => info!("Clearance cached at {}", path.display()),
+        Err(e) => warn!("Could not write clearance cache: {}", e),
+    }
+}
+
+/// Solves `salt` at `difficulty` with the solver settings, enforcing `max_difficulty`.
+/// `default_timeout` applies when no solve timeout is configured.
+pub fn solve(settings: &config::Profile, salt: &str, difficulty: u32, default_timeout: Option<Duration>) -> Result<pow_solver::Solution, pow_solver::SolveError> {
+    let max_difficulty = settings.max_difficulty.unwrap_or(pow_solver::MAX_SOLVABLE_DIFFICULTY);
+    if difficulty > max_difficulty {
+        return Err(pow_solver::SolveError::DifficultyTooHigh { difficulty, max: max_difficulty });
+    }
+    let timeout = settings.solve_timeout.map(Duration::from_secs).or(default_timeout);
+    let num_threads = settings.threads();
+    let initial_attempt_seed = utils::generate_initial_attempt_nonce_seed();
+    info!("Starting PoW with difficulty {} on {} threads (initial seed: {})...", difficulty, num_threads, initial_attempt_seed);
+    pow_solver::solve_challenge(salt, difficulty, initial_attempt_seed, num_threads, timeout)
+}
+
+/// Works out the SSSG endpoints for this challenge: defaults, then whatever the page (or its scripts)
+/// reveals on the target's own origin, then the user's overrides.
+async fn resolve_endpoints(session: &network_client::Session, base_url: &Url, html_content: &str, overrides: &network_client::EndpointOverrides) -> network_client::SssgEndpoints {
+    let mut discovered = html_parser::discover_endpoints(html_content).same_origin(base_url);
+    if !discovered.has_paths() {
+        for src in html_parser::extract_challenge_script_srcs(html_content) {
+            let script_url = match base_url.join(&src) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!("Ignoring challenge script with invalid src {:?}: {}", src, e);
+                    continue;
+                }
+            };
+            match network_client::fetch_script_text(session, &script_url).await {
+                Ok(script_text) => discovered.fill_from(html_parser::discover_endpoints_in_script(&script_text).same_origin(base_url)),
+                Err(e) => warn!("Could not fetch challenge script {}: {}", script_url, e),
+            }
+            if discovered.has_paths() {
+                break;
+            }
+        }
+    }
+    debug!("Discovered endpoint hints: {:?}", discovered);
+
+    let mut endpoints = network_client::SssgEndpoints::default();
+    endpoints.merge(&discovered);
+    endpoints.merge(overrides);
+    endpoints
+}
+
+
+/// An origin clearance can be obtained from (the target URL's own, or a mirror serving the same
+/// paths), with its settings, cookie jar and session.
+pub struct Target {
+    pub url: Url,
+    pub origin: String,
+    pub settings: config::Profile,
+    pub cookie_store: Arc<CookieStoreMutex>,
+    /// The unexpired `sssg_clearance` the jar started with, if any.
+    pub cached_clearance: Option<String>,
+    /// When that clearance expires; `None` for a session cookie.
+    pub cached_expires_at: Option<SystemTime>,
+    pub session: network_client::Session,
+    /// Whether the jar was seeded from the clearance cache, and new clearances are written back to it.
+    pub use_cache: bool,
+}
+
+/// `url` with its origin replaced by `mirror`'s.
+pub fn mirror_url(url: &Url, mirror: &str) -> Result<Url, AcquireError> {
+    let mut mirrored = Url::parse(mirror)?;
+    mirrored.set_path(url.path());
+    mirrored.set_query(url.query());
+    mirrored.set_fragment(url.fragment());
+    Ok(mirrored)
+}
+
+impl Target {
+    /// Builds the cookie jar and session for requests to `url`'s origin. The jar is seeded from
+    /// `settings.cookies`, then, with `use_cache`, from the clearance cache.
+    pub fn new(url: Url, settings: config::Profile, use_cache: bool) -> Result<Target, AcquireError> {
+        let origin_url = url.origin().unicode_serialization();
+
+        // Seed the cookie jar from --cookies, then from the clearance cache for this origin (which wins on conflicts)
+        let mut seed_cookies = Vec::new();
+        if let Some(path) = &settings.cookies {
+            seed_cookies = cookies::import_file(path)?;
+            info!("Imported {} cookie(s) from {}", seed_cookies.len(), path.display());
+        }
+        let cache_dir = cache::cache_dir(settings.cache_dir.as_deref());
+        if use_cache {
+            match cache::load(&cache_dir, &origin_url) {
+                Ok(Some(entry)) => {
+                    match entry.age() {
+                        Some(age) => info!("Found cached cookies for {} (clearance obtained {:.0?} ago)", origin_url, age),
+                        None => info!("Found cached cookies for {} (imported)", origin_url),
+                    }
+                    seed_cookies.extend(entry.cookies);
+                }
+                Ok(None) => info!("No cached clearance for {}", origin_url),
+                Err(e) => warn!("Ignoring unreadable clearance cache for {}: {}", origin_url, e),
+            }
+        }
+        Target::with_cookies(url, settings, seed_cookies, use_cache)
+    }
+
+    /// Like `new`, but with the jar seeded from `seed_cookies` alone.
+    pub fn with_cookies(url: Url, settings: config::Profile, seed_cookies: Vec<cookie_store::Cookie<'static>>, use_cache: bool) -> Result<Target, AcquireError> {
+        let origin_url = url.origin().unicode_serialization();
+        let cookie_store = cookies::store_from(seed_cookies);
+        let cached = cache::clearance_cookie(&cookie_store, &url);
+        let cached_clearance = cached.map(|c| c.value().to_string());
+        let cached_expires_at = cached.and_then(|c| match c.expires {
+            CookieExpiration::AtUtc(at) => Some(SystemTime::from(at)),
+            CookieExpiration::SessionEnd => None,
+        });
+        if cached_clearance.is_none() {
+            info!("No unexpired sssg_clearance for {} in the cookie jar", origin_url);
+        }
+        let cookie_store = Arc::new(CookieStoreMutex::new(cookie_store));
+        let session = build_session(&settings, &url, &cookie_store)?;
+
+        Ok(Target { url, origin: origin_url, settings, cookie_store, cached_clearance, cached_expires_at, session, use_cache })
+    }
+
+    /// Drops the clearance the jar started with, so that `acquire` solves for a new one.
+    pub fn forget_cached_clearance(&mut self) {
+        if let Ok(mut store) = self.cookie_store.lock() {
+            let kept = store.iter_unexpired().filter(|c| c.name() != clearance::CLEARANCE_COOKIE).cloned().collect();
+            *store = cookies::store_from(kept);
+        }
+        self.cached_clearance = None;
+        self.cached_expires_at = None;
+    }
+}
+
+/// Builds a session for requests to `url` with `settings`, sharing `cookie_store`.
+pub fn build_session(settings: &config::Profile, url: &Url, cookie_store: &Arc<CookieStoreMutex>) -> Result<network_client::Session, AcquireError> {
+    Ok(session_builder(settings, url, cookie_store)?.build()?)
+}
+
+/// The builder behind `build_session`, for callers that need to adjust it further.
+pub fn session_builder(settings: &config::Profile, url: &Url, cookie_store: &Arc<CookieStoreMutex>) -> Result<network_client::SessionBuilder, AcquireError> {
+    let origin_url = url.origin().unicode_serialization();
+    let mut page_headers = network_client::default_page_headers();
+    let mut api_headers = network_client::default_api_headers();
+    // Add dynamic headers
+    for headers in [&mut page_headers, &mut api_headers] {
+        add_origin_headers(headers, &origin_url, url.as_str());
+        network_client::apply_header_overrides(headers, &settings.headers)?;
+    }
+    network_client::apply_header_overrides(&mut page_headers, &settings.page_headers)?;
+    network_client::apply_header_overrides(&mut api_headers, &settings.api_headers)?;
+
+    let mut builder = network_client::Session::builder()
+        .page_headers(page_headers)
+        .api_headers(api_headers)
+        .cookie_store(Arc::clone(cookie_store))
+        .retry(settings.retry_policy())
+        .proxy(settings.proxy.clone())
+        .no_proxy(settings.no_proxy.clone())
+        .timeouts(settings.timeouts())
+        .deadline(settings.deadline.map(Duration::from_secs))
+        .danger_accept_invalid_certs(settings.insecure.unwrap_or(false));
+    if let Some(path) = &settings.cacert {
+        builder = builder.root_certificates(network_client::load_certificates(path)?);
+    }
+    if let Some(cert) = &settings.cert {
+        builder = builder.identity(Some(network_client::load_identity(cert, settings.key.as_deref())?));
+    }
+    for entry in &settings.resolve {
+        let entry: network_client::ResolveOverride = entry.parse()?;
+        info!("Resolving {} to {:?}", entry.host, entry.addrs);
+        builder = builder.resolve(&entry.host, &entry.addrs);
+    }
+    Ok(builder)
+}
+
+/// Accepts `page` from `mirror` if it is usable: the real page, whether behind a clearance we
+/// already hold or not protected at all, or a challenge within the difficulty limit.
+fn check_mirror_page(mirror: &Target, page: &network_client::FetchedPage) -> Result<(), AcquireError> {
+    if html_parser::classify_page(&page.body) == html_parser::PageKind::Content {
+        return Ok(());
+    }
+    let challenge = html_parser::extract_challenge(&page.body)?;
+    let max_difficulty = mirror.settings.max_difficulty.unwrap_or(pow_solver::MAX_SOLVABLE_DIFFICULTY);
+    if challenge.difficulty > max_difficulty {
+        return Err(pow_solver::SolveError::DifficultyTooHigh { difficulty: challenge.difficulty, max: max_difficulty }.into());
+    }
+    Ok(())
+}
+
+/// Records a mirror that could not be used. `failed` is kept in the order the mirrors were given.
+fn mirror_failed(failed: &mut Vec<(usize, report::MirrorFailure)>, index: usize, origin: &str, error: &AcquireError) {
+    warn!("Mirror {} failed: {}", origin, error);
+    failed.push((index, report::MirrorFailure { origin: origin.to_string(), error: error.to_string() }));
+    failed.sort_by_key(|(index, _)| *index);
+}
+
+/// Fetches the page from each mirror in turn and returns the first usable one. If none is, the
+/// last mirror's error is returned.
+async fn try_mirrors(mirrors: Vec<Target>, failed_mirrors: &mut Vec<report::MirrorFailure>) -> Result<(Target, network_client::FetchedPage), AcquireError> {
+    let mut failed = Vec::new();
+    let mut last_error = None;
+    for (index, mirror) in mirrors.into_iter().enumerate() {
+        let result = match network_client::fetch_initial_page_html(&mirror.session, mirror.url.as_str()).await {
+            Ok(page) => check_mirror_page(&mirror, &page).map(|()| page),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(page) => {
+                failed_mirrors.extend(failed.into_iter().map(|(_, failure)| failure));
+                return Ok((mirror, page));
+            }
+            Err(e) => {
+                mirror_failed(&mut failed, index, &mirror.origin, &e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.expect("at least one mirror"))
+}
+
+/// Fetches the page from every mirror at once and returns the first usable one, abandoning the
+/// rest. If none is, the error of the last mirror in the given order is returned.
+async fn race_mirrors(mirrors: Vec<Target>, failed_mirrors: &mut Vec<report::MirrorFailure>) -> Result<(Target, network_client::FetchedPage), AcquireError> {
+    let count = mirrors.len();
+    let mut fetches = tokio::task::JoinSet::new();
+    for (index, mirror) in mirrors.into_iter().enumerate() {
+        fetches.spawn(async move {
+            let result = network_client::fetch_initial_page_html(&mirror.session, mirror.url.as_str()).await;
+            (index, mirror, result)
+        });
+    }
+    let mut failed = Vec::new();
+    let mut errors: Vec<Option<AcquireError>> = (0..count).map(|_| None).collect();
+    while let Some(joined) = fetches.join_next().await {
+        let (index, mirror, result) = joined.map_err(|e| AcquireError::Internal(e.to_string()))?;
+        let result = match result {
+            Ok(page) => check_mirror_page(&mirror, &page).map(|()| page),
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(page) => {
+                failed_mirrors.extend(failed.into_iter().map(|(_, failure)| failure));
+                return Ok((mirror, page)); // Dropping `fetches` aborts the others
+            }
+            Err(e) => {
+                mirror_failed(&mut failed, index, &mirror.origin, &e);
+                errors[index] = Some(e);
+            }
+        }
+    }
+    Err(errors.into_iter().flatten().last().expect("at least one mirror"))
+}
+
+/// Adds `Origin` and `Referer` as a browser would send them for `url`.
+fn add_origin_headers(headers: &mut HeaderMap, origin_url: &str, url: &str) {
+    if let Ok(origin_val) = HeaderValue::from_str(origin_url) {
+        headers.insert(ORIGIN, origin_val);
+    }
+    if let Ok(referer_val) = HeaderValue::from_str(url) { // Use the full URL as referer
+        headers.insert(REFERER, referer_val);
+    }
+}
+
+/// What one challenge cycle produced.
+struct RoundOutcome {
+    challenge: report::ChallengeReport,
+    solve: report::SolveReport,
+    check: report::CheckReport,
+    clearance: clearance::Clearance,
+    /// The target page as fetched with the clearance, if the /check policy already did.
+    page: Option<network_client::FetchedPage>,
+}
+
+/// Runs challenge cycles on `html_content` until one is accepted. When /answer rejects a solution
+/// (typically because the salt expired), a fresh challenge is fetched into `html_content` and solved
+/// instead of resubmitting the old one, up to the retry policy's attempt limit.
+async fn solve_with_salt_refresh(session: &network_client::Session, base_url: &Url, html_content: &mut String, settings: &config::Profile) -> Result<RoundOutcome, AcquireError> {
+    let mut attempt = 1;
+    loop {
+        match run_challenge_round(session, base_url, html_content, settings).await {
+            Err(AcquireError::Network(network_client::NetworkError::AnswerRejected { status, message })) if attempt < session.retry.max_attempts => {
+                warn!("/answer rejected the solution ({}): {}. Fetching a fresh challenge (attempt {}/{})", status, message, attempt, session.retry.max_attempts);
+                *html_content = network_client::fetch_initial_page_html(session, base_url.as_str()).await?.body;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Like `solve`, but stops at the session's deadline and reports that as a deadline timeout. The
+/// solve runs on the blocking thread pool, so a server keeps answering other requests meanwhile.
+async fn solve_before_deadline(session: &network_client::Session, settings: &config::Profile, salt: &str, difficulty: u32, default_timeout: Option<Duration>) -> Result<pow_solver::Solution, AcquireError> {
+    let deadline_timeout = || network_client::NetworkError::Timeout { phase: network_client::TimeoutPhase::Deadline, context: "solving".to_string() };
+    let (settings, limit, deadline_limited) = match session.remaining() {
+        None => (settings.clone(), default_timeout, false),
+        Some(remaining) if remaining.is_zero() => return Err(deadline_timeout().into()),
+        Some(remaining) => {
+            let timeout = settings.solve_timeout.map(Duration::from_secs).or(default_timeout);
+            let capped = match timeout {
+                Some(timeout) if timeout <= remaining => settings.clone(),
+                _ => config::Profile { solve_timeout: None, ..settings.clone() },
+            };
+            let limit = timeout.map_or(remaining, |t| t.min(remaining));
+            (capped, Some(limit), limit == remaining)
+        }
+    };
+    let salt = salt.to_string();
+    let solved = tokio::task::spawn_blocking(move || solve(&settings, &salt, difficulty, limit))
+        .await
+        .map_err(|e| AcquireError::Internal(e.to_string()))?;
+    match solved {
+        Err(pow_solver::SolveError::Timeout { .. }) if deadline_limited => Err(deadline_timeout().into()),
+        result => Ok(result?),
+    }
+}
+
+/// Runs one challenge cycle on `html_content`: extract parameters, solve the PoW,
+/// submit to /answer and, as the /check policy decides, to /check.
+async fn run_challenge_round(session: &network_client::Session, base_url: &Url, html_content: &str, settings: &config::Profile) -> Result<RoundOutcome, AcquireError> {
+    info!("Extracting challenge parameters...");
+    let challenge = html_parser::extract_challenge(html_content)?;
+    info!("Salt: {}, Difficulty: {}", challenge.salt, challenge.difficulty);
+    let endpoints = resolve_endpoints(session, base_url, html_content, &settings.endpoint_overrides()).await;
+    info!("Using endpoints: answer={} check={} (fields: {}/{}/{})", endpoints.answer_path, endpoints.check_path, endpoints.salt_field, endpoints.attempt_field, endpoints.token_field);
+    let challenge_report = report::ChallengeReport::new(&challenge, &endpoints);
+
+    // Solve PoW. A salt older than the challenge's timeout will be rejected anyway, so stop solving there by default
+    let challenge_timeout = (challenge.timeout > 0).then(|| Duration::from_millis(challenge.timeout));
+    let solution = solve_before_deadline(session, settings, &challenge.salt, challenge.difficulty, challenge_timeout).await?;
+    info!("Solution found!");
+    info!("\tAttempt: {}", solution.attempt);
+    info!("\tHash:    {}", solution.hash);
+    info!("\tRate:    {:.0} H/s ({} hashes)", solution.hash_rate(), solution.hashes);
+    let solve_report = report::SolveReport::new(&solution, settings.threads());
+
+    // Submit solution
+    info!("Submitting solution to /answer...");
+    let answer_clearance = network_client::submit_pow_answer(session, base_url, &endpoints, &challenge.salt, &solution.attempt).await?;
+    info!("Auth token from /answer response: {}", answer_clearance.token);
+
+    let policy = settings.check.unwrap_or_default();
+    let (use_check, reason, page) = match policy {
+        config::CheckPolicy::Never => (false, "the policy is never".to_string(), None),
+        config::CheckPolicy::Always => (true, "the policy is always".to_string(), None),
+        config::CheckPolicy::Auto => match (&answer_clearance.cookie, answer_clearance.remaining()) {
+            (None, _) => (true, "/answer set no sssg_clearance cookie".to_string(), None),
+            (Some(_), Some(remaining)) if remaining.is_zero() => (true, "the /answer cookie is already expired".to_string(), None),
+            (Some(_), _) => {
+                info!("Test-fetching {} with the /answer cookie...", base_url);
+                // The cookie jar in `client` is automatically updated by reqwest
+                let page = network_client::fetch_page_html_with_cookies(session, base_url.as_str()).await?;
+                match html_parser::classify_page(&page.body) {
+                    html_parser::PageKind::Content => (false, "a test fetch with the /answer cookie got the page".to_string(), Some(page)),
+                    html_parser::PageKind::Challenge => (true, "a test fetch with the /answer cookie was challenged".to_string(), None),
+                }
+            }
+        },
+    };
+    info!("/check policy {}: {} {}", policy, if use_check { "calling /check, since" } else { "using the /answer cookie, since" }, reason);
+    let check = report::CheckReport { policy: policy.to_string(), called: use_check, reason };
+
+    let clearance = if use_check {
+        info!("Submitting token from /answer to /check endpoint...");
+        let check_clearance = network_client::submit_final_check(session, base_url, &endpoints, &answer_clearance.token).await?;
+        info!("Successfully obtained sssg_clearance token (from /check): {}", check_clearance.value());
+        check_clearance
+    } else {
+        answer_clearance
+    };
+    Ok(RoundOutcome { challenge: challenge_report, solve: solve_report, check, clearance, page })
+}
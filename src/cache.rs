@@ -0,0 +1,164 @@
+use cookie_store::{Cookie, CookieStore};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+use log::{debug, warn};
+use crate::clearance::CLEARANCE_COOKIE;
+
+/// What is stored on disk for one origin: the whole cookie jar and when the clearance was obtained.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CacheEntry {
+    pub origin: String,
+    /// Seconds since the Unix epoch at which the clearance was obtained; `None` if unknown, as
+    /// for cookies imported from a file.
+    #[serde(default)]
+    pub obtained_at: Option<u64>,
+    pub cookies: Vec<Cookie<'static>>,
+}
+
+impl CacheEntry {
+    pub fn new(origin: &str, store: &CookieStore) -> CacheEntry {
+        let obtained_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        CacheEntry {
+            origin: origin.to_string(),
+            obtained_at: Some(obtained_at),
+            cookies: store.iter_unexpired().cloned().collect(),
+        }
+    }
+
+    /// An entry for cookies obtained elsewhere, e.g. imported from a browser: their expiry is
+    /// known, but not when the clearance was obtained.
+    pub fn imported(origin: &str, store: &CookieStore) -> CacheEntry {
+        CacheEntry { obtained_at: None, ..CacheEntry::new(origin, store) }
+    }
+
+    /// How long ago the clearance was obtained, if known.
+    pub fn age(&self) -> Option<Duration> {
+        let obtained_at = UNIX_EPOCH + Duration::from_secs(self.obtained_at?);
+        Some(SystemTime::now().duration_since(obtained_at).unwrap_or(Duration::ZERO))
+    }
+}
+
+/// Returns the unexpired `sssg_clearance` cookie the store would send to `url`, if any.
+pub fn clearance_cookie<'a>(store: &'a CookieStore, url: &Url) -> Option<&'a Cookie<'static>> {
+    store.matches(url).into_iter().find(|cookie| cookie.name() == CLEARANCE_COOKIE)
+}
+
+/// The directory cache files live in: `dir_override` if given, else the platform cache directory.
+pub fn cache_dir(dir_override: Option<&Path>) -> PathBuf {
+    match dir_override {
+        Some(dir) => dir.to_path_buf(),
+        None => dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(env!("CARGO_PKG_NAME")),
+    }
+}
+
+/// The cache file for an origin, e.g. `https_kiwifarms.st.json`.
+pub fn cache_file(dir: &Path, origin: &str) -> PathBuf {
+    let name: String = origin
+        .replace("://", "_")
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' || c == '-' { c } else { '_' })
+        .collect();
+    dir.join(format!("{}.json", name))
+}
+
+/// Loads the cache entry for `origin`. A missing file is not an error.
+pub fn load(dir: &Path, origin: &str) -> io::Result<Option<CacheEntry>> {
+    let path = cache_file(dir, origin);
+    let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let entry: CacheEntry = serde_json::from_reader(BufReader::new(file))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+    debug!("[CACHE] Loaded {} cookie(s) for {} from {}", entry.cookies.len(), origin, path.display());
+    Ok(Some(entry))
+}
+
+/// Writes the cache entry for its origin. The file is written to a temporary file in the same
+/// directory and renamed into place, so concurrent readers never see a partial file and
+/// concurrent writers simply replace each other's complete entries.
+pub fn save(dir: &Path, entry: &CacheEntry) -> io::Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let path = cache_file(dir, &entry.origin);
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(&mut tmp, entry)?;
+    tmp.write_all(b"\n")?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(&path).map_err(|e| e.error)?;
+    debug!("[CACHE] Wrote {} cookie(s) for {} to {}", entry.cookies.len(), entry.origin, path.display());
+    Ok(path)
+}
+
+/// Loads every cache entry in `dir`. Files that cannot be read are logged and skipped.
+pub fn list(dir: &Path) -> io::Result<Vec<CacheEntry>> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for dir_entry in read_dir {
+        let path = dir_entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let parsed = fs::File::open(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|file| serde_json::from_reader::<_, CacheEntry>(BufReader::new(file)).map_err(|e| e.to_string()));
+        match parsed {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("[CACHE] Skipping {}: {}", path.display(), e),
+        }
+    }
+    entries.sort_by(|a, b| a.origin.cmp(&b.origin));
+    Ok(entries)
+}
+
+/// Deletes the cache entry for `origin`. Returns whether there was one.
+pub fn remove(dir: &Path, origin: &str) -> io::Result<bool> {
+    match fs::remove_file(cache_file(dir, origin)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Deletes every cache file in `dir`, including ones that cannot be read any more. Returns how
+/// many were removed.
+pub fn purge(dir: &Path) -> io::Result<usize> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for dir_entry in read_dir {
+        let path = dir_entry?.path();
+        if !is_cache_file(&path) {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
+
+/// Whether `path` is named like a file from `cache_file`, so that purging a directory shared
+/// with other files leaves those alone.
+fn is_cache_file(path: &Path) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    name.ends_with(".json") && (name.starts_with("http_") || name.starts_with("https_"))
+}
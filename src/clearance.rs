@@ -0,0 +1,153 @@
+use reqwest::header::{HeaderMap, DATE, SET_COOKIE};
+use std::time::{Duration, SystemTime};
+use log::{debug, warn};
+
+/// Name of the cookie SSSG sets once a challenge has been solved.
+pub const CLEARANCE_COOKIE: &str = "sssg_clearance";
+
+/// Which endpoint handed out a clearance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearanceSource {
+    Answer,
+    Check,
+}
+
+impl std::fmt::Display for ClearanceSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClearanceSource::Answer => write!(f, "/answer"),
+            ClearanceSource::Check => write!(f, "/check"),
+        }
+    }
+}
+
+/// Whether a stored clearance still gets the real page.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClearanceStatus {
+    /// The page came back without a challenge.
+    Valid,
+    /// The page came back as a challenge: the clearance expired, was revoked or was never sent.
+    Challenged,
+    /// The page was refused outright (403/451 without a challenge).
+    Blocked,
+}
+
+impl std::fmt::Display for ClearanceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ClearanceStatus::Valid => write!(f, "valid"),
+            ClearanceStatus::Challenged => write!(f, "expired/challenged"),
+            ClearanceStatus::Blocked => write!(f, "blocked"),
+        }
+    }
+}
+
+/// The attributes of a `Set-Cookie` header, as sent by the server.
+#[derive(Debug, Clone)]
+pub struct CookieAttributes {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    /// The `Expires` attribute, in the server's clock.
+    pub expires: Option<SystemTime>,
+    pub max_age: Option<Duration>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<String>,
+}
+
+impl CookieAttributes {
+    /// Parses a single `Set-Cookie` header value.
+    pub fn parse(set_cookie: &str) -> Option<CookieAttributes> {
+        let cookie = match cookie::Cookie::parse(set_cookie.to_string()) {
+            Ok(cookie) => cookie,
+            Err(e) => {
+                warn!("Ignoring unparsable Set-Cookie header {:?}: {}", set_cookie, e);
+                return None;
+            }
+        };
+        Some(CookieAttributes {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain: cookie.domain().map(str::to_string),
+            path: cookie.path().map(str::to_string),
+            expires: cookie.expires_datetime().map(SystemTime::from),
+            // A negative Max-Age means "expire now"
+            max_age: cookie.max_age().map(|age| Duration::from_secs(age.whole_seconds().max(0) as u64)),
+            secure: cookie.secure().unwrap_or(false),
+            http_only: cookie.http_only().unwrap_or(false),
+            same_site: cookie.same_site().map(|s| s.to_string()),
+        })
+    }
+
+    /// How long the cookie lives from the moment the server sent it.
+    /// `Max-Age` wins over `Expires`, which is measured against the server's `Date` when available.
+    /// `None` for session cookies.
+    pub fn lifetime(&self, server_date: Option<SystemTime>, received_at: SystemTime) -> Option<Duration> {
+        if let Some(max_age) = self.max_age {
+            return Some(max_age);
+        }
+        let expires = self.expires?;
+        Some(expires.duration_since(server_date.unwrap_or(received_at)).unwrap_or(Duration::ZERO))
+    }
+}
+
+/// A clearance obtained from the SSSG API.
+#[derive(Debug, Clone)]
+pub struct Clearance {
+    /// The `auth` value from the JSON response body.
+    pub token: String,
+    pub source: ClearanceSource,
+    /// The `sssg_clearance` cookie from the response, if the server set one.
+    pub cookie: Option<CookieAttributes>,
+    /// The server's `Date` header.
+    pub server_date: Option<SystemTime>,
+    /// When the response was received, in local time.
+    pub received_at: SystemTime,
+    /// When the cookie expires in local time, correcting for clock skew via the server's `Date`.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl Clearance {
+    /// Builds a clearance from an API response's headers and the `auth` value of its body.
+    pub fn from_response(headers: &HeaderMap, token: String, source: ClearanceSource, received_at: SystemTime) -> Clearance {
+        let server_date = headers.get(DATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v).ok());
+        let cookie = headers.get_all(SET_COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .filter_map(CookieAttributes::parse)
+            .find(|c| c.name == CLEARANCE_COOKIE);
+        let expires_at = cookie.as_ref()
+            .and_then(|c| c.lifetime(server_date, received_at))
+            .map(|lifetime| received_at + lifetime);
+        debug!("[CLEARANCE] {} cookie: {:?}, server date: {:?}", source, cookie, server_date);
+        Clearance { token, source, cookie, server_date, received_at, expires_at }
+    }
+
+    /// The value to send as `sssg_clearance`: the cookie if one was set, otherwise the `auth` token.
+    pub fn value(&self) -> &str {
+        self.cookie.as_ref().map(|c| c.value.as_str()).unwrap_or(&self.token)
+    }
+
+    /// Time left until the clearance expires, if the server said.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at.map(|at| at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+    }
+}
+
+/// When to replace a clearance expiring at `expires_at`: `margin` ahead of expiry, or halfway
+/// there if it lives no longer than the margin. Session cookies are re-checked every `interval`.
+pub fn refresh_time(expires_at: Option<SystemTime>, now: SystemTime, margin: Duration, interval: Duration) -> SystemTime {
+    let Some(expires_at) = expires_at else {
+        return now + interval;
+    };
+    let lifetime = expires_at.duration_since(now).unwrap_or(Duration::ZERO);
+    if lifetime > margin {
+        now + (lifetime - margin)
+    } else {
+        now + lifetime / 2
+    }
+}
@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::debug;
+use crate::network_client::{EndpointOverrides, RetryPolicy, Timeouts};
+
+/// Name of the config file inside each configuration directory.
+pub const CONFIG_FILE: &str = "config.toml";
+
+/// Proof-of-work solver implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolverBackend {
+    /// SHA-256 on CPU threads.
+    Cpu,
+}
+
+impl std::str::FromStr for SolverBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(SolverBackend::Cpu),
+            other => Err(format!("unknown solver backend '{}' (expected cpu)", other)),
+        }
+    }
+}
+
+/// When to submit the /answer token to /check.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", try_from = "String")]
+pub enum CheckPolicy {
+    /// Use the /answer cookie as is.
+    Never,
+    /// Always trade the /answer token in at /check.
+    Always,
+    /// Use /check only if /answer set no usable cookie, or a test fetch with it is challenged again.
+    #[default]
+    Auto,
+}
+
+impl std::str::FromStr for CheckPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "never" => Ok(CheckPolicy::Never),
+            "always" => Ok(CheckPolicy::Always),
+            "auto" => Ok(CheckPolicy::Auto),
+            other => Err(format!("unknown /check policy '{}' (expected never, always or auto)", other)),
+        }
+    }
+}
+
+impl std::fmt::Display for CheckPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckPolicy::Never => write!(f, "never"),
+            CheckPolicy::Always => write!(f, "always"),
+            CheckPolicy::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl TryFrom<String> for CheckPolicy {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+/// Settings that can be given in `[defaults]`, in a `[profile.<name>]` table, or on the command line.
+/// Every field is optional; unset fields fall back to the next layer, then to the built-in default.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    /// Origins (`scheme://host[:port]`) this profile applies to. Only meaningful in a profile table.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub origins: Vec<String>,
+    /// Equivalent origins to fall back on (or race, with `race`) when the target's origin fails.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+    /// Try the target's origin and its mirrors at once instead of in order.
+    pub race: Option<bool>,
+    /// Request headers, added to or replacing the built-in browser headers. An empty value removes the header.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Like `headers`, for page and script GETs only. Applied after `headers`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub page_headers: BTreeMap<String, String>,
+    /// Like `headers`, for the /answer and /check POSTs only. Applied after `headers`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub api_headers: BTreeMap<String, String>,
+    pub answer_path: Option<String>,
+    pub check_path: Option<String>,
+    pub salt_field: Option<String>,
+    pub attempt_field: Option<String>,
+    pub token_field: Option<String>,
+    /// When to submit the /answer token to /check.
+    pub check: Option<CheckPolicy>,
+    /// Proxy URL for every request: `http://`, `https://`, `socks5://` or `socks5h://`.
+    pub proxy: Option<String>,
+    /// Hosts that bypass the proxy, in `NO_PROXY` syntax.
+    pub no_proxy: Option<String>,
+    /// Addresses to connect to instead of resolving a host, as curl-style `HOST:PORT:ADDR[,ADDR...]`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub resolve: Vec<String>,
+    /// PEM CA bundle to trust in addition to the system roots.
+    pub cacert: Option<PathBuf>,
+    /// PEM client certificate for mutual TLS.
+    pub cert: Option<PathBuf>,
+    /// PEM private key for `cert`, if not in the same file.
+    pub key: Option<PathBuf>,
+    /// Skip TLS certificate verification.
+    pub insecure: Option<bool>,
+    /// Connection timeout in seconds (0 disables it).
+    pub connect_timeout: Option<u64>,
+    /// Longest wait for more response data, in seconds (0 disables it).
+    pub read_timeout: Option<u64>,
+    /// Timeout for each HTTP step including its retries, in seconds (0 disables it).
+    pub timeout: Option<u64>,
+    /// End-to-end deadline for obtaining clearance, solving included, in seconds.
+    pub deadline: Option<u64>,
+    pub max_attempts: Option<u32>,
+    pub retry_base_ms: Option<u64>,
+    pub retry_max_ms: Option<u64>,
+    pub solver: Option<SolverBackend>,
+    pub threads: Option<usize>,
+    pub max_difficulty: Option<u32>,
+    /// Solve timeout in seconds.
+    pub solve_timeout: Option<u64>,
+    pub cache_dir: Option<PathBuf>,
+    /// A Netscape or JSON cookie file to seed the cookie jar from.
+    pub cookies: Option<PathBuf>,
+}
+
+impl Profile {
+    /// Overlays `other` on `self`: every field set in `other` wins. Headers are merged by name
+    /// (case-insensitively), and `resolve` entries by host; a non-empty `mirrors` list replaces the
+    /// previous one; `origins` are left alone.
+    pub fn merge(&mut self, other: &Profile) {
+        for entry in &other.resolve {
+            let host = entry.split(':').next().unwrap_or_default();
+            self.resolve.retain(|existing| !existing.split(':').next().unwrap_or_default().eq_ignore_ascii_case(host));
+            self.resolve.push(entry.clone());
+        }
+        if !other.mirrors.is_empty() {
+            self.mirrors = other.mirrors.clone();
+        }
+        merge_headers(&mut self.headers, &other.headers);
+        merge_headers(&mut self.page_headers, &other.page_headers);
+        merge_headers(&mut self.api_headers, &other.api_headers);
+        macro_rules! overlay {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field.clone();
+                })*
+            };
+        }
+        overlay!(race, answer_path, check_path, salt_field, attempt_field, token_field, check, proxy, no_proxy, cacert, cert, key, insecure,
+            connect_timeout, read_timeout, timeout, deadline, max_attempts, retry_base_ms, retry_max_ms, solver, threads,
+            max_difficulty, solve_timeout, cache_dir, cookies);
+    }
+
+    pub fn endpoint_overrides(&self) -> EndpointOverrides {
+        EndpointOverrides {
+            answer_path: self.answer_path.clone(),
+            check_path: self.check_path.clone(),
+            salt_field: self.salt_field.clone(),
+            attempt_field: self.attempt_field.clone(),
+            token_field: self.token_field.clone(),
+        }
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        let default = RetryPolicy::default();
+        RetryPolicy {
+            max_attempts: self.max_attempts.unwrap_or(default.max_attempts),
+            base_delay: self.retry_base_ms.map(Duration::from_millis).unwrap_or(default.base_delay),
+            max_delay: self.retry_max_ms.map(Duration::from_millis).unwrap_or(default.max_delay),
+        }
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        let default = Timeouts::default();
+        let secs = |value: Option<u64>, default: Option<Duration>| match value {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => default,
+        };
+        Timeouts {
+            connect: secs(self.connect_timeout, default.connect),
+            read: secs(self.read_timeout, default.read),
+            operation: secs(self.timeout, default.operation),
+        }
+    }
+
+    /// Solver threads; defaults to the number of CPUs.
+    pub fn threads(&self) -> usize {
+        self.threads.unwrap_or_else(num_cpus::get).max(1)
+    }
+}
+
+/// Header names are case-insensitive, so a later layer's `user-agent` replaces an earlier `User-Agent`.
+fn merge_headers(headers: &mut BTreeMap<String, String>, other: &BTreeMap<String, String>) {
+    for (name, value) in other {
+        headers.retain(|existing, _| !existing.eq_ignore_ascii_case(name));
+        headers.insert(name.clone(), value.clone());
+    }
+}
+
+/// The contents of a config file.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Settings for every origin.
+    pub defaults: Profile,
+    /// Named profiles, each applying to the origins it lists.
+    #[serde(rename = "profile")]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io { path: PathBuf, error: std::io::Error },
+    Parse { path: PathBuf, error: toml::de::Error },
+    UnknownProfile(String),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => write!(f, "Cannot read config file {}: {}", path.display(), error),
+            ConfigError::Parse { path, error } => write!(f, "Invalid config file {}: {}", path.display(), error),
+            ConfigError::UnknownProfile(name) => write!(f, "No profile named '{}' in the config file", name),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Where a config file is looked for, most specific first: `$XDG_CONFIG_HOME` (or the platform
+/// equivalent), then each directory of `$XDG_CONFIG_DIRS` (default `/etc/xdg`).
+pub fn config_paths() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Some(dir) = dirs::config_dir() {
+        dirs.push(dir);
+    }
+    match std::env::var_os("XDG_CONFIG_DIRS").filter(|v| !v.is_empty()) {
+        Some(value) => dirs.extend(std::env::split_paths(&value)),
+        None if cfg!(unix) => dirs.push(PathBuf::from("/etc/xdg")),
+        None => {}
+    }
+    dirs.into_iter().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join(CONFIG_FILE)).collect()
+}
+
+impl Config {
+    /// Loads `path` if given (it must exist), else the first config file found in `config_paths()`.
+    /// Returns the config and the file it came from; no file at all gives an empty config.
+    pub fn load(path: Option<&Path>) -> Result<(Config, Option<PathBuf>), ConfigError> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match config_paths().into_iter().find(|p| p.is_file()) {
+                Some(path) => path,
+                None => {
+                    debug!("[CONFIG] No config file found");
+                    return Ok((Config::default(), None));
+                }
+            },
+        };
+        let text = std::fs::read_to_string(&path).map_err(|error| ConfigError::Io { path: path.clone(), error })?;
+        let config = toml::from_str(&text).map_err(|error| ConfigError::Parse { path: path.clone(), error })?;
+        debug!("[CONFIG] Loaded {}", path.display());
+        Ok((config, Some(path)))
+    }
+
+    /// Picks the profile for `origin`: the one named `name` if given, else the first (by name)
+    /// listing `origin`.
+    pub fn profile_for(&self, origin: Option<&str>, name: Option<&str>) -> Result<Option<(&str, &Profile)>, ConfigError> {
+        if let Some(name) = name {
+            return match self.profiles.get_key_value(name) {
+                Some((name, profile)) => Ok(Some((name.as_str(), profile))),
+                None => Err(ConfigError::UnknownProfile(name.to_string())),
+            };
+        }
+        let Some(origin) = origin else {
+            return Ok(None);
+        };
+        Ok(self.profiles.iter()
+            .find(|(_, profile)| profile.origins.iter().any(|o| o.trim_end_matches('/').eq_ignore_ascii_case(origin)))
+            .map(|(name, profile)| (name.as_str(), profile)))
+    }
+
+    /// The settings in effect for `origin`: `[defaults]`, then the matching profile, then `cli`.
+    /// Returns them with the name of the profile used, if any.
+    pub fn effective(&self, origin: Option<&str>, name: Option<&str>, cli: &Profile) -> Result<(Profile, Option<String>), ConfigError> {
+        let mut effective = self.defaults.clone();
+        let profile = self.profile_for(origin, name)?;
+        if let Some((_, profile)) = profile {
+            effective.merge(profile);
+        }
+        effective.merge(cli);
+        effective.origins.clear();
+        Ok((effective, profile.map(|(name, _)| name.to_string())))
+    }
+}
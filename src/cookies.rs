@@ -0,0 +1,238 @@
+use cookie_store::{Cookie, CookieDomain, CookieExpiration, CookieStore};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// File formats the cookie jar can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieFormat {
+    /// Netscape `cookies.txt`, as read by yt-dlp, gallery-dl and `wget --load-cookies`.
+    Netscape,
+    /// `Set-Cookie:` header lines, as read by `curl -b <file>`.
+    Curl,
+    /// A single `Cookie:` request header line for the target URL.
+    Header,
+    /// A JSON array with every cookie attribute.
+    Json,
+}
+
+impl std::str::FromStr for CookieFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "netscape" | "cookies.txt" | "wget" => Ok(CookieFormat::Netscape),
+            "curl" => Ok(CookieFormat::Curl),
+            "header" => Ok(CookieFormat::Header),
+            "json" => Ok(CookieFormat::Json),
+            other => Err(format!("unknown cookie format '{}' (expected netscape, wget, curl, header or json)", other)),
+        }
+    }
+}
+
+/// One cookie with its full attributes, as written by the JSON export.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedCookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    /// True if the cookie had no `Domain` attribute and is only sent to `domain` itself.
+    pub host_only: bool,
+    pub path: String,
+    pub secure: bool,
+    pub http_only: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub same_site: Option<String>,
+    /// Expiry as seconds since the Unix epoch; `None` for session cookies.
+    pub expires: Option<u64>,
+}
+
+impl ExportedCookie {
+    pub fn from_cookie(cookie: &Cookie<'_>) -> ExportedCookie {
+        let (domain, host_only) = match &cookie.domain {
+            CookieDomain::HostOnly(domain) => (domain.clone(), true),
+            CookieDomain::Suffix(domain) => (domain.clone(), false),
+            CookieDomain::NotPresent | CookieDomain::Empty => (String::new(), true),
+        };
+        let expires = match &cookie.expires {
+            CookieExpiration::AtUtc(at) => SystemTime::from(*at)
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .ok(),
+            CookieExpiration::SessionEnd => None,
+        };
+        ExportedCookie {
+            name: cookie.name().to_string(),
+            value: cookie.value().to_string(),
+            domain,
+            host_only,
+            path: String::from(&cookie.path),
+            secure: cookie.secure().unwrap_or(false),
+            http_only: cookie.http_only().unwrap_or(false),
+            same_site: cookie.same_site().map(|s| s.to_string()),
+            expires,
+        }
+    }
+}
+
+impl ExportedCookie {
+    /// Converts back into a jar cookie, keeping the domain (host-only or not) and path.
+    pub fn to_cookie(&self) -> io::Result<Cookie<'static>> {
+        let domain = self.domain.trim_start_matches('.');
+        let mut builder = cookie::Cookie::build((self.name.clone(), self.value.clone()))
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(self.http_only);
+        if !self.host_only {
+            builder = builder.domain(domain.to_string());
+        }
+        if let Some(expires) = self.expires {
+            let at = cookie::time::OffsetDateTime::from_unix_timestamp(expires as i64)
+                .map_err(|e| invalid_data(format!("cookie '{}': bad expiry {}: {}", self.name, expires, e)))?;
+            builder = builder.expires(at);
+        }
+        // The jar validates a cookie against the URL that set it, so synthesize one on its own domain.
+        let request_url = Url::parse(&format!("https://{}{}", domain, self.path))
+            .map_err(|e| invalid_data(format!("cookie '{}': bad domain/path {}{}: {}", self.name, domain, self.path, e)))?;
+        Cookie::try_from_raw_cookie(&builder.build(), &request_url)
+            .map(Cookie::into_owned)
+            .map_err(|e| invalid_data(format!("cookie '{}' rejected for {}: {}", self.name, request_url, e)))
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Builds a cookie jar from `cookies`, dropping expired ones. Later cookies replace earlier ones
+/// with the same domain, path and name.
+pub fn store_from(cookies: Vec<Cookie<'static>>) -> CookieStore {
+    CookieStore::from_cookies(cookies.into_iter().map(Ok::<_, io::Error>), false).unwrap_or_default()
+}
+
+/// Reads a Netscape `cookies.txt` or a JSON cookie file (as written by `CookieFormat::Json`).
+/// The format is detected from the content.
+pub fn import_file(path: &Path) -> io::Result<Vec<Cookie<'static>>> {
+    let text = std::fs::read_to_string(path)?;
+    let exported = if text.trim_start().starts_with('[') {
+        serde_json::from_str::<Vec<ExportedCookie>>(&text)
+            .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?
+    } else {
+        parse_netscape(&text).map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?
+    };
+    exported.iter().map(ExportedCookie::to_cookie).collect()
+}
+
+fn parse_netscape(text: &str) -> Result<Vec<ExportedCookie>, String> {
+    let mut cookies = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [domain, include_subdomains, path, secure, expires, name, value] = fields[..] else {
+            return Err(format!("line {}: expected 7 tab-separated fields, found {}", index + 1, fields.len()));
+        };
+        let expires: u64 = expires.parse()
+            .map_err(|_| format!("line {}: bad expiry '{}'", index + 1, expires))?;
+        cookies.push(ExportedCookie {
+            name: name.to_string(),
+            value: value.to_string(),
+            domain: domain.trim_start_matches('.').to_string(),
+            host_only: !include_subdomains.eq_ignore_ascii_case("TRUE"),
+            path: path.to_string(),
+            secure: secure.eq_ignore_ascii_case("TRUE"),
+            http_only,
+            same_site: None,
+            // 0 marks a session cookie
+            expires: (expires != 0).then_some(expires),
+        });
+    }
+    Ok(cookies)
+}
+
+/// Serializes every unexpired cookie in the store. `url` selects the cookies for `CookieFormat::Header`.
+pub fn export(store: &CookieStore, format: CookieFormat, url: &Url) -> String {
+    let cookies: Vec<ExportedCookie> = store.iter_unexpired().map(ExportedCookie::from_cookie).collect();
+    match format {
+        CookieFormat::Netscape => to_netscape(&cookies),
+        CookieFormat::Curl => to_curl(&cookies),
+        CookieFormat::Header => {
+            let pairs: Vec<String> = store.matches(url)
+                .into_iter()
+                .map(|c| format!("{}={}", c.name(), c.value()))
+                .collect();
+            format!("Cookie: {}\n", pairs.join("; "))
+        }
+        CookieFormat::Json => {
+            let mut json = serde_json::to_string_pretty(&cookies).unwrap_or_else(|_| "[]".to_string());
+            json.push('\n');
+            json
+        }
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file in the same directory, so readers such as
+/// a crawler polling a cookies.txt never see a partial or missing file.
+pub fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    tmp.write_all(contents.as_bytes())?;
+    tmp.as_file().sync_all()?;
+    tmp.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+fn to_netscape(cookies: &[ExportedCookie]) -> String {
+    let mut out = String::from("# Netscape HTTP Cookie File\n# This file was generated by kiwifarms-captchabuster.\n\n");
+    for c in cookies {
+        // Domain cookies are written with a leading dot and the include-subdomains flag set.
+        let domain = if c.host_only { c.domain.clone() } else { format!(".{}", c.domain.trim_start_matches('.')) };
+        let prefix = if c.http_only { "#HttpOnly_" } else { "" };
+        out.push_str(&format!(
+            "{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+            prefix,
+            domain,
+            if c.host_only { "FALSE" } else { "TRUE" },
+            c.path,
+            if c.secure { "TRUE" } else { "FALSE" },
+            c.expires.unwrap_or(0),
+            c.name,
+            c.value,
+        ));
+    }
+    out
+}
+
+fn to_curl(cookies: &[ExportedCookie]) -> String {
+    let mut out = String::new();
+    for c in cookies {
+        out.push_str(&format!("Set-Cookie: {}={}", c.name, c.value));
+        // Without `Domain`, curl keeps a host-only cookie to its own host rather than every subdomain
+        if !c.host_only {
+            out.push_str(&format!("; Domain={}", c.domain));
+        }
+        out.push_str(&format!("; Path={}", c.path));
+        if let Some(expires) = c.expires {
+            out.push_str(&format!("; Expires={}", httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(expires))));
+        }
+        if c.secure {
+            out.push_str("; Secure");
+        }
+        if c.http_only {
+            out.push_str("; HttpOnly");
+        }
+        out.push('\n');
+    }
+    out
+}
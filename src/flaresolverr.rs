@@ -0,0 +1,311 @@
+//! FlareSolverr's `/v1` protocol on top of `serve`, so Jackett, Prowlarr and the scrapers that
+//! already speak it can use this solver for SSSG-protected sites. Requests are answered with the
+//! page fetched through a clearance from the `ClearanceService`; sessions are cookie jars kept
+//! between requests rather than browser instances.
+
+use crate::acquire;
+use crate::cookies::ExportedCookie;
+use crate::html_parser;
+use crate::network_client;
+use crate::server::ClearanceService;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use cookie_store::Cookie;
+use log::{info, warn};
+use reqwest::header::USER_AGENT;
+use reqwest_cookie_store::CookieStoreMutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// The FlareSolverr release whose API this mirrors. Clients check it, so it is reported instead of
+/// this crate's version.
+pub const FLARESOLVERR_VERSION: &str = "3.3.21";
+
+/// The `maxTimeout` FlareSolverr applies when a request has none, in milliseconds.
+const DEFAULT_MAX_TIMEOUT_MS: u64 = 60_000;
+
+/// A `POST /v1` body. Fields this implementation has no use for (`headers`, `download`, ...) are ignored.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct V1Request {
+    pub cmd: String,
+    pub url: Option<String>,
+    pub session: Option<String>,
+    /// Milliseconds the whole command may take.
+    pub max_timeout: Option<u64>,
+    pub cookies: Vec<RequestCookie>,
+    pub return_only_cookies: bool,
+    /// The urlencoded body for `request.post`.
+    pub post_data: Option<String>,
+    pub proxy: Option<RequestProxy>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestCookie {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RequestProxy {
+    pub url: Option<String>,
+}
+
+/// A `POST /v1` response, for every command.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct V1Response {
+    /// `ok` or `error`.
+    pub status: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub solution: Option<Solution>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sessions: Option<Vec<String>>,
+    /// Milliseconds since the Unix epoch.
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    pub version: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Solution {
+    pub url: String,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    /// The page body; empty with `returnOnlyCookies`.
+    pub response: String,
+    pub cookies: Vec<SolutionCookie>,
+    pub user_agent: String,
+}
+
+/// A cookie in the shape FlareSolverr clients parse.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SolutionCookie {
+    pub name: String,
+    pub value: String,
+    pub domain: String,
+    pub path: String,
+    /// Seconds since the Unix epoch; -1 for a session cookie.
+    pub expires: f64,
+    /// Same as `expires`, under the name Selenium uses; absent for a session cookie.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiry: Option<u64>,
+    pub size: usize,
+    pub http_only: bool,
+    pub secure: bool,
+    pub session: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub same_site: Option<String>,
+}
+
+impl From<&ExportedCookie> for SolutionCookie {
+    fn from(cookie: &ExportedCookie) -> SolutionCookie {
+        SolutionCookie {
+            name: cookie.name.clone(),
+            value: cookie.value.clone(),
+            // Domain cookies get the leading dot browsers report them with
+            domain: if cookie.host_only { cookie.domain.clone() } else { format!(".{}", cookie.domain) },
+            path: cookie.path.clone(),
+            expires: cookie.expires.map_or(-1.0, |at| at as f64),
+            expiry: cookie.expires,
+            size: cookie.name.len() + cookie.value.len(),
+            http_only: cookie.http_only,
+            secure: cookie.secure,
+            session: cookie.expires.is_none(),
+            same_site: cookie.same_site.clone(),
+        }
+    }
+}
+
+/// What `GET /` answers, which clients use to detect FlareSolverr.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IndexResponse {
+    pub msg: String,
+    pub version: String,
+    pub user_agent: String,
+}
+
+struct FlareState {
+    service: Arc<ClearanceService>,
+    /// Session id to the cookie jar its requests share.
+    sessions: Mutex<HashMap<String, Arc<CookieStoreMutex>>>,
+}
+
+/// `GET /` and `POST /v1`.
+pub fn router(service: Arc<ClearanceService>) -> Router {
+    let state = Arc::new(FlareState { service, sessions: Mutex::new(HashMap::new()) });
+    Router::new()
+        .route("/", get(index))
+        .route("/v1", post(v1))
+        .with_state(state)
+}
+
+async fn index() -> Json<IndexResponse> {
+    let user_agent = network_client::default_page_headers()
+        .get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    Json(IndexResponse { msg: "FlareSolverr is ready!".to_string(), version: FLARESOLVERR_VERSION.to_string(), user_agent })
+}
+
+async fn v1(State(state): State<Arc<FlareState>>, request: Result<Json<V1Request>, JsonRejection>) -> Response {
+    let start_timestamp = unix_millis();
+    let result = match request {
+        Ok(Json(request)) => {
+            let max_timeout = Duration::from_millis(request.max_timeout.unwrap_or(DEFAULT_MAX_TIMEOUT_MS));
+            match tokio::time::timeout(max_timeout, run_command(&state, &request)).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("Maximum timeout reached. maxTimeout={} (ms)", max_timeout.as_millis())),
+            }
+        }
+        Err(e) => Err(e.body_text()),
+    };
+    let (status, mut response) = match result {
+        Ok(response) => (StatusCode::OK, response),
+        Err(message) => {
+            warn!("FlareSolverr request failed: {}", message);
+            (StatusCode::INTERNAL_SERVER_ERROR, V1Response { status: "error".to_string(), message: format!("Error: {}", message), ..Default::default() })
+        }
+    };
+    response.start_timestamp = start_timestamp;
+    response.end_timestamp = unix_millis();
+    response.version = FLARESOLVERR_VERSION.to_string();
+    (status, Json(response)).into_response()
+}
+
+async fn run_command(state: &FlareState, request: &V1Request) -> Result<V1Response, String> {
+    match request.cmd.as_str() {
+        "request.get" | "request.post" => {
+            let (solution, solved) = solve_request(state, request).await?;
+            let message = if solved { "Challenge solved!" } else { "Challenge not detected!" };
+            Ok(ok(message, V1Response { solution: Some(solution), ..Default::default() }))
+        }
+        "sessions.create" => {
+            let id = request.session.clone().unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+            let mut sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+            if sessions.contains_key(&id) {
+                return Ok(ok("Session already exists.", V1Response { session: Some(id), ..Default::default() }));
+            }
+            sessions.insert(id.clone(), Arc::new(CookieStoreMutex::default()));
+            info!("Created FlareSolverr session {}", id);
+            Ok(ok("Session created successfully.", V1Response { session: Some(id), ..Default::default() }))
+        }
+        "sessions.list" => {
+            let sessions = state.sessions.lock().map_err(|e| e.to_string())?;
+            let mut ids: Vec<String> = sessions.keys().cloned().collect();
+            ids.sort();
+            Ok(ok("", V1Response { sessions: Some(ids), ..Default::default() }))
+        }
+        "sessions.destroy" => {
+            let id = request.session.as_deref().ok_or("Request parameter 'session' is mandatory in 'sessions.destroy' command.")?;
+            if state.sessions.lock().map_err(|e| e.to_string())?.remove(id).is_none() {
+                return Err("The session doesn't exist.".to_string());
+            }
+            info!("Destroyed FlareSolverr session {}", id);
+            Ok(ok("The session has been removed.", V1Response::default()))
+        }
+        "" => Err("Request parameter 'cmd' is mandatory.".to_string()),
+        cmd => Err(format!("Request parameter 'cmd' = '{}' is invalid.", cmd)),
+    }
+}
+
+fn ok(message: &str, response: V1Response) -> V1Response {
+    V1Response { status: "ok".to_string(), message: message.to_string(), ..response }
+}
+
+/// `request.get` and `request.post`: fetch the URL, with a clearance for its origin if it is
+/// challenged. Also returns whether a clearance was needed.
+async fn solve_request(state: &FlareState, request: &V1Request) -> Result<(Solution, bool), String> {
+    let url = request.url.as_deref().ok_or("Request parameter 'url' is mandatory in 'request.get' command.")?;
+    let url = Url::parse(url).map_err(|e| format!("Invalid url {:?}: {}", url, e))?;
+    let form = match request.cmd.as_str() {
+        "request.post" => Some(request.post_data.as_deref().ok_or("Request parameter 'postData' is mandatory in 'request.post' command.")?),
+        _ => None,
+    };
+    if request.proxy.as_ref().is_some_and(|proxy| proxy.url.is_some()) {
+        // Clearances are cached per origin regardless of the route they were obtained over, so a
+        // per-request proxy could hand out a clearance tied to another address
+        return Err("Per-request proxies are not supported; start serve with --proxy instead.".to_string());
+    }
+    let origin = url.origin().unicode_serialization();
+    let settings = state.service.settings(&origin).map_err(|e| e.to_string())?;
+
+    // The session's jar, or a fresh one, with the caller's cookies on top
+    let cookie_store = match &request.session {
+        Some(id) => state.sessions.lock().map_err(|e| e.to_string())?
+            .get(id)
+            .cloned()
+            .ok_or_else(|| format!("The session {} doesn't exist.", id))?,
+        None => Arc::new(CookieStoreMutex::default()),
+    };
+    let caller_cookies = request.cookies.iter()
+        .filter_map(|c| Cookie::parse(format!("{}={}; Path=/", c.name, c.value), &url).ok())
+        .map(Cookie::into_owned)
+        .collect();
+    add_cookies(&cookie_store, &url, caller_cookies);
+    let session = acquire::build_session(&settings, &url, &cookie_store).map_err(|e| e.to_string())?;
+
+    // Get a clearance only when challenged, and solve for a new one if even that is challenged
+    let mut page = network_client::request_page(&session, &url, form).await.map_err(|e| e.to_string())?;
+    let mut solved = false;
+    for force in [false, true] {
+        if html_parser::classify_page(&page.body) == html_parser::PageKind::Content {
+            break;
+        }
+        if solved {
+            warn!("{} still presented a challenge with the clearance, solving again", url);
+        }
+        let clearance = state.service.clearance(&url, force).await.map_err(|e| e.to_string())?;
+        let clearance_cookies = clearance.cookies.iter().filter_map(|c| c.to_cookie().ok()).collect();
+        add_cookies(&cookie_store, &url, clearance_cookies);
+        page = network_client::request_page(&session, &url, form).await.map_err(|e| e.to_string())?;
+        solved = true;
+    }
+    if html_parser::classify_page(&page.body) == html_parser::PageKind::Challenge {
+        return Err("The page still presents a challenge after solving.".to_string());
+    }
+
+    let cookies = match cookie_store.lock() {
+        Ok(store) => store.iter_unexpired().map(ExportedCookie::from_cookie).map(|c| SolutionCookie::from(&c)).collect(),
+        Err(e) => return Err(e.to_string()),
+    };
+    let user_agent = session.page_headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let (headers, response) = if request.return_only_cookies {
+        (BTreeMap::new(), String::new())
+    } else {
+        let headers = page.headers.iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        (headers, page.body)
+    };
+    Ok((Solution { url: page.url.to_string(), status: page.status.as_u16(), headers, response, cookies, user_agent }, solved))
+}
+
+/// Stores `cookies` in the jar as if `url` had set them.
+fn add_cookies(cookie_store: &CookieStoreMutex, url: &Url, cookies: Vec<Cookie<'static>>) {
+    if let Ok(mut store) = cookie_store.lock() {
+        for cookie in cookies {
+            if let Err(e) = store.insert(cookie, url) {
+                warn!("Ignoring cookie for {}: {}", url, e);
+            }
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
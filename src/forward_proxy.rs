@@ -0,0 +1,312 @@
+//! `proxy`: a forward HTTP(S) proxy, so browsers, curl and existing scrapers get through SSSG by
+//! pointing at it, unchanged. HTTPS to a configured origin is intercepted: the `CONNECT` tunnel is
+//! terminated with a certificate from the local CA (see `mitm`) and each request in it is relayed
+//! with the origin's clearance (see `relay`). Tunnels to any other host are passed through as they
+//! are, and so are plain-HTTP requests to origins that are not configured; both go out through
+//! `--proxy`, if set, unless `--no-proxy` exempts the host.
+
+use crate::mitm::CertificateAuthority;
+use crate::relay::Relay;
+use crate::server::ClearanceService;
+use axum::body::Body;
+use axum::http::uri::Authority;
+use axum::http::{Method, Request, Response, StatusCode};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper_util::rt::TokioIo;
+use log::{debug, warn};
+use ipnet::IpNet;
+use percent_encoding::percent_decode_str;
+use std::convert::Infallible;
+use std::io;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::TlsAcceptor;
+use url::Url;
+
+/// The proxy: the relay requests go through, the CA to intercept with and the origins to intercept.
+pub struct ForwardProxy {
+    relay: Relay,
+    ca: CertificateAuthority,
+    origins: Vec<String>,
+}
+
+impl ForwardProxy {
+    /// A proxy intercepting `origins` (`scheme://host[:port]`, as in the config file's `origins`).
+    pub fn new(service: Arc<ClearanceService>, ca: CertificateAuthority, origins: &[String]) -> ForwardProxy {
+        let origins = origins.iter()
+            .map(|origin| match Url::parse(origin) {
+                Ok(url) => url.origin().unicode_serialization(),
+                Err(_) => origin.trim_end_matches('/').to_ascii_lowercase(),
+            })
+            .collect();
+        ForwardProxy { relay: Relay::new(service), ca, origins }
+    }
+
+    /// Whether requests to `origin` get clearances, rather than being passed through.
+    pub fn intercepts(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o.eq_ignore_ascii_case(origin))
+    }
+
+    /// Accepts proxy connections on `listener` until the task is dropped.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let proxy = Arc::clone(&self);
+            tokio::spawn(async move {
+                let service = service_fn(move |request| {
+                    let proxy = Arc::clone(&proxy);
+                    async move { Ok::<_, Infallible>(proxy.handle(request).await) }
+                });
+                if let Err(e) = http1::Builder::new().serve_connection(TokioIo::new(stream), service).with_upgrades().await {
+                    debug!("[PROXY] Connection from {} ended: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Answers one request made to the proxy: a `CONNECT`, or a plain-HTTP request in absolute form.
+    async fn handle(self: Arc<Self>, request: Request<Incoming>) -> Response<Body> {
+        if request.method() == Method::CONNECT {
+            return self.connect(request).await;
+        }
+        let url = match Url::parse(&request.uri().to_string()) {
+            Ok(url) if url.scheme() == "http" => url,
+            _ => return text_response(StatusCode::BAD_REQUEST, "This is a proxy: requests must be CONNECTs or carry an absolute http:// URL"),
+        };
+        let intercept = self.intercepts(&url.origin().unicode_serialization());
+        debug!("[PROXY] {} {}{}", request.method(), url, if intercept { " (intercepted)" } else { "" });
+        self.relay.relay(request.map(Body::new), url, intercept).await
+    }
+
+    /// Accepts a `CONNECT` and, once the client has switched to the tunnel, intercepts it or
+    /// connects it through to the host. A tunnel that is passed through is opened first, so the
+    /// client hears about a host it cannot reach.
+    async fn connect(self: Arc<Self>, request: Request<Incoming>) -> Response<Body> {
+        let Some(authority) = request.uri().authority().cloned() else {
+            return text_response(StatusCode::BAD_REQUEST, "CONNECT needs a host:port");
+        };
+        let host = authority.host().trim_start_matches('[').trim_end_matches(']').to_string();
+        let port = authority.port_u16().unwrap_or(443);
+        let origin = if port == 443 { format!("https://{}", authority.host()) } else { format!("https://{}:{}", authority.host(), port) };
+        let intercept = self.intercepts(&origin);
+        debug!("[PROXY] CONNECT {}{}", authority, if intercept { " (intercepted)" } else { "" });
+        let upstream = if intercept {
+            None
+        } else {
+            match self.open_tunnel(&host, port).await {
+                Ok(upstream) => Some(upstream),
+                Err(e) => {
+                    warn!("Cannot open a tunnel to {}: {}", authority, e);
+                    return text_response(StatusCode::BAD_GATEWAY, &format!("Cannot connect to {}: {}", authority, e));
+                }
+            }
+        };
+
+        tokio::spawn(async move {
+            let upgraded = match hyper::upgrade::on(request).await {
+                Ok(upgraded) => TokioIo::new(upgraded),
+                Err(e) => {
+                    warn!("CONNECT to {} was not upgraded: {}", authority, e);
+                    return;
+                }
+            };
+            let result = match upstream {
+                None => self.intercept(upgraded, &host, origin).await,
+                Some(upstream) => tunnel(upgraded, upstream, &authority).await.map_err(Into::into),
+            };
+            if let Err(e) = result {
+                debug!("[PROXY] Tunnel to {} ended: {}", authority, e);
+            }
+        });
+        Response::new(Body::empty())
+    }
+
+    /// Connects to `host:port` for a tunnel that is passed through, the way plain-HTTP requests to
+    /// other origins are relayed: through the proxy in the default settings, unless its `no_proxy`
+    /// exempts the host.
+    async fn open_tunnel(&self, host: &str, port: u16) -> io::Result<TcpStream> {
+        let settings = self.relay.service().settings("").map_err(io::Error::other)?;
+        match settings.proxy.as_deref() {
+            Some(proxy) if !bypasses_proxy(settings.no_proxy.as_deref(), host) => connect_via_proxy(proxy, host, port).await,
+            _ => TcpStream::connect((host, port)).await,
+        }
+    }
+
+    /// Terminates TLS on `stream` as `host` and relays the requests in it to `origin`.
+    async fn intercept(self: Arc<Self>, stream: TokioIo<hyper::upgrade::Upgraded>, host: &str, origin: String) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let config = self.ca.server_config(host)?;
+        let tls = TlsAcceptor::from(config).accept(stream).await?;
+        let service = service_fn(move |request: Request<Incoming>| {
+            let proxy = Arc::clone(&self);
+            // Not joined onto the origin: a target such as `//other.host/` would then leave it
+            let url = Url::parse(&format!("{}{}", origin, request.uri().path_and_query().map_or("/", |pq| pq.as_str())));
+            async move {
+                Ok::<_, Infallible>(match url {
+                    Ok(url) => proxy.relay.relay(request.map(Body::new), url, true).await,
+                    Err(e) => text_response(StatusCode::BAD_REQUEST, &format!("Invalid request target: {}", e)),
+                })
+            }
+        });
+        http1::Builder::new().serve_connection(TokioIo::new(tls), service).await?;
+        Ok(())
+    }
+}
+
+/// Copies bytes both ways between `stream` and `upstream` until either side closes.
+async fn tunnel(mut stream: TokioIo<hyper::upgrade::Upgraded>, mut upstream: TcpStream, authority: &Authority) -> io::Result<()> {
+    let (sent, received) = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await?;
+    debug!("[PROXY] Tunnel to {} closed ({} bytes sent, {} received)", authority, sent, received);
+    Ok(())
+}
+
+/// Whether `no_proxy` (or, without it, `NO_PROXY`) exempts `host` from the proxy, matching entries
+/// as reqwest does for requests: `*`, IP addresses and CIDR ranges, and domains with their subdomains.
+fn bypasses_proxy(no_proxy: Option<&str>, host: &str) -> bool {
+    let list = match no_proxy {
+        Some(list) => list.to_string(),
+        None => std::env::var("NO_PROXY").or_else(|_| std::env::var("no_proxy")).unwrap_or_default(),
+    };
+    let ip = host.parse::<IpAddr>().ok();
+    let host = host.to_ascii_lowercase();
+    list.split(',').map(str::trim).filter(|entry| !entry.is_empty()).any(|entry| {
+        if entry == "*" {
+            return true;
+        }
+        if let Some(ip) = ip {
+            return entry.parse::<IpNet>().is_ok_and(|net| net.contains(&ip)) || entry.parse::<IpAddr>().is_ok_and(|entry| entry == ip);
+        }
+        let domain = entry.trim_start_matches('.').to_ascii_lowercase();
+        host == domain || host.ends_with(&format!(".{}", domain))
+    })
+}
+
+/// Opens a tunnel to `host:port` through `proxy`: an HTTP `CONNECT` for `http://` proxies, or a
+/// SOCKS5 `CONNECT` that resolves `host` here for `socks5://` and on the proxy for `socks5h://`.
+async fn connect_via_proxy(proxy: &str, host: &str, port: u16) -> io::Result<TcpStream> {
+    let url = Url::parse(proxy).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("proxy {}: {}", proxy, e)))?;
+    let proxy_host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    let proxy_port = url.port_or_known_default().unwrap_or(1080);
+    let credentials = (!url.username().is_empty()).then(|| {
+        let decode = |part: &str| percent_decode_str(part).decode_utf8_lossy().into_owned();
+        (decode(url.username()), decode(url.password().unwrap_or_default()))
+    });
+    debug!("[PROXY] Tunnelling to {}:{} through {}://{}:{}", host, port, url.scheme(), proxy_host, proxy_port);
+    let mut stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+    match url.scheme() {
+        "http" => http_connect(&mut stream, host, port, credentials).await?,
+        "socks5" => socks5_connect(&mut stream, host, port, false, credentials).await?,
+        "socks5h" => socks5_connect(&mut stream, host, port, true, credentials).await?,
+        scheme => return Err(io::Error::new(io::ErrorKind::Unsupported, format!("tunnels through {}:// proxies are not supported", scheme))),
+    }
+    Ok(stream)
+}
+
+/// Asks an HTTP proxy on `stream` for a tunnel to `host:port`.
+async fn http_connect(stream: &mut TcpStream, host: &str, port: u16, credentials: Option<(String, String)>) -> io::Result<()> {
+    let authority = if host.contains(':') { format!("[{}]:{}", host, port) } else { format!("{}:{}", host, port) };
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", authority);
+    if let Some((username, password)) = credentials {
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", BASE64.encode(format!("{}:{}", username, password))));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // A byte at a time, so that nothing the tunnel carries after the response head is read here
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() > 8192 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "proxy response head too long"));
+        }
+        head.push(stream.read_u8().await?);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let status_line = head.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(io::Error::new(io::ErrorKind::ConnectionRefused, format!("proxy refused the tunnel: {}", status_line))),
+    }
+}
+
+/// Asks a SOCKS5 proxy on `stream` for a connection to `host:port`, handing it the name with
+/// `remote_dns` and the address `host` resolves to here otherwise.
+async fn socks5_connect(stream: &mut TcpStream, host: &str, port: u16, remote_dns: bool, credentials: Option<(String, String)>) -> io::Result<()> {
+    let refused = |message: String| io::Error::new(io::ErrorKind::ConnectionRefused, message);
+
+    // Greeting: offer username/password authentication only when there are credentials
+    let methods: &[u8] = if credentials.is_some() { &[0, 2] } else { &[0] };
+    stream.write_all(&[&[5, methods.len() as u8], methods].concat()).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    match (choice[1], credentials) {
+        (0, _) => {}
+        (2, Some((username, password))) => {
+            let (username, password) = (username.as_bytes(), password.as_bytes());
+            if username.len() > 255 || password.len() > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "SOCKS5 credentials longer than 255 bytes"));
+            }
+            stream.write_all(&[&[1, username.len() as u8], username, &[password.len() as u8], password].concat()).await?;
+            let mut status = [0u8; 2];
+            stream.read_exact(&mut status).await?;
+            if status[1] != 0 {
+                return Err(refused("SOCKS5 proxy rejected the credentials".to_string()));
+            }
+        }
+        _ => return Err(refused("SOCKS5 proxy accepts none of the offered authentication methods".to_string())),
+    }
+
+    let mut request = vec![5, 1, 0];
+    let ip = match host.parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) if remote_dns => None,
+        Err(_) => Some(tokio::net::lookup_host((host, port)).await?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} has no addresses", host)))?
+            .ip()),
+    };
+    match ip {
+        Some(IpAddr::V4(ip)) => {
+            request.push(1);
+            request.extend_from_slice(&ip.octets());
+        }
+        Some(IpAddr::V6(ip)) => {
+            request.push(4);
+            request.extend_from_slice(&ip.octets());
+        }
+        None => {
+            let name = u8::try_from(host.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "host name too long for SOCKS5"))?;
+            request.push(3);
+            request.push(name);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // Reply: version, status, reserved, then the bound address, which is not needed
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(refused(format!("SOCKS5 proxy refused the connection (reply {})", reply[1])));
+    }
+    let address_len = match reply[3] {
+        1 => 4,
+        4 => 16,
+        3 => stream.read_u8().await? as usize,
+        other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("SOCKS5 reply with unknown address type {}", other))),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+fn text_response(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Body::from(format!("{}\n", message)));
+    *response.status_mut() = status;
+    response
+}
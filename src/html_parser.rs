@@ -1,18 +1,34 @@
 use scraper::{Html, Selector};
 use regex::Regex;
 use once_cell::sync::Lazy;
+use crate::network_client::EndpointOverrides;
 
 static SCRIPT_SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("script").expect("Failed to parse script selector"));
 static CHALLENGE_RE: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r#"window\.sssg_challenge\s*\(\s*['"]([^'"]+)['"]\s*,\s*(\d+)\s*,\s*(\d+)\s*\)"#)
         .expect("Failed to compile challenge regex")
 });
+static SCRIPT_SRC_SELECTOR: Lazy<Selector> = Lazy::new(|| Selector::parse("script[src]").expect("Failed to parse script[src] selector"));
+// String literals ending in `/answer` or `/check`, e.g. fetch("/.sssg/api/answer", ...)
+static ANSWER_PATH_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"["'`]((?:https?://[^"'`\s]+)?/[^"'`\s]*/answer)["'`]"#)
+        .expect("Failed to compile answer path regex")
+});
+static CHECK_PATH_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"["'`]((?:https?://[^"'`\s]+)?/[^"'`\s]*/check)["'`]"#)
+        .expect("Failed to compile check path regex")
+});
+// Form field names as they appear when a body is built by hand: "a=" + salt, `&b=${attempt}`, params.append("f", token)
+static FIELD_RE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?:[?&"'`]([A-Za-z_][\w-]*)=(?:["'`]\s*\+|\$\{))|(?:\.(?:append|set)\(\s*["'`]([A-Za-z_][\w-]*)["'`])"#)
+        .expect("Failed to compile form field regex")
+});
+// How far around an endpoint literal to look for the form fields sent to it.
+const FIELD_SEARCH_WINDOW: usize = 512;
 
 #[derive(Debug)]
 pub enum ParseError {
-    SelectorError(String),
     ChallengeScriptNotFound,
-    RegexError(regex::Error),
     ParameterNotFound(String),
     InvalidParameterValue(String),
 }
@@ -20,9 +36,7 @@ pub enum ParseError {
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ParseError::SelectorError(s) => write!(f, "HTML selector error: {}", s),
             ParseError::ChallengeScriptNotFound => write!(f, "SSSG challenge script not found in HTML"),
-            ParseError::RegexError(e) => write!(f, "Regex error: {}", e),
             ParseError::ParameterNotFound(param) => write!(f, "Challenge parameter '{}' not found", param),
             ParseError::InvalidParameterValue(param) => write!(f, "Invalid value for challenge parameter '{}'", param),
         }
@@ -31,18 +45,45 @@ impl std::fmt::Display for ParseError {
 
 impl std::error::Error for ParseError {}
 
+/// What kind of page a response body is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+    /// An SSSG interstitial carrying a `window.sssg_challenge(...)` call.
+    Challenge,
+    /// Anything else, i.e. the page we actually asked for.
+    Content,
+}
+
+/// Classifies a response body by looking for the SSSG challenge script. A script calling
+/// `window.sssg_challenge` makes it a challenge even if the call cannot be parsed, so that a
+/// changed challenge is reported rather than taken for the page.
+pub fn classify_page(html_content: &str) -> PageKind {
+    let document = Html::parse_document(html_content);
+    let challenged = document.select(&SCRIPT_SELECTOR).any(|script| script.text().any(|text| text.contains("window.sssg_challenge")));
+    if challenged { PageKind::Challenge } else { PageKind::Content }
+}
+
+/// The arguments of a `window.sssg_challenge(salt, difficulty, timeout)` call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Challenge {
+    pub salt: String,
+    /// Required number of leading zero bits.
+    pub difficulty: u32,
+    /// The third argument (a timeout), passed through as given by the page.
+    pub timeout: u64,
+}
+
 /// Extracts the `salt` and `difficulty` from the HTML content.
 /// It looks for a script tag containing `window.sssg_challenge(...)`.
 pub fn extract_challenge_params(html_content: &str) -> Result<(String, u32), ParseError> {
+    extract_challenge(html_content).map(|challenge| (challenge.salt, challenge.difficulty))
+}
+
+/// Extracts all `window.sssg_challenge(...)` arguments from the HTML content.
+pub fn extract_challenge(html_content: &str) -> Result<Challenge, ParseError> {
     let document = Html::parse_document(html_content);
-    // Use the lazily initialized selector and regex
-    // let script_selector = Selector::parse("script").map_err(|e| ParseError::SelectorError(e.to_string()))?; // Replaced by SCRIPT_SELECTOR
 
-    // Regex to find the sssg_challenge call and capture its arguments
     // Example: window.sssg_challenge("salt_value", difficulty_value, timeout_value);
-    // let re = Regex::new(r#"window\.sssg_challenge\s*\(\s*['"]([^'"]+)['"]\s*,\s*(\d+)\s*,\s*(\d+)\s*\)"#)
-    //     .map_err(ParseError::RegexError)?; // Replaced by CHALLENGE_RE
-
     for script_element in document.select(&SCRIPT_SELECTOR) {
         if let Some(script_text) = script_element.text().next() {
             if let Some(captures) = CHALLENGE_RE.captures(script_text) {
@@ -55,16 +96,105 @@ pub fn extract_challenge_params(html_content: &str) -> Result<(String, u32), Par
                     .as_str();
                 let difficulty = difficulty_str.parse::<u32>()
                     .map_err(|_| ParseError::InvalidParameterValue(format!("difficulty: {}", difficulty_str)))?;
-                
-                // The third parameter (timeout) is captured but not used in this function's return.
-                // let _timeout = captures.get(3)
-                //     .ok_or_else(|| ParseError::ParameterNotFound("timeout".to_string()))?
-                //     .as_str();
+                let timeout_str = captures.get(3)
+                    .ok_or_else(|| ParseError::ParameterNotFound("timeout".to_string()))?
+                    .as_str();
+                let timeout = timeout_str.parse::<u64>()
+                    .map_err(|_| ParseError::InvalidParameterValue(format!("timeout: {}", timeout_str)))?;
 
-                return Ok((salt, difficulty));
+                return Ok(Challenge { salt, difficulty, timeout });
             }
         }
     }
 
     Err(ParseError::ChallengeScriptNotFound)
-}
\ No newline at end of file
+}
+
+/// Looks for SSSG API endpoint paths and form field names in the inline scripts of the challenge page.
+/// Anything that cannot be found is left as `None`.
+pub fn discover_endpoints(html_content: &str) -> EndpointOverrides {
+    let document = Html::parse_document(html_content);
+    let mut discovered = EndpointOverrides::default();
+    for script_element in document.select(&SCRIPT_SELECTOR) {
+        let script_text: String = script_element.text().collect();
+        discovered.fill_from(discover_endpoints_in_script(&script_text));
+    }
+    discovered
+}
+
+/// Returns the `src` of every script on the page that looks like it belongs to SSSG.
+pub fn extract_challenge_script_srcs(html_content: &str) -> Vec<String> {
+    let document = Html::parse_document(html_content);
+    document.select(&SCRIPT_SRC_SELECTOR)
+        .filter_map(|element| element.value().attr("src"))
+        .filter(|src| src.contains("sssg"))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Looks for SSSG API endpoint paths and form field names in a piece of JavaScript.
+/// Field names are taken from the code right after each endpoint literal, falling back to the code before it.
+pub fn discover_endpoints_in_script(script_text: &str) -> EndpointOverrides {
+    let mut discovered = EndpointOverrides::default();
+
+    if let Some(m) = ANSWER_PATH_RE.captures(script_text).and_then(|c| c.get(1)) {
+        discovered.answer_path = Some(m.as_str().to_string());
+        if let [salt_field, attempt_field] = fields_near(script_text, m.start(), m.end(), 2).as_slice() {
+            discovered.salt_field = Some(salt_field.clone());
+            discovered.attempt_field = Some(attempt_field.clone());
+        }
+    }
+    if let Some(m) = CHECK_PATH_RE.captures(script_text).and_then(|c| c.get(1)) {
+        discovered.check_path = Some(m.as_str().to_string());
+        if let [token_field] = fields_near(script_text, m.start(), m.end(), 1).as_slice() {
+            discovered.token_field = Some(token_field.clone());
+        }
+    }
+
+    discovered
+}
+
+/// Returns up to `count` field names sent to the endpoint literal at `start..end`:
+/// the first ones after it, or else the last ones before it.
+fn fields_near(script_text: &str, start: usize, end: usize, count: usize) -> Vec<String> {
+    let after = &script_text[end..floor_char_boundary(script_text, end + FIELD_SEARCH_WINDOW)];
+    let mut fields = fields_in(after);
+    if !fields.is_empty() {
+        fields.truncate(count);
+        return fields;
+    }
+    let before = &script_text[floor_char_boundary(script_text, start.saturating_sub(FIELD_SEARCH_WINDOW))..start];
+    // Only consider code after the previous endpoint literal, if there is one in the window.
+    let from = ANSWER_PATH_RE.find_iter(before)
+        .chain(CHECK_PATH_RE.find_iter(before))
+        .map(|m| m.end())
+        .max()
+        .unwrap_or(0);
+    let mut fields = fields_in(&before[from..]);
+    fields.drain(..fields.len().saturating_sub(count));
+    fields
+}
+
+fn fields_in(text: &str) -> Vec<String> {
+    // Stop at the next endpoint literal so fields for /check are not attributed to /answer.
+    let limit = [ANSWER_PATH_RE.find(text), CHECK_PATH_RE.find(text)]
+        .into_iter()
+        .flatten()
+        .map(|m| m.start())
+        .min()
+        .unwrap_or(text.len());
+    FIELD_RE.captures_iter(&text[..limit])
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)))
+        .map(|m| m.as_str().to_string())
+        .collect()
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
@@ -0,0 +1,19 @@
+//! Solver for the SSSG proof-of-work challenge, usable as a library or through the CLI in `main.rs`.
+
+pub mod acquire;
+pub mod cache;
+pub mod clearance;
+pub mod config;
+pub mod cookies;
+pub mod flaresolverr;
+pub mod forward_proxy;
+pub mod html_parser;
+pub mod mitm;
+pub mod network_client;
+pub mod pow_solver;
+pub mod relay;
+pub mod report;
+pub mod reverse_proxy;
+pub mod server;
+pub mod single_flight;
+pub mod utils;
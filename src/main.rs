@@ -1,33 +1,14 @@
-mod network_client;
-mod html_parser;
-mod pow_solver;
-mod utils;
-
-use clap::Parser;
-use reqwest::Client;
-use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, ORIGIN, PRAGMA, REFERER, USER_AGENT, HeaderName};
+use kiwifarms_captchabuster::{acquire, cache, clearance, config, cookies, forward_proxy, html_parser, mitm, network_client, pow_solver, report, reverse_proxy, server, utils};
+
+use clap::{Parser, Subcommand};
+use std::collections::BTreeMap;
 use url::Url;
-use once_cell::sync::Lazy;
-use log::{info, debug, error, warn};
-
-static BASE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
-    let mut headers = HeaderMap::new();
-    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.5"));
-    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
-    headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
-    headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
-    headers.insert(HeaderName::from_static("sec-fetch-dest"), HeaderValue::from_static("empty"));
-    headers.insert(HeaderName::from_static("sec-fetch-mode"), HeaderValue::from_static("cors"));
-    headers.insert(HeaderName::from_static("sec-fetch-site"), HeaderValue::from_static("same-origin"));
-    headers.insert(HeaderName::from_static("sec-gpc"), HeaderValue::from_static("1"));
-    headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/135.0.0.0 Safari/537.36"));
-    headers.insert(HeaderName::from_static("sec-ch-ua"), HeaderValue::from_static("\"Google Chrome\";v=\"135\", \"Not-A.Brand\";v=\"8\", \"Chromium\";v=\"135\""));
-    headers.insert(HeaderName::from_static("sec-ch-ua-mobile"), HeaderValue::from_static("?0"));
-    headers.insert(HeaderName::from_static("sec-ch-ua-platform"), HeaderValue::from_static("\"Windows\""));
-    headers
-});
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use reqwest_cookie_store::CookieStoreMutex;
+use log::{info, warn};
 
 // Custom Application Error Type
 #[derive(Debug)]
@@ -36,6 +17,11 @@ enum AppError {
     Parse(html_parser::ParseError),
     Io(std::io::Error),
     UrlParse(url::ParseError),
+    ChallengeNotCleared { rounds: u32 },
+    Solve(pow_solver::SolveError),
+    Config(config::ConfigError),
+    InvalidSolution { leading_zeros: u32, difficulty: u32 },
+    ClearanceRejected(clearance::ClearanceStatus),
     Boxed(Box<dyn std::error::Error>), // For other generic errors
 }
 
@@ -46,6 +32,11 @@ impl std::fmt::Display for AppError {
             AppError::Parse(err) => write!(f, "Parsing error: {}", err),
             AppError::Io(err) => write!(f, "IO error: {}", err),
             AppError::UrlParse(err) => write!(f, "URL parsing error: {}", err),
+            AppError::ChallengeNotCleared { rounds } => write!(f, "Page still presents a challenge after {} round(s) of solving", rounds),
+            AppError::Solve(err) => write!(f, "Solver error: {}", err),
+            AppError::Config(err) => write!(f, "Config error: {}", err),
+            AppError::InvalidSolution { leading_zeros, difficulty } => write!(f, "Solution has {} leading zero bits, {} required", leading_zeros, difficulty),
+            AppError::ClearanceRejected(status) => write!(f, "Clearance is not accepted: {}", status),
             AppError::Boxed(err) => write!(f, "Error: {}", err),
         }
     }
@@ -58,17 +49,86 @@ impl std::error::Error for AppError {
             AppError::Parse(err) => Some(err),
             AppError::Io(err) => Some(err),
             AppError::UrlParse(err) => Some(err),
+            AppError::ChallengeNotCleared { .. } => None,
+            AppError::Solve(err) => Some(err),
+            AppError::Config(err) => Some(err),
+            AppError::InvalidSolution { .. } => None,
+            AppError::ClearanceRejected(_) => None,
             AppError::Boxed(err) => Some(err.as_ref()),
         }
     }
 }
 
+// Process exit codes. These are part of the CLI's interface: scripts rely on them, so never renumber.
+const EXIT_FAILURE: u8 = 1; // Anything not covered below
+const EXIT_USAGE: u8 = 2; // Bad arguments or config file (also what clap exits with)
+const EXIT_NETWORK: u8 = 3; // Connection failures, timeouts and unexpected HTTP statuses; worth retrying
+const EXIT_CHALLENGE_NOT_FOUND: u8 = 4; // The page has no SSSG challenge we can parse
+const EXIT_BLOCKED: u8 = 5; // Refused outright, or still challenged after every round; back off
+const EXIT_DIFFICULTY_TOO_HIGH: u8 = 6; // Difficulty above --max-difficulty
+const EXIT_SOLVER_TIMEOUT: u8 = 7; // No solution within the solve timeout
+const EXIT_ANSWER_REJECTED: u8 = 8; // /answer refused every solution
+const EXIT_CHECK_REJECTED: u8 = 9; // /check refused the token
+const EXIT_IO: u8 = 10; // Reading or writing local files failed
+const EXIT_CLEARANCE_CHALLENGED: u8 = 11; // check-clearance: the clearance expired or was not accepted
+
+impl AppError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            AppError::Network(err) => match err {
+                network_client::NetworkError::AnswerRejected { .. } => EXIT_ANSWER_REJECTED,
+                network_client::NetworkError::CheckRejected { .. } => EXIT_CHECK_REJECTED,
+                network_client::NetworkError::MissingAuthToken(context) if context == "/check" => EXIT_CHECK_REJECTED,
+                network_client::NetworkError::MissingAuthToken(_) => EXIT_ANSWER_REJECTED,
+                network_client::NetworkError::Blocked { .. } => EXIT_BLOCKED,
+                network_client::NetworkError::UrlParseError(_)
+                | network_client::NetworkError::InvalidHeader(_)
+                | network_client::NetworkError::InvalidProxy(_)
+                | network_client::NetworkError::InvalidResolve(_)
+                | network_client::NetworkError::InvalidTls(_) => EXIT_USAGE,
+                _ => EXIT_NETWORK,
+            },
+            AppError::Parse(_) => EXIT_CHALLENGE_NOT_FOUND,
+            AppError::Io(_) => EXIT_IO,
+            AppError::UrlParse(_) => EXIT_USAGE,
+            AppError::ChallengeNotCleared { .. } => EXIT_BLOCKED,
+            AppError::Solve(err) => match err {
+                pow_solver::SolveError::DifficultyTooHigh { .. } => EXIT_DIFFICULTY_TOO_HIGH,
+                pow_solver::SolveError::Timeout { .. } | pow_solver::SolveError::NoSolution => EXIT_SOLVER_TIMEOUT,
+            },
+            AppError::Config(_) => EXIT_USAGE,
+            AppError::InvalidSolution { .. } => EXIT_ANSWER_REJECTED, // What /answer would do with it
+            AppError::ClearanceRejected(clearance::ClearanceStatus::Blocked) => EXIT_BLOCKED,
+            AppError::ClearanceRejected(_) => EXIT_CLEARANCE_CHALLENGED,
+            AppError::Boxed(_) => EXIT_FAILURE,
+        }
+    }
+
+    /// Whether the failure is the outcome of a check whose report has already been printed, so
+    /// `--output-format json` needs no error document on top of it.
+    fn reported(&self) -> bool {
+        matches!(self, AppError::ClearanceRejected(_) | AppError::InvalidSolution { .. })
+    }
+}
+
 impl From<network_client::NetworkError> for AppError {
     fn from(err: network_client::NetworkError) -> Self {
         AppError::Network(err)
     }
 }
 
+impl From<pow_solver::SolveError> for AppError {
+    fn from(err: pow_solver::SolveError) -> Self {
+        AppError::Solve(err)
+    }
+}
+
+impl From<config::ConfigError> for AppError {
+    fn from(err: config::ConfigError) -> Self {
+        AppError::Config(err)
+    }
+}
+
 impl From<html_parser::ParseError> for AppError {
     fn from(err: html_parser::ParseError) -> Self {
         AppError::Parse(err)
@@ -98,26 +158,595 @@ impl From<Box<dyn std::error::Error>> for AppError {
     }
 }
 
+impl From<acquire::AcquireError> for AppError {
+    fn from(err: acquire::AcquireError) -> Self {
+        match err {
+            acquire::AcquireError::Network(err) => AppError::Network(err),
+            acquire::AcquireError::Parse(err) => AppError::Parse(err),
+            acquire::AcquireError::Solve(err) => AppError::Solve(err),
+            acquire::AcquireError::Io(err) => AppError::Io(err),
+            acquire::AcquireError::UrlParse(err) => AppError::UrlParse(err),
+            acquire::AcquireError::Config(err) => AppError::Config(err),
+            acquire::AcquireError::NotCleared { rounds } => AppError::ChallengeNotCleared { rounds },
+            acquire::AcquireError::Internal(message) => AppError::Boxed(message.into()),
+            acquire::AcquireError::Shared(err) => match Arc::try_unwrap(err) {
+                Ok(err) => AppError::from(err),
+                Err(err) => AppError::Boxed(err.to_string().into()),
+            },
+        }
+    }
+}
+
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    /// Human-readable progress lines (and the page HTML for `fetch`).
+    Text,
+    /// A single JSON document describing the run.
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
-struct Args {
+struct Cli {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Obtain an sssg_clearance for a URL (reusing a cached one if it still works).
+    Clear(ClearArgs),
+    /// Obtain clearance if needed and print the page.
+    Fetch(ClearArgs),
+    /// Test whether a stored clearance still gets the page, without solving anything.
+    CheckClearance(CheckClearanceArgs),
+    /// Keep clearances for one or more URLs fresh, replacing each ahead of its expiry.
+    Refresh(RefreshArgs),
+    /// Run a local HTTP API that hands out clearances to other programs.
+    Serve(ServeArgs),
+    /// Run a forward HTTP(S) proxy that gets clearances for the requests passing through it.
+    Proxy(ProxyArgs),
+    /// Serve one protected origin on a local address, getting clearances for every request.
+    ReverseProxy(ReverseProxyArgs),
+    /// Solve a challenge offline, given its salt and difficulty.
+    Solve(SolveArgs),
+    /// Check a solution offline.
+    Verify(VerifyArgs),
+    /// Measure solver throughput.
+    Bench(BenchArgs),
+    /// Inspect and manage the clearance cache.
+    #[clap(subcommand)]
+    Cookies(CookiesCommand),
+    /// Inspect the configuration.
+    #[clap(subcommand)]
+    Config(ConfigCommand),
+}
+
+// Options shared by every subcommand.
+#[derive(clap::Args, Debug)]
+struct CommonArgs {
+    /// Config file to use instead of the one found in the XDG config directories.
+    #[clap(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Config profile to use instead of the one matching the URL's origin.
+    #[clap(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// What to print on stdout: progress lines, or one JSON document with the whole run.
+    #[clap(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
+    /// 'Name: value' for every request; 'Name:' removes a header; '@FILE' reads one header per line.
+    #[clap(short = 'H', long = "header", global = true, value_name = "HEADER")]
+    headers: Vec<String>,
+
+    /// Like -H, for page and script GETs only.
+    #[clap(long = "page-header", global = true, value_name = "HEADER")]
+    page_headers: Vec<String>,
+
+    /// Like -H, for the /answer and /check POSTs only.
+    #[clap(long = "api-header", global = true, value_name = "HEADER")]
+    api_headers: Vec<String>,
+
+    /// Proxy for every request: http://, https://, socks5:// or socks5h:// (resolves names on the proxy, for .onion).
+    #[clap(long, global = true, value_name = "URL")]
+    proxy: Option<String>,
+
+    /// Comma-separated hosts that bypass the proxy. Defaults to $NO_PROXY.
+    #[clap(long, global = true, value_name = "HOSTS")]
+    no_proxy: Option<String>,
+
+    /// Connect to ADDR (comma-separated for several) instead of resolving HOST; URLs, Host and cookies still use HOST. Repeatable.
+    #[clap(long, global = true, value_name = "HOST:PORT:ADDR")]
+    resolve: Vec<String>,
+
+    /// PEM CA bundle to trust in addition to the system roots.
+    #[clap(long, global = true, value_name = "PATH")]
+    cacert: Option<PathBuf>,
+
+    /// PEM client certificate for mutual TLS (may also hold the key).
+    #[clap(long, global = true, value_name = "PATH")]
+    cert: Option<PathBuf>,
+
+    /// PEM private key for --cert.
+    #[clap(long, global = true, value_name = "PATH", requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// Skip TLS certificate verification. For test mirrors only.
+    #[clap(short = 'k', long, global = true)]
+    insecure: bool,
+
+    /// Connection timeout (default 10, 0 for none).
+    #[clap(long, global = true, value_name = "SECS")]
+    connect_timeout: Option<u64>,
+
+    /// Longest wait for more response data (default 30, 0 for none).
+    #[clap(long, global = true, value_name = "SECS")]
+    read_timeout: Option<u64>,
+
+    /// Limit for each HTTP step including its retries (default 120, 0 for none).
+    #[clap(long, global = true, value_name = "SECS")]
+    timeout: Option<u64>,
+
+    /// Where clearance cache files are kept. Defaults to the platform cache directory.
+    #[clap(long, global = true)]
+    cache_dir: Option<PathBuf>,
+
+    /// Neither read nor write the clearance cache.
+    #[clap(long, global = true)]
+    no_cache: bool,
+
+    /// Attempts per network step (default 4); also bounds salt refreshes after a rejected answer.
+    #[clap(long, global = true)]
+    max_attempts: Option<u32>,
+
+    /// Base backoff delay in milliseconds (default 500), doubled per retry (with jitter).
+    #[clap(long, global = true)]
+    retry_base_ms: Option<u64>,
+
+    /// Longest single wait in milliseconds (default 30000), including Retry-After.
+    #[clap(long, global = true)]
+    retry_max_ms: Option<u64>,
+}
+
+impl CommonArgs {
+    fn json_output(&self) -> bool {
+        self.output_format == OutputFormat::Json
+    }
+
+    /// The settings given on the command line, which win over the config file.
+    fn cli_profile(&self) -> Result<config::Profile, AppError> {
+        Ok(config::Profile {
+            headers: header_args(&self.headers)?,
+            page_headers: header_args(&self.page_headers)?,
+            api_headers: header_args(&self.api_headers)?,
+            proxy: self.proxy.clone(),
+            no_proxy: self.no_proxy.clone(),
+            resolve: self.resolve.clone(),
+            cacert: self.cacert.clone(),
+            cert: self.cert.clone(),
+            key: self.key.clone(),
+            insecure: self.insecure.then_some(true),
+            connect_timeout: self.connect_timeout,
+            read_timeout: self.read_timeout,
+            timeout: self.timeout,
+            cache_dir: self.cache_dir.clone(),
+            max_attempts: self.max_attempts,
+            retry_base_ms: self.retry_base_ms,
+            retry_max_ms: self.retry_max_ms,
+            ..config::Profile::default()
+        })
+    }
+
+    /// Loads the config file and merges it with `cli` for `origin`.
+    fn settings(&self, origin: Option<&str>, cli: &config::Profile) -> Result<config::Profile, AppError> {
+        let (config, path) = config::Config::load(self.config.as_deref())?;
+        let (settings, profile) = config.effective(origin, self.profile.as_deref(), cli)?;
+        if let (Some(path), Some(profile)) = (&path, &profile) {
+            info!("Using profile '{}' from {}", profile, path.display());
+        }
+        Ok(settings)
+    }
+}
+
+/// Parses `-H` style arguments into header overrides (an empty value removes the header).
+/// `@FILE` reads the file's lines the same way, skipping blank lines and `#` comments.
+fn header_args(args: &[String]) -> Result<BTreeMap<String, String>, AppError> {
+    let mut headers = BTreeMap::new();
+    for arg in args {
+        match arg.strip_prefix('@') {
+            Some(path) => {
+                for line in std::fs::read_to_string(path)?.lines() {
+                    let line = line.trim();
+                    if line.is_empty() || line.starts_with('#') {
+                        continue;
+                    }
+                    let (name, value) = parse_header(line).map_err(|e| network_client::NetworkError::InvalidHeader(format!("in {}: {}", path, e)))?;
+                    headers.insert(name, value);
+                }
+            }
+            None => {
+                let (name, value) = parse_header(arg).map_err(network_client::NetworkError::InvalidHeader)?;
+                headers.insert(name, value);
+            }
+        }
+    }
+    Ok(headers)
+}
+
+fn parse_header(line: &str) -> Result<(String, String), String> {
+    match line.split_once(':') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((name.trim().to_string(), value.trim().to_string())),
+        _ => Err(format!("expected 'Name: value' or 'Name:', got {:?}", line)),
+    }
+}
+
+// Solver options, shared by the subcommands that solve.
+#[derive(clap::Args, Debug)]
+struct SolverArgs {
+    /// Solver threads. Defaults to the number of CPUs.
+    #[clap(long)]
+    threads: Option<usize>,
+
+    /// Refuse challenges harder than this (leading zero bits, default 32).
+    #[clap(long, value_parser = clap::value_parser!(u32).range(0..=pow_solver::MAX_SOLVABLE_DIFFICULTY as i64))]
+    max_difficulty: Option<u32>,
+
+    /// Give up solving after this long. Defaults to the challenge's own timeout.
+    #[clap(long, value_name = "SECS")]
+    solve_timeout: Option<u64>,
+
+    /// Solver backend. Only `cpu` exists so far.
+    #[clap(long)]
+    solver: Option<config::SolverBackend>,
+}
+
+impl SolverArgs {
+    fn apply(&self, profile: &mut config::Profile) {
+        profile.threads = self.threads;
+        profile.max_difficulty = self.max_difficulty;
+        profile.solve_timeout = self.solve_timeout;
+        profile.solver = self.solver;
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct ClearArgs {
     #[clap(value_parser)]
     url: String,
 
+    /// When to call /check: never, always (plain --check) or auto (the default).
+    #[clap(long, value_name = "POLICY", num_args = 0..=1, require_equals = true, default_missing_value = "always")]
+    check: Option<config::CheckPolicy>,
+
+    /// With `fetch`, how many times to solve if the cleared page keeps presenting a challenge.
+    #[clap(long, default_value_t = 3)]
+    max_rounds: u32,
+
+    /// An equivalent origin to try, in order, if the URL's origin fails. Repeatable.
+    #[clap(long = "mirror", value_name = "ORIGIN")]
+    mirrors: Vec<String>,
+
+    /// Try the URL's origin and every --mirror at once and use the first with a solvable challenge.
+    #[clap(long)]
+    race: bool,
+
+    /// Give up if clearance is not obtained within this long, solving included.
+    #[clap(long, value_name = "SECS")]
+    deadline: Option<u64>,
+
+    /// Seed the cookie jar from a Netscape cookies.txt or JSON cookie file.
+    #[clap(long, value_name = "PATH")]
+    cookies: Option<PathBuf>,
+
+    /// Write the cookie jar after clearance: netscape (alias wget), curl, header or json.
+    #[clap(long, value_name = "FORMAT")]
+    export_cookies: Option<cookies::CookieFormat>,
+
+    /// File for --export-cookies. Defaults to stdout for `clear`; required with `fetch` and with JSON output.
+    #[clap(long, requires = "export_cookies")]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    solver: SolverArgs,
+
+    // Overrides for the SSSG API layout. When unset, values discovered on the challenge page are used,
+    // falling back to the stock `/.sssg/api/...` endpoints and `a`/`b`/`f` field names.
+    /// Path or URL of the /answer endpoint.
+    #[clap(long)]
+    answer_path: Option<String>,
+
+    /// Path or URL of the /check endpoint.
+    #[clap(long)]
+    check_path: Option<String>,
+
+    /// Form field carrying the salt to /answer.
+    #[clap(long)]
+    salt_field: Option<String>,
+
+    /// Form field carrying the attempt to /answer.
+    #[clap(long)]
+    attempt_field: Option<String>,
+
+    /// Form field carrying the token to /check.
+    #[clap(long)]
+    token_field: Option<String>,
+}
+
+impl ClearArgs {
+    fn cli_profile(&self, common: &CommonArgs) -> Result<config::Profile, AppError> {
+        let mut profile = common.cli_profile()?;
+        self.solver.apply(&mut profile);
+        profile.answer_path = self.answer_path.clone();
+        profile.check_path = self.check_path.clone();
+        profile.salt_field = self.salt_field.clone();
+        profile.attempt_field = self.attempt_field.clone();
+        profile.token_field = self.token_field.clone();
+        profile.check = self.check;
+        profile.cookies = self.cookies.clone();
+        profile.deadline = self.deadline;
+        profile.mirrors = self.mirrors.clone();
+        profile.race = self.race.then_some(true);
+        Ok(profile)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+struct SolveArgs {
+    salt: String,
+
+    difficulty: u32,
+
+    #[clap(flatten)]
+    solver: SolverArgs,
+}
+
+// Arguments of `check-clearance`. The clearance comes from --clearance, else --cookies, else the cache.
+#[derive(clap::Args, Debug)]
+struct CheckClearanceArgs {
+    url: String,
+
+    /// Netscape cookies.txt or JSON cookie file holding the clearance.
+    #[clap(long, value_name = "PATH", conflicts_with = "clearance")]
+    cookies: Option<PathBuf>,
+
+    /// The sssg_clearance value itself.
+    #[clap(long, value_name = "VALUE")]
+    clearance: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct RefreshArgs {
+    #[clap(value_parser, required = true)]
+    urls: Vec<String>,
+
+    /// Replace a clearance this long before it expires.
+    #[clap(long, value_name = "SECS", default_value_t = 300)]
+    margin: u64,
+
+    /// How often to re-check clearances that are session cookies.
+    #[clap(long, value_name = "SECS", default_value_t = 3600)]
+    interval: u64,
+
+    /// Wait this long before retrying an origin whose refresh failed.
+    #[clap(long, value_name = "SECS", default_value_t = 60)]
+    retry_after: u64,
+
+    /// Refresh what is due once and exit, instead of running until killed.
     #[clap(long)]
-    html: bool,
+    once: bool,
+
+    /// After each refresh, write every origin's cookies: netscape (alias wget), curl, json, or header (one origin only).
+    #[clap(long, value_name = "FORMAT")]
+    export_cookies: Option<cookies::CookieFormat>,
+
+    /// File for --export-cookies, replaced atomically. Defaults to stdout.
+    #[clap(long, requires = "export_cookies")]
+    output: Option<PathBuf>,
+
+    #[clap(flatten)]
+    clearance: ClearanceArgs,
+}
+
+// How clearances are obtained, for the commands that keep getting them: `refresh` and the services.
+#[derive(clap::Args, Debug)]
+struct ClearanceArgs {
+    /// When to call /check: never, always (plain --check) or auto (the default).
+    #[clap(long, value_name = "POLICY", num_args = 0..=1, require_equals = true, default_missing_value = "always")]
+    check: Option<config::CheckPolicy>,
+
+    /// Give up on obtaining a clearance that takes longer than this, solving included.
+    #[clap(long, value_name = "SECS")]
+    deadline: Option<u64>,
+
+    #[clap(flatten)]
+    solver: SolverArgs,
+}
 
-    #[clap(long)] // If present, perform the /check call. Default is to skip.
-    check: bool,
+impl ClearanceArgs {
+    fn cli_profile(&self, common: &CommonArgs) -> Result<config::Profile, AppError> {
+        let mut profile = common.cli_profile()?;
+        self.solver.apply(&mut profile);
+        profile.check = self.check;
+        profile.deadline = self.deadline;
+        Ok(profile)
+    }
+}
+
+// Options shared by the commands that hand out clearances to other programs: `serve`, `proxy` and
+// `reverse-proxy`.
+#[derive(clap::Args, Debug)]
+struct ServiceArgs {
+    /// Solve for a new clearance rather than use one expiring within this long.
+    #[clap(long, value_name = "SECS", default_value_t = 60)]
+    margin: u64,
+
+    /// How long to use a clearance that is a session cookie before checking it again.
+    #[clap(long, value_name = "SECS", default_value_t = 1800)]
+    session_ttl: u64,
+
+    #[clap(flatten)]
+    clearance: ClearanceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Address to listen on.
+    #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:8191")]
+    listen: std::net::SocketAddr,
+
+    #[clap(flatten)]
+    service: ServiceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ProxyArgs {
+    /// Address to listen on.
+    #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:3128")]
+    listen: std::net::SocketAddr,
+
+    /// Intercept this origin (repeatable), besides those listed in the config file's profiles.
+    #[clap(long = "origin", value_name = "ORIGIN")]
+    origins: Vec<String>,
+
+    /// CA certificate to intercept HTTPS with; generated with its key if neither exists (default: in the data directory).
+    #[clap(long, value_name = "FILE")]
+    ca_cert: Option<PathBuf>,
+
+    /// The CA's private key (default: next to the default certificate).
+    #[clap(long, value_name = "FILE")]
+    ca_key: Option<PathBuf>,
+
+    #[clap(flatten)]
+    service: ServiceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReverseProxyArgs {
+    /// Address to listen on.
+    #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:8080")]
+    listen: std::net::SocketAddr,
+
+    /// The protected origin to relay to, e.g. https://kiwifarms.st.
+    #[clap(long, value_name = "ORIGIN")]
+    upstream: Url,
+
+    #[clap(flatten)]
+    service: ServiceArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct VerifyArgs {
+    salt: String,
+
+    attempt: String,
+
+    difficulty: u32,
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
+    /// Difficulty of each benchmark challenge.
+    #[clap(long, default_value_t = 16)]
+    difficulty: u32,
+
+    /// Number of challenges to solve.
+    #[clap(long, default_value_t = 5)]
+    rounds: u32,
+
+    #[clap(flatten)]
+    solver: SolverArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum CookiesCommand {
+    /// List cached origins with their clearance expiry.
+    List,
+    /// Print the cached cookies for a URL's origin.
+    Show {
+        url: String,
+
+        /// Cookie format: netscape (alias wget), curl, header or json.
+        #[clap(long, default_value = "json")]
+        format: cookies::CookieFormat,
+    },
+    /// Merge a Netscape cookies.txt or JSON cookie file into the cache for a URL's origin.
+    Import {
+        url: String,
+
+        file: PathBuf,
+    },
+    /// Delete the cached cookies for the given URLs' origins.
+    Remove {
+        #[clap(required = true)]
+        urls: Vec<String>,
+    },
+    /// Delete every cache file.
+    Purge,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigCommand {
+    /// Print the effective settings: config file, matching profile and command-line options merged.
+    Show {
+        /// Show the settings for this URL's origin. Without it, only `[defaults]` (or --profile) apply.
+        url: Option<String>,
+    },
+    /// List the paths a config file is looked for at.
+    Paths,
 }
 
 #[tokio::main]
-async fn main() -> Result<(), AppError> {
-    let args = Args::parse();
-    env_logger::init(); // Initialize logger
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    env_logger::init(); // Initialize logger; it writes to stderr, so stdout stays clean for HTML or JSON
+
+    let result = match &cli.command {
+        Command::Clear(args) => run_clear(&cli.common, args, false).await,
+        Command::Fetch(args) => run_clear(&cli.common, args, true).await,
+        Command::Solve(args) => run_solve(&cli.common, args),
+        Command::CheckClearance(args) => run_check_clearance(&cli.common, args).await,
+        Command::Refresh(args) => run_refresh(&cli.common, args).await,
+        Command::Serve(args) => run_serve(&cli.common, args).await,
+        Command::Proxy(args) => run_proxy(&cli.common, args).await,
+        Command::ReverseProxy(args) => run_reverse_proxy(&cli.common, args).await,
+        Command::Verify(args) => run_verify(&cli.common, args),
+        Command::Bench(args) => run_bench(&cli.common, args),
+        Command::Cookies(command) => run_cookies(&cli.common, command),
+        Command::Config(command) => run_config(&cli.common, command),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e); // Every variant names its kind ("Network error: ...")
+            if cli.common.json_output() && !e.reported() {
+                // Scripts reading stdout get a document for failures too
+                let report = report::ErrorReport { error: e.to_string(), exit_code: e.exit_code() };
+                let _ = print_json(&report);
+            }
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
 
-    let suppress_logging = args.html; // We'll keep this for now to control HTML output, but phase out for general logging
+/// `clear` and `fetch`: obtain (or reuse) a clearance for `args.url`, and with `fetch_page` print the page.
+async fn run_clear(common: &CommonArgs, args: &ClearArgs, fetch_page: bool) -> Result<(), AppError> {
+    let json_output = common.json_output();
+    // Cookies on stdout would be mixed into the JSON document or the page
+    if args.export_cookies.is_some() && args.output.is_none() {
+        if json_output {
+            return Err(AppError::Boxed("--export-cookies needs --output when --output-format is json".into()));
+        }
+        if fetch_page {
+            return Err(AppError::Boxed("--export-cookies needs --output with fetch, which prints the page".into()));
+        }
+    }
+    let suppress_logging = fetch_page || json_output; // Human-readable chatter only when stdout is not carrying HTML or JSON
 
     if !suppress_logging { // This specific one might stay if it's considered direct user output not a "log"
         println!("Target URL: {}", args.url);
@@ -127,81 +756,496 @@ async fn main() -> Result<(), AppError> {
 
     let base_url = Url::parse(&args.url)?; // url::ParseError converted via From trait
     let origin_url = base_url.origin().unicode_serialization();
+    let cli = args.cli_profile(common)?;
+    let settings = common.settings(Some(&origin_url), &cli)?;
 
-    let mut headers = BASE_HEADERS.clone(); // Clone the lazily initialized base headers
-    // Add dynamic headers
-    if let Ok(origin_val) = HeaderValue::from_str(&origin_url) {
-        headers.insert(ORIGIN, origin_val);
-    }
-    if let Ok(referer_val) = HeaderValue::from_str(&args.url) { // Use the full URL as referer
-        headers.insert(REFERER, referer_val);
-    }
-
-    let client = Client::builder()
-        .default_headers(headers)
-        .cookie_provider(std::sync::Arc::new(reqwest::cookie::Jar::default()))
-        .build()?; // reqwest::Error converted via From trait
-
-    // let origin = base_url.origin().unicode_serialization(); // Now used for ORIGIN header
-
-    // 1. Fetch initial page and extract challenge parameters
-    info!("Fetching initial page...");
-    let html_content = network_client::fetch_initial_page_html(&client, &args.url).await?;
-    info!("Page fetched. Extracting challenge parameters...");
-    let (salt, difficulty) = html_parser::extract_challenge_params(&html_content)?;
-    info!("Salt: {}, Difficulty: {}", salt, difficulty);
-
-    // 2. Solve PoW
-    let num_threads = num_cpus::get();
-    let initial_attempt_seed = utils::generate_initial_attempt_nonce_seed();
-    info!("Starting PoW with difficulty {} on {} threads (initial seed: {})...", difficulty, num_threads, initial_attempt_seed);
-
-    if let Some((successful_attempt, solution_hash_hex)) = pow_solver::solve_challenge(&salt, difficulty, initial_attempt_seed, num_threads) {
-        info!("Solution found!");
-        info!("\tAttempt: {}", successful_attempt);
-        info!("\tHash:    {}", solution_hash_hex);
-
-        // 3. Submit solution
-        info!("Submitting solution to /answer...");
-        let temp_auth_token = network_client::submit_pow_answer(&client, &base_url, &salt, &successful_attempt).await?;
-        info!("Auth token from /answer response: {}", temp_auth_token);
-
-        let final_clearance_token_to_report: String;
-
-        if args.check {
-            info!("--check flag is set. Submitting token from /answer to /check endpoint...");
-            let token_from_check = network_client::submit_final_check(&client, &base_url, &temp_auth_token).await?;
-            final_clearance_token_to_report = token_from_check;
-            if !suppress_logging { // This is direct output to user
-                println!("\nSuccessfully obtained sssg_clearance token (from /check): {}", final_clearance_token_to_report);
-            } else {
-                info!("Successfully obtained sssg_clearance token (from /check): {}", final_clearance_token_to_report);
-            }
+    // The target's origin first, then its mirrors with the same path, each with its own settings and cookie jar
+    let mut targets = vec![acquire::Target::new(base_url.clone(), settings.clone(), !common.no_cache)?];
+    for mirror in &settings.mirrors {
+        let url = acquire::mirror_url(&base_url, mirror)?;
+        let origin = url.origin().unicode_serialization();
+        if targets.iter().any(|t| t.origin == origin) {
+            continue;
+        }
+        let mirror_settings = common.settings(Some(&origin), &cli)?;
+        targets.push(acquire::Target::new(url, mirror_settings, !common.no_cache)?);
+    }
+
+    let options = acquire::AcquireOptions { fetch_page, max_rounds: args.max_rounds };
+    let acquire::Acquired { target, failed_mirrors, reused, nothing_to_clear, rounds, clearance, page } = acquire::acquire(targets, options).await?;
+    if !failed_mirrors.is_empty() {
+        if !suppress_logging {
+            println!("Using mirror: {}", target.url);
+        }
+        info!("Using mirror {} for {}", target.origin, args.url);
+    }
+
+    let mut run_report = report::RunReport {
+        url: target.url.to_string(),
+        origin: target.origin.clone(),
+        failed_mirrors,
+        reused_clearance: reused.is_some(),
+        nothing_to_clear,
+        rounds,
+        clearance: None,
+        requests: Vec::new(),
+        page: None,
+    };
+    if let Some(token) = &reused {
+        if !suppress_logging { // This is direct output to user
+            println!("\nSSSG Clearance still valid (from cookie jar): {}", token);
+        }
+        run_report.clearance = Some(report::ClearanceReport::reused(token, target.cached_expires_at));
+    }
+    if nothing_to_clear && !suppress_logging { // This is direct output to user
+        println!("\nNo challenge: {} served the page without one, nothing to clear", target.origin);
+    }
+    if let Some(clearance) = &clearance {
+        let expiry = match clearance.expires_at {
+            Some(at) => format!("expires {}", httpdate::fmt_http_date(at)),
+            None => "session cookie".to_string(),
+        };
+        let round = run_report.rounds.len();
+        if !suppress_logging { // This is direct output to user
+            println!("\nSSSG Clearance obtained (from {}, round {}, {}): {}", clearance.source, round, expiry, clearance.value());
         } else {
-            info!("Skipping /check endpoint by default. Using cookie from /answer response.");
-            // The cookie jar in `client` is automatically updated by reqwest
-            // The temp_auth_token is the value from the /answer JSON body.
-            final_clearance_token_to_report = temp_auth_token;
-            if !suppress_logging { // This is direct output to user
-                println!("\nSSSG Clearance obtained (from /answer): {}", final_clearance_token_to_report);
-            } else {
-                info!("SSSG Clearance obtained (from /answer): {}", final_clearance_token_to_report);
+            info!("SSSG Clearance obtained (from {}, round {}, {}): {}", clearance.source, round, expiry, clearance.value());
+        }
+        run_report.clearance = Some(report::ClearanceReport::from(clearance));
+    }
+    match target.cookie_store.lock() {
+        Ok(store) => export_cookies(&store, args.export_cookies, args.output.as_deref(), &target.url)?,
+        Err(e) => return Err(AppError::Boxed(e.to_string().into())),
+    }
+    if fetch_page {
+        run_report.page = page.map(|page| report::PageReport { status: page.status.as_u16(), body: page.body });
+    }
+
+    if json_output {
+        run_report.requests = target.session.timings().iter().map(report::RequestReport::from).collect();
+        print_json(&run_report)?;
+    } else if let Some(page) = &run_report.page {
+        // This println call is for the actual HTML output, so it is not suppressed by RUST_LOG.
+        println!("{}", page.body);
+    }
+
+    Ok(())
+}
+
+/// `check-clearance`: request the URL with a stored clearance and say whether it still works.
+async fn run_check_clearance(common: &CommonArgs, args: &CheckClearanceArgs) -> Result<(), AppError> {
+    let (url, origin) = origin_of(&args.url)?;
+    let settings = common.settings(Some(&origin), &common.cli_profile()?)?;
+
+    let (source, seed_cookies) = if let Some(value) = &args.clearance {
+        let cookie = cookie_store::Cookie::parse(format!("{}={}; Path=/", clearance::CLEARANCE_COOKIE, value), &url)
+            .map_err(|e| AppError::Boxed(format!("Invalid clearance value: {}", e).into()))?;
+        ("argument", vec![cookie.into_owned()])
+    } else if let Some(path) = &args.cookies {
+        ("file", cookies::import_file(path)?)
+    } else {
+        let cache_dir = cache::cache_dir(settings.cache_dir.as_deref());
+        let cookies = cache::load(&cache_dir, &origin)?.map(|entry| entry.cookies).unwrap_or_default();
+        ("cache", cookies)
+    };
+    info!("Loaded {} cookie(s) from the {}", seed_cookies.len(), source);
+    let cookie_store = Arc::new(CookieStoreMutex::new(cookies::store_from(seed_cookies)));
+    let session = acquire::build_session(&settings, &url, &cookie_store)?;
+
+    let check = network_client::check_clearance(&session, &cookie_store, &url).await?;
+    if common.json_output() {
+        print_json(&report::ClearanceCheckReport::new(&url, &origin, source, &check))?;
+    } else {
+        println!("Clearance: {}", check.value.as_deref().unwrap_or("(none)"));
+        println!("Status:    {} ({})", check.status, check.http_status);
+        match (&check.value, check.remaining()) {
+            (None, _) => {}
+            (Some(_), Some(remaining)) => println!("Expires:   in {:.0?}", remaining),
+            (Some(_), None) => println!("Expires:   unknown"),
+        }
+    }
+    if check.status != clearance::ClearanceStatus::Valid {
+        return Err(AppError::ClearanceRejected(check.status));
+    }
+    Ok(())
+}
+
+/// One URL kept fresh by `refresh`.
+struct RefreshEntry {
+    url: Url,
+    origin: String,
+    next: SystemTime,
+    /// The cookies after the last successful refresh, for `--export-cookies`.
+    cookies: Vec<cookie_store::Cookie<'static>>,
+}
+
+/// `refresh`: keep a clearance for each URL, solving a new one `--margin` ahead of expiry. Each
+/// new clearance goes to the cache and, with `--export-cookies`, all origins' cookies to one file.
+async fn run_refresh(common: &CommonArgs, args: &RefreshArgs) -> Result<(), AppError> {
+    if common.no_cache && args.export_cookies.is_none() {
+        return Err(AppError::Boxed("refresh with --no-cache needs --export-cookies to keep the clearances anywhere".into()));
+    }
+    let cli = args.clearance.cli_profile(common)?;
+    let margin = Duration::from_secs(args.margin);
+    let mut entries = Vec::new();
+    for url in &args.urls {
+        let (url, origin) = origin_of(url)?;
+        entries.push(RefreshEntry { url, origin, next: SystemTime::now(), cookies: Vec::new() });
+    }
+    // One `Cookie:` line only fits one origin
+    if args.export_cookies == Some(cookies::CookieFormat::Header) && entries.iter().any(|entry| entry.origin != entries[0].origin) {
+        return Err(AppError::Boxed("--export-cookies header holds the cookies of one origin; use another format to refresh several".into()));
+    }
+
+    loop {
+        let mut last_error = None;
+        let mut refreshed = false;
+        for entry in entries.iter_mut().filter(|entry| entry.next <= SystemTime::now()) {
+            match refresh_origin(common, &cli, entry, margin, Duration::from_secs(args.interval)).await {
+                Ok(()) => refreshed = true,
+                Err(e) => {
+                    entry.next = SystemTime::now() + Duration::from_secs(args.retry_after);
+                    warn!("Refreshing {} failed, retrying in {}s: {}", entry.origin, args.retry_after, e);
+                    last_error = Some(e);
+                }
             }
         }
+        if refreshed {
+            let store = cookies::store_from(entries.iter().flat_map(|entry| entry.cookies.iter().cloned()).collect());
+            // Every entry is on the same origin for `header`, which is the only format using the URL
+            export_cookies(&store, args.export_cookies, args.output.as_deref(), &entries[0].url)?;
+        }
+        if args.once {
+            return last_error.map_or(Ok(()), Err);
+        }
+
+        let next = entries.iter().map(|entry| entry.next).min().expect("at least one URL");
+        let wait = next.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
+        info!("Next refresh in {:.0?}", wait);
+        tokio::time::sleep(wait).await;
+    }
+}
 
-        if args.html {
-            info!("\nFetching final page HTML with current sssg_clearance cookie...");
-            // The client now has the sssg_clearance cookie in its jar
-            let final_html_content = network_client::fetch_page_html_with_cookies(&client, &args.url).await?;
-            // This println call is for the actual HTML output, so it is not suppressed by RUST_LOG.
-            println!("{}", final_html_content);
+/// Gets `entry` a clearance that lasts beyond `margin`, keeping the stored one if it does and still
+/// works, and schedules the next refresh.
+async fn refresh_origin(common: &CommonArgs, cli: &config::Profile, entry: &mut RefreshEntry, margin: Duration, interval: Duration) -> Result<(), AppError> {
+    // Settings are reloaded every time, so edits to the config file apply to the next refresh
+    let settings = common.settings(Some(&entry.origin), cli)?;
+    let mut target = if common.no_cache && !entry.cookies.is_empty() {
+        // Without the cache, the previous refresh's cookies are the only record of the clearance
+        acquire::Target::with_cookies(entry.url.clone(), settings, entry.cookies.clone(), false)?
+    } else {
+        acquire::Target::new(entry.url.clone(), settings, !common.no_cache)?
+    };
+    if let Some(expires_at) = target.cached_expires_at {
+        let remaining = expires_at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO);
+        if remaining <= margin {
+            info!("Clearance for {} expires in {:.0?}, replacing it", entry.origin, remaining);
+            target.forget_cached_clearance();
         }
+    }
+
+    let acquired = acquire::acquire(vec![target], acquire::AcquireOptions::default()).await?;
+    let (expires_at, clearance_report) = match (&acquired.reused, &acquired.clearance) {
+        (Some(token), _) => (acquired.target.cached_expires_at, report::ClearanceReport::reused(token, acquired.target.cached_expires_at)),
+        (None, Some(clearance)) => (clearance.expires_at, report::ClearanceReport::from(clearance)),
+        (None, None) if acquired.nothing_to_clear => return Err(AppError::Boxed(format!("{} serves the page without a challenge; there is no clearance to keep", entry.origin).into())),
+        (None, None) => return Err(AppError::Boxed("no clearance obtained".into())),
+    };
+    let now = SystemTime::now();
+    entry.next = clearance::refresh_time(expires_at, now, margin, interval);
+    entry.cookies = match acquired.target.cookie_store.lock() {
+        Ok(store) => store.iter_unexpired().cloned().collect(),
+        Err(e) => return Err(AppError::Boxed(e.to_string().into())),
+    };
+
+    let refreshed = acquired.reused.is_none();
+    if common.json_output() {
+        let line = report::RefreshReport::new(&entry.url, &entry.origin, refreshed, clearance_report, entry.next);
+        println!("{}", serde_json::to_string(&line).map_err(|e| AppError::Boxed(Box::new(e)))?);
+    } else {
+        let expiry = match expires_at {
+            Some(at) => format!("expires {}", httpdate::fmt_http_date(at)),
+            None => "session cookie".to_string(),
+        };
+        println!("{} {} ({}): {}; next refresh {}", if refreshed { "Refreshed" } else { "Kept" }, entry.origin, expiry, clearance_report.value, httpdate::fmt_http_date(entry.next));
+    }
+    Ok(())
+}
+
+/// Loads the config file, if there is one.
+fn load_config(common: &CommonArgs) -> Result<config::Config, AppError> {
+    let (config, path) = config::Config::load(common.config.as_deref())?;
+    if let Some(path) = &path {
+        info!("Using config file {}", path.display());
+    }
+    Ok(config)
+}
+
+/// The clearance service behind `serve`, `proxy` and `reverse-proxy`, with settings from `config`
+/// and the command line, and a listener on `listen` for it.
+async fn start_service(common: &CommonArgs, args: &ServiceArgs, config: config::Config, listen: std::net::SocketAddr) -> Result<(Arc<server::ClearanceService>, tokio::net::TcpListener), AppError> {
+    let settings = server::SettingsSource { config, profile: common.profile.clone(), overrides: args.clearance.cli_profile(common)? };
+    let options = server::ServiceOptions {
+        margin: Duration::from_secs(args.margin),
+        session_ttl: Duration::from_secs(args.session_ttl),
+        use_cache: !common.no_cache,
+    };
+    let service = Arc::new(server::ClearanceService::new(settings, options));
+
+    if !listen.ip().is_loopback() {
+        warn!("Listening on {}, which is not a loopback address: anyone who can reach it can use your clearances", listen);
+    }
+    let listener = tokio::net::TcpListener::bind(listen).await?;
+    Ok((service, listener))
+}
+
+/// `serve`: answer clearance requests over HTTP until interrupted.
+async fn run_serve(common: &CommonArgs, args: &ServeArgs) -> Result<(), AppError> {
+    let (service, listener) = start_service(common, &args.service, load_config(common)?, args.listen).await?;
+    println!("Listening on http://{}", listener.local_addr()?);
+    axum::serve(listener, server::router(service))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("Shutting down");
+        })
+        .await?;
+    Ok(())
+}
+
+/// `proxy`: relay requests, intercepting the configured origins, until interrupted.
+async fn run_proxy(common: &CommonArgs, args: &ProxyArgs) -> Result<(), AppError> {
+    let config = load_config(common)?;
+    let mut origins = args.origins.clone();
+    origins.extend(config.profiles.values().flat_map(|profile| profile.origins.iter().cloned()));
+    if origins.is_empty() {
+        return Err(AppError::Boxed("No origins to intercept: pass --origin or list origins in a config file profile".into()));
+    }
+
+    let (default_cert, default_key) = mitm::default_paths();
+    let ca_cert = args.ca_cert.clone().unwrap_or(default_cert);
+    let ca_key = args.ca_key.clone().unwrap_or(default_key);
+    let (ca, created) = mitm::CertificateAuthority::load_or_create(&ca_cert, &ca_key).map_err(|e| AppError::Boxed(e.into()))?;
+    if created {
+        println!("Generated a CA to intercept HTTPS with; have your clients trust {}", ca_cert.display());
+    }
+
+    let (service, listener) = start_service(common, &args.service, config, args.listen).await?;
+    let proxy = Arc::new(forward_proxy::ForwardProxy::new(service, ca, &origins));
+    println!("Proxy listening on http://{} (CA certificate {}), intercepting {}", listener.local_addr()?, ca_cert.display(), origins.join(", "));
+    tokio::select! {
+        result = proxy.serve(listener) => result?,
+        _ = tokio::signal::ctrl_c() => info!("Shutting down"),
+    }
+    Ok(())
+}
 
+/// `reverse-proxy`: relay every request to `--upstream`, until interrupted.
+async fn run_reverse_proxy(common: &CommonArgs, args: &ReverseProxyArgs) -> Result<(), AppError> {
+    if !matches!(args.upstream.scheme(), "http" | "https") {
+        return Err(AppError::Boxed(format!("Unsupported upstream URL scheme: {}", args.upstream.scheme()).into()));
+    }
+    if args.upstream.path() != "/" || args.upstream.query().is_some() {
+        warn!("Only the origin of {} is used; requests keep their own paths", args.upstream);
+    }
+    let (service, listener) = start_service(common, &args.service, load_config(common)?, args.listen).await?;
+    let local = Url::parse(&format!("http://{}", listener.local_addr()?))?;
+    let proxy = Arc::new(reverse_proxy::ReverseProxy::new(service, &args.upstream, &local));
+    println!("Serving {} on {}", args.upstream.origin().unicode_serialization(), local.origin().unicode_serialization());
+    axum::serve(listener, proxy.router())
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+            info!("Shutting down");
+        })
+        .await?;
+    Ok(())
+}
+
+/// `solve`: solve a challenge without touching the network.
+fn run_solve(common: &CommonArgs, args: &SolveArgs) -> Result<(), AppError> {
+    let mut cli = common.cli_profile()?;
+    args.solver.apply(&mut cli);
+    let settings = common.settings(None, &cli)?;
+    let solution = acquire::solve(&settings, &args.salt, args.difficulty, None)?;
+    if common.json_output() {
+        return print_json(&report::SolveReport::new(&solution, settings.threads()));
+    }
+    println!("Attempt: {}", solution.attempt);
+    println!("Hash:    {}", solution.hash);
+    println!("Hashes:  {} in {:.2?} ({:.0} H/s)", solution.hashes, solution.duration, solution.hash_rate());
+    Ok(())
+}
+
+/// `verify`: check that an attempt meets a difficulty for a salt.
+fn run_verify(common: &CommonArgs, args: &VerifyArgs) -> Result<(), AppError> {
+    let (hash, leading_zeros) = pow_solver::check_attempt(&args.salt, &args.attempt);
+    let valid = leading_zeros >= args.difficulty;
+    if common.json_output() {
+        print_json(&report::VerifyReport {
+            salt: args.salt.clone(),
+            attempt: args.attempt.clone(),
+            hash,
+            leading_zeros,
+            difficulty: args.difficulty,
+            valid,
+        })?;
     } else {
-        warn!("No solution found for the PoW challenge.");
-        // Consider returning an error here if no solution is an actual failure condition
-        // For now, it just prints and exits successfully.
+        println!("Hash:          {}", hash);
+        println!("Leading zeros: {} (difficulty {})", leading_zeros, args.difficulty);
+        println!("{}", if valid { "Valid" } else { "Invalid" });
+    }
+    if !valid {
+        return Err(AppError::InvalidSolution { leading_zeros, difficulty: args.difficulty });
+    }
+    Ok(())
+}
+
+/// `bench`: solve `--rounds` synthetic challenges and report the hash rate.
+fn run_bench(common: &CommonArgs, args: &BenchArgs) -> Result<(), AppError> {
+    let mut cli = common.cli_profile()?;
+    args.solver.apply(&mut cli);
+    let settings = common.settings(None, &cli)?;
+    let threads = settings.threads();
+    let mut solutions = Vec::new();
+    for round in 1..=args.rounds {
+        // Random salts keep rounds independent of each other and of earlier runs
+        let salt = format!("{:016x}", utils::generate_initial_attempt_nonce_seed() as u64);
+        let solution = acquire::solve(&settings, &salt, args.difficulty, None)?;
+        if !common.json_output() {
+            println!("Round {}/{}: {} hashes in {:.2?} ({:.0} H/s)", round, args.rounds, solution.hashes, solution.duration, solution.hash_rate());
+        }
+        solutions.push(solution);
+    }
+    let bench_report = report::BenchReport::new(args.difficulty, threads, &solutions);
+    if common.json_output() {
+        return print_json(&bench_report);
+    }
+    println!("Difficulty {} on {} threads: {} hashes in {:.0} ms, {:.0} H/s", bench_report.difficulty, threads, bench_report.total_hashes, bench_report.total_ms, bench_report.hash_rate);
+    Ok(())
+}
+
+/// `cookies`: inspect and manage the clearance cache.
+fn run_cookies(common: &CommonArgs, command: &CookiesCommand) -> Result<(), AppError> {
+    // A profile may keep its origins' cookies elsewhere, so the directory depends on the URL
+    let cache_dir_for = |origin: Option<&str>| -> Result<PathBuf, AppError> {
+        let settings = common.settings(origin, &common.cli_profile()?)?;
+        Ok(cache::cache_dir(settings.cache_dir.as_deref()))
+    };
+    match command {
+        CookiesCommand::List => {
+            let origins: Vec<report::CachedOriginReport> = cache::list(&cache_dir_for(None)?)?.iter().map(report::CachedOriginReport::from).collect();
+            if common.json_output() {
+                return print_json(&origins);
+            }
+            for origin in &origins {
+                let clearance = match (origin.clearance_valid, origin.clearance_expires) {
+                    (false, _) => "no valid clearance".to_string(),
+                    (true, Some(at)) => format!("clearance expires {}", httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(at))),
+                    (true, None) => "clearance until session end".to_string(),
+                };
+                println!("{}\t{} cookie(s)\t{}", origin.origin, origin.cookies, clearance);
+            }
+        }
+        CookiesCommand::Show { url, format } => {
+            let (base_url, origin) = origin_of(url)?;
+            let entry = cache::load(&cache_dir_for(Some(&origin))?, &origin)?.ok_or_else(|| AppError::Boxed(format!("No cached cookies for {}", origin).into()))?;
+            print!("{}", cookies::export(&cookies::store_from(entry.cookies), *format, &base_url));
+        }
+        CookiesCommand::Import { url, file } => {
+            let (_, origin) = origin_of(url)?;
+            let cache_dir = cache_dir_for(Some(&origin))?;
+            let (mut merged, obtained_at) = match cache::load(&cache_dir, &origin)? {
+                Some(entry) => (entry.cookies, entry.obtained_at),
+                None => (Vec::new(), None),
+            };
+            let imported = cookies::import_file(file)?;
+            let count = imported.len();
+            // The cached clearance's age still holds unless the file brings a clearance of its own
+            let replaces_clearance = imported.iter().any(|c| c.name() == clearance::CLEARANCE_COOKIE);
+            merged.extend(imported);
+            let mut entry = cache::CacheEntry::imported(&origin, &cookies::store_from(merged));
+            if !replaces_clearance {
+                entry.obtained_at = obtained_at;
+            }
+            let path = cache::save(&cache_dir, &entry)?;
+            info!("Imported {} cookie(s) for {} into {}", count, origin, path.display());
+        }
+        CookiesCommand::Remove { urls } => {
+            for url in urls {
+                let (_, origin) = origin_of(url)?;
+                if cache::remove(&cache_dir_for(Some(&origin))?, &origin)? {
+                    info!("Removed cached cookies for {}", origin);
+                } else {
+                    warn!("No cached cookies for {}", origin);
+                }
+            }
+        }
+        CookiesCommand::Purge => {
+            let cache_dir = cache_dir_for(None)?;
+            let removed = cache::purge(&cache_dir)?;
+            info!("Removed {} cache file(s) from {}", removed, cache_dir.display());
+        }
     }
+    Ok(())
+}
 
+/// `config`: show where configuration comes from and what is in effect.
+fn run_config(common: &CommonArgs, command: &ConfigCommand) -> Result<(), AppError> {
+    match command {
+        ConfigCommand::Show { url } => {
+            let origin = match url {
+                Some(url) => Some(origin_of(url)?.1),
+                None => None,
+            };
+            let (config, path) = config::Config::load(common.config.as_deref())?;
+            let (settings, profile) = config.effective(origin.as_deref(), common.profile.as_deref(), &common.cli_profile()?)?;
+            if common.json_output() {
+                return print_json(&serde_json::json!({
+                    "file": path,
+                    "origin": origin,
+                    "profile": profile,
+                    "settings": settings,
+                }));
+            }
+            let toml = toml::to_string_pretty(&settings).map_err(|e| AppError::Boxed(Box::new(e)))?;
+            println!("# config file: {}", path.map(|p| p.display().to_string()).unwrap_or_else(|| "(none)".to_string()));
+            println!("# profile: {}", profile.as_deref().unwrap_or("(none)"));
+            print!("{}", toml);
+        }
+        ConfigCommand::Paths => {
+            for path in config::config_paths() {
+                println!("{}", path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parses a URL and returns it with its origin, the key of the clearance cache.
+fn origin_of(url: &str) -> Result<(Url, String), AppError> {
+    let url = Url::parse(url)?;
+    let origin = url.origin().unicode_serialization();
+    Ok((url, origin))
+}
+
+fn print_json<T: serde::Serialize>(value: &T) -> Result<(), AppError> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| AppError::Boxed(Box::new(e)))?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Writes the cookie jar in `format` (from `--export-cookies`), if any, to `output` or stdout.
+fn export_cookies(store: &cookie_store::CookieStore, format: Option<cookies::CookieFormat>, output: Option<&Path>, base_url: &Url) -> Result<(), AppError> {
+    let Some(format) = format else {
+        return Ok(());
+    };
+    let exported = cookies::export(store, format, base_url);
+    match output {
+        Some(path) => {
+            cookies::write_file(path, &exported)?;
+            info!("Cookies exported as {:?} to {}", format, path.display());
+        }
+        None => print!("{}", exported),
+    }
     Ok(())
 }
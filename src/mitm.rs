@@ -0,0 +1,163 @@
+//! The certificate authority `proxy` intercepts HTTPS with. It is generated once and kept on disk,
+//! so a client only has to be told to trust it once, and issues a certificate for each intercepted
+//! host as the proxy first sees it.
+
+use crate::cookies;
+use log::{debug, info};
+use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair, KeyUsagePurpose};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::{self, ServerConfig};
+
+/// The common name of the CA certificate. Its organizational unit is a fingerprint of its key, so
+/// that CAs generated on different machines are told apart, and so the copy signed with can be
+/// rebuilt from the key alone. Only CAs generated here can be loaded.
+pub const CA_NAME: &str = "kiwifarms-captchabuster interception CA";
+
+/// How long issued host certificates are valid. Clients refuse much longer-lived ones.
+const HOST_CERT_DAYS: i64 = 365;
+
+#[derive(Debug)]
+pub enum MitmError {
+    Io { path: PathBuf, error: std::io::Error },
+    /// A certificate or key cannot be generated or parsed.
+    Certificate(String),
+    Tls(rustls::Error),
+}
+
+impl std::fmt::Display for MitmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MitmError::Io { path, error } => write!(f, "Cannot access {}: {}", path.display(), error),
+            MitmError::Certificate(detail) => write!(f, "Invalid interception CA: {}", detail),
+            MitmError::Tls(e) => write!(f, "TLS error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MitmError {}
+
+impl From<rcgen::Error> for MitmError {
+    fn from(err: rcgen::Error) -> MitmError {
+        MitmError::Certificate(err.to_string())
+    }
+}
+
+impl From<rustls::Error> for MitmError {
+    fn from(err: rustls::Error) -> MitmError {
+        MitmError::Tls(err)
+    }
+}
+
+/// Where the CA certificate and key are kept unless given: the platform data directory.
+pub fn default_paths() -> (PathBuf, PathBuf) {
+    let dir = dirs::data_dir().unwrap_or_else(std::env::temp_dir).join(env!("CARGO_PKG_NAME"));
+    (dir.join("ca.pem"), dir.join("ca-key.pem"))
+}
+
+/// The CA and the server configurations issued from it so far, per host.
+pub struct CertificateAuthority {
+    cert_pem: String,
+    cert_der: CertificateDer<'static>,
+    key: KeyPair,
+    /// The CA rebuilt from `key`, to sign with. It has the same name and key as `cert_der`, so
+    /// clients trusting that accept what it signs.
+    issuer: rcgen::Certificate,
+    /// One key for every host certificate; generating a key per host would only cost time.
+    host_key: KeyPair,
+    configs: Mutex<HashMap<String, Arc<ServerConfig>>>,
+}
+
+impl CertificateAuthority {
+    /// A new CA that only lives in memory.
+    pub fn generate() -> Result<CertificateAuthority, MitmError> {
+        let key = KeyPair::generate()?;
+        let now = OffsetDateTime::now_utc();
+        let mut params = ca_params(&key);
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(10 * 365);
+        let cert = params.self_signed(&key)?;
+        CertificateAuthority::from_parts(cert.pem(), key)
+    }
+
+    /// Loads the CA from `cert_path` and `key_path`, generating and saving one if neither exists.
+    /// Returns whether it was generated, in which case clients have yet to be told to trust it.
+    pub fn load_or_create(cert_path: &Path, key_path: &Path) -> Result<(CertificateAuthority, bool), MitmError> {
+        if !cert_path.exists() && !key_path.exists() {
+            let ca = CertificateAuthority::generate()?;
+            if let Some(dir) = cert_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                std::fs::create_dir_all(dir).map_err(|error| MitmError::Io { path: dir.to_path_buf(), error })?;
+            }
+            // The temporary file behind write_file is only readable by its owner, which the key needs
+            cookies::write_file(key_path, &ca.key.serialize_pem()).map_err(|error| MitmError::Io { path: key_path.to_path_buf(), error })?;
+            cookies::write_file(cert_path, &ca.cert_pem).map_err(|error| MitmError::Io { path: cert_path.to_path_buf(), error })?;
+            info!("Generated interception CA {}", cert_path.display());
+            return Ok((ca, true));
+        }
+        let read = |path: &Path| std::fs::read_to_string(path).map_err(|error| MitmError::Io { path: path.to_path_buf(), error });
+        let (cert_pem, key_pem) = (read(cert_path)?, read(key_path)?);
+        let key = KeyPair::from_pem(&key_pem)?;
+        Ok((CertificateAuthority::from_parts(cert_pem, key)?, false))
+    }
+
+    fn from_parts(cert_pem: String, key: KeyPair) -> Result<CertificateAuthority, MitmError> {
+        let cert_der = CertificateDer::from_pem_slice(cert_pem.as_bytes()).map_err(|e| MitmError::Certificate(e.to_string()))?;
+        if !cert_der.windows(key.public_key_der().len()).any(|w| w == key.public_key_der()) {
+            return Err(MitmError::Certificate("the key does not belong to the certificate".to_string()));
+        }
+        let issuer = ca_params(&key).self_signed(&key)?;
+        Ok(CertificateAuthority { cert_pem, cert_der, key, issuer, host_key: KeyPair::generate()?, configs: Mutex::new(HashMap::new()) })
+    }
+
+    /// The CA certificate, PEM-encoded, for clients to trust.
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// A TLS server configuration presenting a certificate for `host` (a name or an IP address).
+    pub fn server_config(&self, host: &str) -> Result<Arc<ServerConfig>, MitmError> {
+        let host = host.to_ascii_lowercase();
+        if let Some(config) = self.configs.lock().ok().and_then(|configs| configs.get(&host).cloned()) {
+            return Ok(config);
+        }
+
+        let now = OffsetDateTime::now_utc();
+        let mut params = CertificateParams::new(vec![host.clone()])?;
+        params.distinguished_name.push(DnType::CommonName, host.as_str());
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(HOST_CERT_DAYS);
+        let cert = params.signed_by(&self.host_key, &self.issuer, &self.key)?;
+        debug!("[MITM] Issued a certificate for {}", host);
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(self.host_key.serialize_der()));
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.der().clone(), self.cert_der.clone()], key)?;
+        // Requests are relayed over HTTP/1.1 only
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let config = Arc::new(config);
+        if let Ok(mut configs) = self.configs.lock() {
+            configs.insert(host, Arc::clone(&config));
+        }
+        Ok(config)
+    }
+}
+
+/// The name and constraints of the CA with `key`. Validity is left out: it only matters in the saved certificate,
+/// not in the copy rebuilt to sign with.
+fn ca_params(key: &KeyPair) -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.distinguished_name.push(DnType::CommonName, CA_NAME);
+    params.distinguished_name.push(DnType::OrganizationalUnitName, hex::encode(&Sha256::digest(key.public_key_der())[..8]));
+    params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign, KeyUsagePurpose::DigitalSignature];
+    params
+}
@@ -1,9 +1,19 @@
-use reqwest::{Client, Error as ReqwestError, StatusCode};
-use std::time::Instant;
+use reqwest::{Certificate, Client, Error as ReqwestError, Identity, RequestBuilder, Response, StatusCode};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_LANGUAGE, CACHE_CONTROL, CONNECTION, CONTENT_TYPE, PRAGMA, RETRY_AFTER, USER_AGENT};
+use reqwest_cookie_store::CookieStoreMutex;
+use once_cell::sync::Lazy;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 use serde::{Deserialize};
 use log::{debug, info, warn};
-use serde_json; // Added for from_str
+use rand::Rng;
 use url::Url;
+use crate::clearance::{Clearance, ClearanceSource, ClearanceStatus, CLEARANCE_COOKIE};
+use crate::html_parser;
 
 #[derive(Debug, Deserialize)]
 struct AnswerResponse {
@@ -15,10 +25,772 @@ struct CheckResponse {
     auth: String,
 }
 
+/// Paths and form field names used when talking to the SSSG API.
+/// Paths may be absolute (`/.sssg/api/answer`) or full URLs; they are resolved against the target URL.
+#[derive(Debug, Clone)]
+pub struct SssgEndpoints {
+    pub answer_path: String,
+    pub check_path: String,
+    pub salt_field: String,
+    pub attempt_field: String,
+    pub token_field: String,
+}
+
+impl Default for SssgEndpoints {
+    fn default() -> Self {
+        SssgEndpoints {
+            answer_path: "/.sssg/api/answer".to_string(),
+            check_path: "/.sssg/api/check".to_string(),
+            salt_field: "a".to_string(),
+            attempt_field: "b".to_string(),
+            token_field: "f".to_string(),
+        }
+    }
+}
+
+/// Partial endpoint configuration, as discovered from a challenge page or given by the user.
+/// Only the fields that are `Some` replace the current value when merged.
+#[derive(Debug, Clone, Default)]
+pub struct EndpointOverrides {
+    pub answer_path: Option<String>,
+    pub check_path: Option<String>,
+    pub salt_field: Option<String>,
+    pub attempt_field: Option<String>,
+    pub token_field: Option<String>,
+}
+
+impl EndpointOverrides {
+    /// Returns true if both endpoint paths are known.
+    pub fn has_paths(&self) -> bool {
+        self.answer_path.is_some() && self.check_path.is_some()
+    }
+
+    /// Fills the fields that are still `None` from `other`.
+    pub fn fill_from(&mut self, other: EndpointOverrides) {
+        self.answer_path = self.answer_path.take().or(other.answer_path);
+        self.check_path = self.check_path.take().or(other.check_path);
+        self.salt_field = self.salt_field.take().or(other.salt_field);
+        self.attempt_field = self.attempt_field.take().or(other.attempt_field);
+        self.token_field = self.token_field.take().or(other.token_field);
+    }
+
+    /// Drops the paths that do not resolve to `base_url`'s origin, with the field names found next
+    /// to them, so that page content cannot send the solution somewhere else.
+    pub fn same_origin(mut self, base_url: &Url) -> EndpointOverrides {
+        let foreign = |path: &Option<String>| {
+            path.as_ref().is_some_and(|path| base_url.join(path).map_or(true, |url| url.origin() != base_url.origin()))
+        };
+        if foreign(&self.answer_path) {
+            warn!("Ignoring discovered /answer endpoint on another origin: {}", self.answer_path.as_deref().unwrap_or_default());
+            self.answer_path = None;
+            self.salt_field = None;
+            self.attempt_field = None;
+        }
+        if foreign(&self.check_path) {
+            warn!("Ignoring discovered /check endpoint on another origin: {}", self.check_path.as_deref().unwrap_or_default());
+            self.check_path = None;
+            self.token_field = None;
+        }
+        self
+    }
+}
+
+impl SssgEndpoints {
+    /// Applies the `Some` fields of `overrides` on top of the current values.
+    pub fn merge(&mut self, overrides: &EndpointOverrides) {
+        if let Some(v) = &overrides.answer_path { self.answer_path = v.clone(); }
+        if let Some(v) = &overrides.check_path { self.check_path = v.clone(); }
+        if let Some(v) = &overrides.salt_field { self.salt_field = v.clone(); }
+        if let Some(v) = &overrides.attempt_field { self.attempt_field = v.clone(); }
+        if let Some(v) = &overrides.token_field { self.token_field = v.clone(); }
+    }
+
+    /// Resolves the /answer endpoint against the target URL.
+    pub fn answer_url(&self, base_url: &Url) -> Result<Url, url::ParseError> {
+        base_url.join(&self.answer_path)
+    }
+
+    /// Resolves the /check endpoint against the target URL.
+    pub fn check_url(&self, base_url: &Url) -> Result<Url, url::ParseError> {
+        base_url.join(&self.check_path)
+    }
+}
+
+/// How often and how patiently a request is retried on transient failures
+/// (timeouts, refused or dropped connections, 429 and 5xx gateway/availability responses).
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay cap for the first retry; doubles with every further attempt.
+    pub base_delay: Duration,
+    /// Upper bound for any single delay, including one requested via `Retry-After`.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Exponential backoff with full jitter for the given (1-based) failed attempt.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exp = self.base_delay.saturating_mul(1u32 << attempt.saturating_sub(1).min(16));
+        let cap = exp.min(self.max_delay);
+        cap.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+fn is_retryable_status(status: StatusCode) -> bool {
+    matches!(status,
+        StatusCode::TOO_MANY_REQUESTS
+        | StatusCode::INTERNAL_SERVER_ERROR
+        | StatusCode::BAD_GATEWAY
+        | StatusCode::SERVICE_UNAVAILABLE
+        | StatusCode::GATEWAY_TIMEOUT)
+}
+
+/// Whether `err` is worth retrying: a timeout, or a connection that was refused, reset or closed
+/// early. Certificate and proxy failures, bad requests and the like would only fail again.
+pub fn is_transient_error(err: &ReqwestError) -> bool {
+    if err.is_timeout() {
+        return true;
+    }
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = source {
+        if let Some(io) = e.downcast_ref::<std::io::Error>() {
+            if matches!(io.kind(),
+                std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::TimedOut) {
+                return true;
+            }
+        }
+        if e.downcast_ref::<hyper::Error>().is_some_and(|e| e.is_incomplete_message() || e.is_canceled()) {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+/// Parses a `Retry-After` value given either as delay-seconds or as an HTTP date, relative to `now`.
+pub fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(now).unwrap_or(Duration::ZERO))
+}
+
+fn retry_after(response: &Response) -> Option<Duration> {
+    parse_retry_after(response.headers().get(RETRY_AFTER)?.to_str().ok()?, SystemTime::now())
+}
+
+static BASE_HEADERS: Lazy<HeaderMap> = Lazy::new(|| {
+    let mut headers = HeaderMap::new();
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("en-US,en;q=0.5"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    headers.insert(CONNECTION, HeaderValue::from_static("keep-alive"));
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/x-www-form-urlencoded"));
+    headers.insert(PRAGMA, HeaderValue::from_static("no-cache"));
+    headers.insert(HeaderName::from_static("sec-fetch-dest"), HeaderValue::from_static("empty"));
+    headers.insert(HeaderName::from_static("sec-fetch-mode"), HeaderValue::from_static("cors"));
+    headers.insert(HeaderName::from_static("sec-fetch-site"), HeaderValue::from_static("same-origin"));
+    headers.insert(HeaderName::from_static("sec-gpc"), HeaderValue::from_static("1"));
+    headers.insert(USER_AGENT, HeaderValue::from_static("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/135.0.0.0 Safari/537.36"));
+    headers.insert(HeaderName::from_static("sec-ch-ua"), HeaderValue::from_static("\"Google Chrome\";v=\"135\", \"Not-A.Brand\";v=\"8\", \"Chromium\";v=\"135\""));
+    headers.insert(HeaderName::from_static("sec-ch-ua-mobile"), HeaderValue::from_static("?0"));
+    headers.insert(HeaderName::from_static("sec-ch-ua-platform"), HeaderValue::from_static("\"Windows\""));
+    headers
+});
+
+/// The browser-like headers sent with page and script fetches.
+pub fn default_page_headers() -> HeaderMap {
+    let mut headers = BASE_HEADERS.clone();
+    headers.remove(CONTENT_TYPE); // GETs carry no body
+    headers
+}
+
+/// The browser-like headers sent with the /answer and /check POSTs.
+pub fn default_api_headers() -> HeaderMap {
+    BASE_HEADERS.clone()
+}
+
+/// Applies `overrides` to `headers`: each entry replaces the header of the same name, and an
+/// empty value removes it.
+pub fn apply_header_overrides(headers: &mut HeaderMap, overrides: &BTreeMap<String, String>) -> Result<(), NetworkError> {
+    for (name, value) in overrides {
+        let header_name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|e| NetworkError::InvalidHeader(format!("{:?}: {}", name, e)))?;
+        if value.is_empty() {
+            headers.remove(&header_name);
+            continue;
+        }
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|e| NetworkError::InvalidHeader(format!("value of {}: {}", header_name, e)))?;
+        headers.insert(header_name, header_value);
+    }
+    Ok(())
+}
+
+/// Which limit a request ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Establishing the connection (TCP, proxy and TLS handshakes).
+    Connect,
+    /// Waiting for the next bytes of a response.
+    Read,
+    /// One HTTP step as a whole, including retries and reading the body.
+    Operation,
+    /// The end-to-end deadline of the session.
+    Deadline,
+}
+
+impl std::fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TimeoutPhase::Connect => write!(f, "connect"),
+            TimeoutPhase::Read => write!(f, "read"),
+            TimeoutPhase::Operation => write!(f, "operation"),
+            TimeoutPhase::Deadline => write!(f, "deadline"),
+        }
+    }
+}
+
+/// Time limits for HTTP steps. `None` disables a limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Per connection attempt.
+    pub connect: Option<Duration>,
+    /// Longest wait for more response data.
+    pub read: Option<Duration>,
+    /// Per step (e.g. "submit to /answer"), across all of its attempts.
+    pub operation: Option<Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            connect: Some(Duration::from_secs(10)),
+            read: Some(Duration::from_secs(30)),
+            operation: Some(Duration::from_secs(120)),
+        }
+    }
+}
+
+/// How long one request took, across all of its attempts.
+#[derive(Debug, Clone)]
+pub struct RequestTiming {
+    pub step: String,
+    pub method: String,
+    pub url: String,
+    /// Status of the last attempt; `None` if it failed without a response.
+    pub status: Option<u16>,
+    pub attempts: u32,
+    pub duration: Duration,
+}
+
+/// A client together with the state shared by every request made through it:
+/// the retry policy, the headers for each kind of request and a record of request timings.
+#[derive(Debug)]
+pub struct Session {
+    pub client: Client,
+    pub retry: RetryPolicy,
+    /// Headers for page and script GETs.
+    pub page_headers: HeaderMap,
+    /// Headers for the /answer and /check POSTs.
+    pub api_headers: HeaderMap,
+    pub timeouts: Timeouts,
+    /// When the whole job (e.g. obtaining clearance) must be done by.
+    pub deadline: Option<Instant>,
+    timings: Mutex<Vec<RequestTiming>>,
+}
+
+impl Session {
+    /// Wraps an existing client, sending the default header sets.
+    pub fn new(client: Client, retry: RetryPolicy) -> Session {
+        Session {
+            client,
+            retry,
+            page_headers: default_page_headers(),
+            api_headers: default_api_headers(),
+            timeouts: Timeouts::default(),
+            deadline: None,
+            timings: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn builder() -> SessionBuilder {
+        SessionBuilder::default()
+    }
+
+    /// Time left until the end-to-end deadline, if there is one.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs one HTTP step (all attempts and the body read) within the operation timeout and the deadline,
+    /// whichever ends first. `context` names the step in the error.
+    async fn bounded<T>(&self, context: &str, step: impl Future<Output = Result<T, NetworkError>>) -> Result<T, NetworkError> {
+        let limit = match (self.timeouts.operation, self.remaining()) {
+            (Some(operation), Some(remaining)) if operation < remaining => Some((operation, TimeoutPhase::Operation)),
+            (_, Some(remaining)) => Some((remaining, TimeoutPhase::Deadline)),
+            (Some(operation), None) => Some((operation, TimeoutPhase::Operation)),
+            (None, None) => None,
+        };
+        let Some((limit, phase)) = limit else {
+            return step.await;
+        };
+        if limit.is_zero() {
+            return Err(NetworkError::Timeout { phase, context: context.to_string() });
+        }
+        match tokio::time::timeout(limit, step).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!("[TIMEOUT] {} hit the {} timeout after {:.2?}", context, phase, limit);
+                Err(NetworkError::Timeout { phase, context: context.to_string() })
+            }
+        }
+    }
+
+    fn get(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.client.get(url).headers(self.page_headers.clone())
+    }
+
+    fn post(&self, url: impl reqwest::IntoUrl) -> RequestBuilder {
+        self.client.post(url).headers(self.api_headers.clone())
+    }
+
+    /// Timings of every request made so far, oldest first.
+    pub fn timings(&self) -> Vec<RequestTiming> {
+        self.timings.lock().map(|t| t.clone()).unwrap_or_default()
+    }
+
+    fn record_timing(&self, timing: RequestTiming) {
+        if let Ok(mut timings) = self.timings.lock() {
+            timings.push(timing);
+        }
+    }
+}
+
+/// Builds a `Session` and the client behind it.
+#[derive(Debug)]
+pub struct SessionBuilder {
+    page_headers: HeaderMap,
+    api_headers: HeaderMap,
+    cookie_store: Option<Arc<CookieStoreMutex>>,
+    retry: RetryPolicy,
+    proxy: Option<String>,
+    no_proxy: Option<String>,
+    timeouts: Timeouts,
+    deadline: Option<Duration>,
+    resolve: BTreeMap<String, Vec<IpAddr>>,
+    dns_resolver: Option<SharedResolver>,
+    root_certificates: Vec<Certificate>,
+    identity: Option<Identity>,
+    accept_invalid_certs: bool,
+    relay: bool,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        SessionBuilder {
+            page_headers: default_page_headers(),
+            api_headers: default_api_headers(),
+            cookie_store: None,
+            retry: RetryPolicy::default(),
+            proxy: None,
+            no_proxy: None,
+            timeouts: Timeouts::default(),
+            deadline: None,
+            resolve: BTreeMap::new(),
+            dns_resolver: None,
+            root_certificates: Vec::new(),
+            identity: None,
+            accept_invalid_certs: false,
+            relay: false,
+        }
+    }
+}
+
+impl SessionBuilder {
+    /// Replaces the headers sent with page and script GETs.
+    pub fn page_headers(mut self, headers: HeaderMap) -> Self {
+        self.page_headers = headers;
+        self
+    }
+
+    /// Replaces the headers sent with the /answer and /check POSTs.
+    pub fn api_headers(mut self, headers: HeaderMap) -> Self {
+        self.api_headers = headers;
+        self
+    }
+
+    /// Adds (or replaces) a header in both sets.
+    pub fn header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.page_headers.insert(name.clone(), value.clone());
+        self.api_headers.insert(name, value);
+        self
+    }
+
+    /// Shares a cookie jar with the session. Without one the session keeps its own.
+    pub fn cookie_store(mut self, cookie_store: Arc<CookieStoreMutex>) -> Self {
+        self.cookie_store = Some(cookie_store);
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Sends every request through `proxy` (`http://`, `https://`, `socks5://` or `socks5h://`,
+    /// optionally with `user:password@`). Without one, the `HTTP_PROXY`/`HTTPS_PROXY`/`ALL_PROXY`
+    /// environment variables apply.
+    pub fn proxy(mut self, proxy: Option<String>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Hosts that bypass the proxy, in `NO_PROXY` syntax (`example.com,.onion,10.0.0.0/8`).
+    /// Defaults to the `NO_PROXY` environment variable.
+    pub fn no_proxy(mut self, no_proxy: Option<String>) -> Self {
+        self.no_proxy = no_proxy;
+        self
+    }
+
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Fails every request made more than `deadline` after `build()` with a `Deadline` timeout.
+    pub fn deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Connects to `addrs` instead of whatever `host` resolves to. The URL is left alone, so the
+    /// `Host` header, TLS name and cookie domains are still those of `host`.
+    pub fn resolve(mut self, host: &str, addrs: &[IpAddr]) -> Self {
+        self.resolve.insert(host.to_ascii_lowercase(), addrs.to_vec());
+        self
+    }
+
+    /// Resolves host names with `resolver` instead of the system resolver. Overrides given with
+    /// `resolve` still win.
+    pub fn dns_resolver(mut self, resolver: Arc<dyn reqwest::dns::Resolve>) -> Self {
+        self.dns_resolver = Some(SharedResolver(resolver));
+        self
+    }
+
+    /// Trusts `certificates` (see `load_certificates`) in addition to the system roots.
+    pub fn root_certificates(mut self, certificates: Vec<Certificate>) -> Self {
+        self.root_certificates.extend(certificates);
+        self
+    }
+
+    /// Presents `identity` (see `load_identity`) to servers that ask for a client certificate.
+    pub fn identity(mut self, identity: Option<Identity>) -> Self {
+        self.identity = identity;
+        self
+    }
+
+    /// Skips server certificate and hostname verification. Only for test mirrors.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Builds a client for relaying another program's requests (see `send_relayed`): redirects
+    /// are handed back rather than followed, no cookie jar is kept so only the cookies a request
+    /// carries are sent, and there is no deadline since the session outlives any one job.
+    pub fn relay(mut self, relay: bool) -> Self {
+        self.relay = relay;
+        self
+    }
+
+    pub fn build(self) -> Result<Session, NetworkError> {
+        let mut client_builder = Client::builder();
+        if self.relay {
+            client_builder = client_builder.redirect(reqwest::redirect::Policy::none());
+        } else {
+            client_builder = client_builder.cookie_provider(self.cookie_store.unwrap_or_default());
+        }
+        if let Some(proxy) = &self.proxy {
+            client_builder = client_builder.proxy(build_proxy(proxy, self.no_proxy.as_deref())?);
+        }
+        if let Some(resolver) = self.dns_resolver {
+            client_builder = client_builder.dns_resolver(Arc::new(resolver));
+        }
+        for (host, addrs) in &self.resolve {
+            // Port 0: connect on the URL's port, or the scheme's default
+            let addrs: Vec<SocketAddr> = addrs.iter().map(|ip| SocketAddr::new(*ip, 0)).collect();
+            debug!("[RESOLVE] {} -> {:?}", host, addrs);
+            client_builder = client_builder.resolve_to_addrs(host, &addrs);
+        }
+        for certificate in self.root_certificates {
+            client_builder = client_builder.add_root_certificate(certificate);
+        }
+        if let Some(identity) = self.identity {
+            client_builder = client_builder.identity(identity);
+        }
+        if self.accept_invalid_certs {
+            warn!("TLS certificate verification is disabled");
+            client_builder = client_builder.danger_accept_invalid_certs(true).danger_accept_invalid_hostnames(true);
+        }
+        if let Some(timeout) = self.timeouts.connect {
+            client_builder = client_builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeouts.read {
+            client_builder = client_builder.read_timeout(timeout);
+        }
+        Ok(Session {
+            client: client_builder.build()?,
+            retry: self.retry,
+            page_headers: self.page_headers,
+            api_headers: self.api_headers,
+            timeouts: self.timeouts,
+            deadline: self.deadline.filter(|_| !self.relay).map(|d| Instant::now() + d),
+            timings: Mutex::new(Vec::new()),
+        })
+    }
+}
+
+/// Reads the PEM certificates in `path`, e.g. a CA bundle for `SessionBuilder::root_certificates`.
+pub fn load_certificates(path: &Path) -> Result<Vec<Certificate>, NetworkError> {
+    let invalid = |detail: String| NetworkError::InvalidTls(format!("{}: {}", path.display(), detail));
+    let pem = std::fs::read(path).map_err(|e| invalid(e.to_string()))?;
+    let certificates = Certificate::from_pem_bundle(&pem).map_err(|e| invalid(e.to_string()))?;
+    if certificates.is_empty() {
+        return Err(invalid("no PEM certificates found".to_string()));
+    }
+    debug!("[TLS] Loaded {} certificate(s) from {}", certificates.len(), path.display());
+    Ok(certificates)
+}
+
+/// Reads a client certificate (with any intermediates) and its private key, both PEM, for
+/// `SessionBuilder::identity`. Without `key`, the key is expected in `cert` too.
+pub fn load_identity(cert: &Path, key: Option<&Path>) -> Result<Identity, NetworkError> {
+    let invalid = |path: &Path, detail: String| NetworkError::InvalidTls(format!("{}: {}", path.display(), detail));
+    let cert_pem = std::fs::read_to_string(cert).map_err(|e| invalid(cert, e.to_string()))?;
+    let (cert_pem, key_pem) = match key {
+        Some(key) => (cert_pem, std::fs::read_to_string(key).map_err(|e| invalid(key, e.to_string()))?),
+        None => split_private_key(&cert_pem),
+    };
+    Identity::from_pkcs8_pem(cert_pem.as_bytes(), key_pem.as_bytes()).map_err(|e| invalid(key.unwrap_or(cert), e.to_string()))
+}
+
+/// Separates the `PRIVATE KEY` blocks of a PEM file from the rest, since the certificate parser
+/// does not skip them.
+fn split_private_key(pem: &str) -> (String, String) {
+    let (mut certs, mut keys) = (String::new(), String::new());
+    let mut in_key = false;
+    for line in pem.lines() {
+        if line.starts_with("-----BEGIN ") {
+            in_key = line.contains("PRIVATE KEY");
+        }
+        let target = if in_key { &mut keys } else { &mut certs };
+        target.push_str(line);
+        target.push('\n');
+    }
+    (certs, keys)
+}
+
+/// A resolver handed to `SessionBuilder::dns_resolver`.
+#[derive(Clone)]
+struct SharedResolver(Arc<dyn reqwest::dns::Resolve>);
+
+impl reqwest::dns::Resolve for SharedResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        self.0.resolve(name)
+    }
+}
+
+impl std::fmt::Debug for SharedResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SharedResolver")
+    }
+}
+
+/// A curl-style `--resolve HOST:PORT:ADDR[,ADDR...]` entry. IPv6 addresses go in brackets.
+/// `PORT` may be `*`; it is accepted for compatibility with curl, but the override applies to the
+/// host on every port.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveOverride {
+    pub host: String,
+    pub port: Option<u16>,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl std::str::FromStr for ResolveOverride {
+    type Err = NetworkError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let invalid = |detail: &str| NetworkError::InvalidResolve(format!("{}: {}", spec, detail));
+        let mut parts = spec.splitn(3, ':');
+        let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid("expected HOST:PORT:ADDR[,ADDR...]"));
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse().map_err(|_| invalid("invalid port"))?),
+        };
+        let addrs = addrs.split(',')
+            .map(|addr| addr.trim().trim_start_matches('[').trim_end_matches(']').parse())
+            .collect::<Result<Vec<IpAddr>, _>>()
+            .map_err(|_| invalid("invalid IP address"))?;
+        Ok(ResolveOverride { host: host.to_ascii_lowercase(), port, addrs })
+    }
+}
+
+/// Proxy schemes `SessionBuilder::proxy` accepts. `socks5h` resolves host names on the proxy,
+/// which onion addresses need.
+const PROXY_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+fn build_proxy(proxy: &str, no_proxy: Option<&str>) -> Result<reqwest::Proxy, NetworkError> {
+    let url = Url::parse(proxy).map_err(|e| NetworkError::InvalidProxy(format!("{}: {}", proxy, e)))?;
+    if !PROXY_SCHEMES.contains(&url.scheme()) {
+        return Err(NetworkError::InvalidProxy(format!("{}: unsupported scheme '{}' (expected {})", proxy, url.scheme(), PROXY_SCHEMES.join(", "))));
+    }
+    let no_proxy = match no_proxy {
+        Some(list) => reqwest::NoProxy::from_string(list),
+        None => reqwest::NoProxy::from_env(),
+    };
+    debug!("[PROXY] Using {} (no proxy for: {:?})", url.scheme(), no_proxy);
+    Ok(reqwest::Proxy::all(url)?.no_proxy(no_proxy))
+}
+
+/// Sends the request produced by `build`, retrying according to the session's policy,
+/// and records its timing on the session.
+/// `build` is called once per attempt since a `RequestBuilder` cannot be reused.
+/// The last response is returned as-is once attempts run out, so callers still see the final status.
+async fn send_with_retry<F>(session: &Session, label: &str, build: F) -> Result<Response, NetworkError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    send_recorded(session, label, &session.retry, build).await
+}
+
+/// Like `send_with_retry`, but sent exactly once whatever the session's policy, for requests that
+/// must not be repeated, such as a form POST made on another program's behalf.
+async fn send_once<F>(session: &Session, label: &str, build: F) -> Result<Response, NetworkError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let once = RetryPolicy { max_attempts: 1, ..session.retry.clone() };
+    send_recorded(session, label, &once, build).await
+}
+
+/// Sends the request produced by `build` under `policy` and records its timing on the session.
+async fn send_recorded<F>(session: &Session, label: &str, policy: &RetryPolicy, build: F) -> Result<Response, NetworkError>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let start_time = Instant::now();
+    // Filled in from the first attempt's request
+    let mut target = (String::new(), String::new());
+    let result = retry_loop(policy, label, build, &mut target).await;
+    let (method, url) = target;
+    session.record_timing(RequestTiming {
+        step: label.to_string(),
+        method,
+        url,
+        status: result.as_ref().ok().map(|(response, _)| response.status().as_u16()),
+        attempts: match &result { Ok((_, attempts)) => *attempts, Err((_, attempts)) => *attempts },
+        duration: start_time.elapsed(),
+    });
+    result.map(|(response, _)| response).map_err(|(e, _)| e)
+}
+
+/// The retry loop behind `send_with_retry`. Returns the outcome along with the number of attempts
+/// made, and sets `target` to the method and URL of the first attempt.
+async fn retry_loop<F>(policy: &RetryPolicy, label: &str, build: F, target: &mut (String, String)) -> Result<(Response, u32), (NetworkError, u32)>
+where
+    F: Fn() -> RequestBuilder,
+{
+    let max_attempts = policy.max_attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        let (client, request) = build().build_split();
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                if let Some(url) = e.url() {
+                    target.1 = url.to_string();
+                }
+                return Err((e.into(), attempt));
+            }
+        };
+        if attempt == 1 {
+            *target = (request.method().to_string(), request.url().to_string());
+        }
+        let delay = match client.execute(request).await {
+            Ok(response) if attempt < max_attempts && is_retryable_status(response.status()) => {
+                let status = response.status();
+                let requested = match status {
+                    StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE => retry_after(&response),
+                    _ => None,
+                };
+                let delay = requested.map(|d| d.min(policy.max_delay)).unwrap_or_else(|| policy.backoff(attempt));
+                warn!("[RETRY] {} got {} (attempt {}/{}), retrying in {:.2?}", label, status, attempt, max_attempts, delay);
+                delay
+            }
+            Ok(response) => return Ok((response, attempt)),
+            Err(e) if attempt < max_attempts && is_transient_error(&e) => {
+                let delay = policy.backoff(attempt);
+                warn!("[RETRY] {} failed: {} (attempt {}/{}), retrying in {:.2?}", label, e, attempt, max_attempts, delay);
+                delay
+            }
+            Err(e) => return Err((e.into(), attempt)),
+        };
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+/// A fetched page and the status it came with.
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    pub status: StatusCode,
+    pub body: String,
+}
+
 #[derive(Debug)]
 pub enum NetworkError {
     Reqwest(ReqwestError),
     ApiError { status: StatusCode, message: String },
+    /// The server refused the submitted solution, e.g. because the salt has expired.
+    AnswerRejected { status: StatusCode, message: String },
+    /// The server refused the token submitted to /check.
+    CheckRejected { status: StatusCode, message: String },
+    /// The page was refused outright (403/451 without a challenge to solve).
+    Blocked { status: StatusCode, url: String },
+    /// A configured header name or value is not valid HTTP.
+    InvalidHeader(String),
+    /// The proxy URL cannot be parsed or has an unsupported scheme.
+    InvalidProxy(String),
+    /// A `--resolve` entry cannot be parsed.
+    InvalidResolve(String),
+    /// A CA bundle, client certificate or key cannot be read or used.
+    InvalidTls(String),
+    /// A time limit ran out; `context` is the URL or step that was interrupted.
+    Timeout { phase: TimeoutPhase, context: String },
     UrlParseError(url::ParseError),
     MissingAuthToken(String),
     SerdeJsonError(serde_json::Error),
@@ -26,6 +798,12 @@ pub enum NetworkError {
 
 impl From<ReqwestError> for NetworkError {
     fn from(err: ReqwestError) -> NetworkError {
+        if err.is_timeout() {
+            // reqwest only enforces the connect and read timeouts; the others are ours
+            let phase = if err.is_connect() { TimeoutPhase::Connect } else { TimeoutPhase::Read };
+            let context = err.url().map(|u| u.to_string()).unwrap_or_else(|| "request".to_string());
+            return NetworkError::Timeout { phase, context };
+        }
         NetworkError::Reqwest(err)
     }
 }
@@ -47,6 +825,14 @@ impl std::fmt::Display for NetworkError {
         match self {
             NetworkError::Reqwest(e) => write!(f, "HTTP request error: {}", e),
             NetworkError::ApiError { status, message } => write!(f, "API error ({}): {}", status, message),
+            NetworkError::AnswerRejected { status, message } => write!(f, "Solution rejected by /answer ({}): {}", status, message),
+            NetworkError::CheckRejected { status, message } => write!(f, "Token rejected by /check ({}): {}", status, message),
+            NetworkError::Blocked { status, url } => write!(f, "Access to {} blocked ({})", url, status),
+            NetworkError::InvalidHeader(detail) => write!(f, "Invalid header: {}", detail),
+            NetworkError::InvalidProxy(detail) => write!(f, "Invalid proxy: {}", detail),
+            NetworkError::InvalidResolve(detail) => write!(f, "Invalid resolve override: {}", detail),
+            NetworkError::InvalidTls(detail) => write!(f, "Invalid TLS setting: {}", detail),
+            NetworkError::Timeout { phase, context } => write!(f, "Timed out ({} timeout) during {}", phase, context),
             NetworkError::UrlParseError(e) => write!(f, "URL parsing error: {}", e),
             NetworkError::MissingAuthToken(context) => write!(f, "Missing auth token in API response ({})", context),
             NetworkError::SerdeJsonError(e) => write!(f, "JSON deserialization error: {}", e),
@@ -57,115 +843,254 @@ impl std::fmt::Display for NetworkError {
 impl std::error::Error for NetworkError {}
 
 /// Fetches the initial HTML content from the given URL.
-pub async fn fetch_initial_page_html(client: &Client, url_str: &str) -> Result<String, NetworkError> {
-    let start_time = Instant::now();
-    let response_result = client.get(url_str).send().await;
-    let duration = start_time.elapsed();
-    info!("[TIMING] fetch_initial_page_html for {} took {:.2?}", url_str, duration);
+pub async fn fetch_initial_page_html(session: &Session, url_str: &str) -> Result<FetchedPage, NetworkError> {
+    session.bounded(url_str, async {
+        let start_time = Instant::now();
+        let response_result = send_with_retry(session, "fetch_initial_page_html", || session.get(url_str)).await;
+        let duration = start_time.elapsed();
+        info!("[TIMING] fetch_initial_page_html for {} took {:.2?}", url_str, duration);
 
-    let response = response_result?;
-    if !response.status().is_success() {
-        return Err(NetworkError::ApiError {
-            status: response.status(),
-            message: format!("Failed to fetch initial page: {}", url_str),
-        });
-    }
-    Ok(response.text().await?)
+        read_page(response_result?, url_str, "Failed to fetch initial page").await
+    }).await
+}
+
+/// Fetches the text of a script referenced by the challenge page.
+pub async fn fetch_script_text(session: &Session, url: &Url) -> Result<String, NetworkError> {
+    session.bounded(url.as_str(), async {
+        let start_time = Instant::now();
+        let response_result = send_with_retry(session, "fetch_script_text", || session.get(url.clone())).await;
+        let duration = start_time.elapsed();
+        info!("[TIMING] fetch_script_text for {} took {:.2?}", url, duration);
+
+        let response = response_result?;
+        if !response.status().is_success() {
+            return Err(NetworkError::ApiError {
+                status: response.status(),
+                message: format!("Failed to fetch challenge script: {}", url),
+            });
+        }
+        Ok(response.text().await?)
+    }).await
 }
 
 /// Submits the Proof-of-Work solution to the /answer endpoint.
-/// Returns the temporary authentication token.
-pub async fn submit_pow_answer(client: &Client, base_url: &Url, salt: &str, successful_attempt_str: &str) -> Result<String, NetworkError> {
-    let origin = base_url.origin().unicode_serialization();
-    let answer_url_str = format!("{}/.sssg/api/answer", origin);
-    let answer_url = Url::parse(&answer_url_str)?;
-    
-    let params = [("a", salt), ("b", successful_attempt_str)];
-    debug!("[API] Sending POST to /answer URL: {}", answer_url);
-    debug!("[API] /answer form params: {:?}", params);
+/// Returns the clearance, whose `token` is the temporary authentication token for /check.
+pub async fn submit_pow_answer(session: &Session, base_url: &Url, endpoints: &SssgEndpoints, salt: &str, successful_attempt_str: &str) -> Result<Clearance, NetworkError> {
+    session.bounded("submit_pow_answer", async {
+        let answer_url = endpoints.answer_url(base_url)?;
 
-    let start_time = Instant::now();
-    let response_result = client.post(answer_url.clone())
-        .form(&params)
-        .send()
-        .await;
-    let duration = start_time.elapsed();
-    info!("[TIMING] submit_pow_answer to {} took {:.2?}", answer_url, duration);
+        let params = [(endpoints.salt_field.as_str(), salt), (endpoints.attempt_field.as_str(), successful_attempt_str)];
+        debug!("[API] Sending POST to /answer URL: {}", answer_url);
+        debug!("[API] /answer form params: {:?}", params);
+
+        let start_time = Instant::now();
+        let response_result = send_with_retry(session, "submit_pow_answer", || session.post(answer_url.clone()).form(&params)).await;
+        let duration = start_time.elapsed();
+        info!("[TIMING] submit_pow_answer to {} took {:.2?}", answer_url, duration);
     
-    let response = response_result?;
+        let response = response_result?;
 
-    if !response.status().is_success() {
-        let status_code = response.status();
-        let error_text = match response.text().await {
-            Ok(text) => text,
-            Err(e) => format!("Failed to read error body (detail: {}). Original status: {}", e, status_code),
-        };
-        return Err(NetworkError::ApiError {
-            status: status_code,
-            message: format!("Failed to submit to /answer. Server response: {}", error_text),
-        });
-    }
+        if !response.status().is_success() {
+            let status_code = response.status();
+            let error_text = match response.text().await {
+                Ok(text) => text,
+                Err(e) => format!("Failed to read error body (detail: {}). Original status: {}", e, status_code),
+            };
+            if status_code.is_client_error() && status_code != StatusCode::TOO_MANY_REQUESTS {
+                return Err(NetworkError::AnswerRejected { status: status_code, message: error_text });
+            }
+            return Err(NetworkError::ApiError {
+                status: status_code,
+                message: format!("Failed to submit to /answer. Server response: {}", error_text),
+            });
+        }
 
-    let response_text = response.text().await?;
-    debug!("[API] /answer response body: {}", response_text);
-    let answer_json: AnswerResponse = serde_json::from_str(&response_text)
-        .map_err(NetworkError::from)?;
-    Ok(answer_json.auth)
+        let received_at = SystemTime::now();
+        let headers = response.headers().clone();
+        let response_text = response.text().await?;
+        debug!("[API] /answer response body: {}", response_text);
+        let answer_json: AnswerResponse = serde_json::from_str(&response_text)
+            .map_err(NetworkError::from)?;
+        if answer_json.auth.is_empty() {
+            return Err(NetworkError::MissingAuthToken("/answer".to_string()));
+        }
+        Ok(Clearance::from_response(&headers, answer_json.auth, ClearanceSource::Answer, received_at))
+    }).await
 }
 
 /// Submits the temporary authentication token to the /check endpoint.
-/// Returns the final sssg_clearance token.
-pub async fn submit_final_check(client: &Client, base_url: &Url, temp_auth_token: &str) -> Result<String, NetworkError> {
-    let origin = base_url.origin().unicode_serialization();
-    let check_url_str = format!("{}/.sssg/api/check", origin);
-    let check_url = Url::parse(&check_url_str)?;
+/// Returns the final sssg_clearance.
+pub async fn submit_final_check(session: &Session, base_url: &Url, endpoints: &SssgEndpoints, temp_auth_token: &str) -> Result<Clearance, NetworkError> {
+    session.bounded("submit_final_check", async {
+        let check_url = endpoints.check_url(base_url)?;
 
-    let params = [("f", temp_auth_token)];
-    debug!("[API] Sending POST to /check URL: {}", check_url);
-    debug!("[API] /check form params: {:?}", params);
+        let params = [(endpoints.token_field.as_str(), temp_auth_token)];
+        debug!("[API] Sending POST to /check URL: {}", check_url);
+        debug!("[API] /check form params: {:?}", params);
 
-    let start_time = Instant::now();
-    let response_result = client.post(check_url.clone())
-        .form(&params)
-        .send()
-        .await;
-    let duration = start_time.elapsed();
-    info!("[TIMING] submit_final_check to {} took {:.2?}", check_url, duration);
-
-    let response = response_result?;
-
-    if !response.status().is_success() {
-        let status_code = response.status();
-        let error_text = match response.text().await {
-            Ok(text) => text,
-            Err(e) => format!("Failed to read error body (detail: {}). Original status: {}", e, status_code),
-        };
-        return Err(NetworkError::ApiError {
-            status: status_code,
-            message: format!("Failed to submit to /check. Server response: {}", error_text),
-        });
-    }
+        let start_time = Instant::now();
+        let response_result = send_with_retry(session, "submit_final_check", || session.post(check_url.clone()).form(&params)).await;
+        let duration = start_time.elapsed();
+        info!("[TIMING] submit_final_check to {} took {:.2?}", check_url, duration);
+
+        let response = response_result?;
 
-    let response_text = response.text().await?;
-    debug!("[API] /check response body: {}", response_text);
-    let check_json: CheckResponse = serde_json::from_str(&response_text)
-        .map_err(NetworkError::from)?;
-    Ok(check_json.auth)
+        if !response.status().is_success() {
+            let status_code = response.status();
+            let error_text = match response.text().await {
+                Ok(text) => text,
+                Err(e) => format!("Failed to read error body (detail: {}). Original status: {}", e, status_code),
+            };
+            if status_code.is_client_error() && status_code != StatusCode::TOO_MANY_REQUESTS {
+                return Err(NetworkError::CheckRejected { status: status_code, message: error_text });
+            }
+            return Err(NetworkError::ApiError {
+                status: status_code,
+                message: format!("Failed to submit to /check. Server response: {}", error_text),
+            });
+        }
+
+        let received_at = SystemTime::now();
+        let headers = response.headers().clone();
+        let response_text = response.text().await?;
+        debug!("[API] /check response body: {}", response_text);
+        let check_json: CheckResponse = serde_json::from_str(&response_text)
+            .map_err(NetworkError::from)?;
+        if check_json.auth.is_empty() {
+            return Err(NetworkError::MissingAuthToken("/check".to_string()));
+        }
+        Ok(Clearance::from_response(&headers, check_json.auth, ClearanceSource::Check, received_at))
+    }).await
 }
 
 /// Fetches HTML content from the given URL using the client (which should have cookies set).
-pub async fn fetch_page_html_with_cookies(client: &Client, url_str: &str) -> Result<String, NetworkError> {
-    let start_time = Instant::now();
-    let response_result = client.get(url_str).send().await;
-    let duration = start_time.elapsed();
-    info!("[TIMING] fetch_page_html_with_cookies for {} took {:.2?}", url_str, duration);
+pub async fn fetch_page_html_with_cookies(session: &Session, url_str: &str) -> Result<FetchedPage, NetworkError> {
+    session.bounded(url_str, async {
+        let start_time = Instant::now();
+        let response_result = send_with_retry(session, "fetch_page_html_with_cookies", || session.get(url_str)).await;
+        let duration = start_time.elapsed();
+        info!("[TIMING] fetch_page_html_with_cookies for {} took {:.2?}", url_str, duration);
 
-    let response = response_result?;
-    if !response.status().is_success() {
-        return Err(NetworkError::ApiError {
+        read_page(response_result?, url_str, "Failed to fetch page HTML").await
+    }).await
+}
+
+/// A page fetched on behalf of another program, with whatever status it came with.
+#[derive(Debug, Clone)]
+pub struct PageResponse {
+    /// The URL after redirects.
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+/// Requests `url` with the page headers: a GET, or with `form` a POST of that urlencoded body.
+/// Unlike the other fetches, any status is returned rather than turned into an error. Only GETs
+/// are retried; a POST is sent once, as a relayed request is, since it may have effects.
+pub async fn request_page(session: &Session, url: &Url, form: Option<&str>) -> Result<PageResponse, NetworkError> {
+    session.bounded(url.as_str(), async {
+        let start_time = Instant::now();
+        let response_result = match form {
+            Some(form) => send_once(session, "request_page", || session.client.post(url.clone())
+                .headers(session.page_headers.clone())
+                .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                .body(form.to_string())).await,
+            None => send_with_retry(session, "request_page", || session.get(url.clone())).await,
+        };
+        let duration = start_time.elapsed();
+        info!("[TIMING] request_page for {} took {:.2?}", url, duration);
+
+        let response = response_result?;
+        Ok(PageResponse {
+            url: response.url().clone(),
             status: response.status(),
-            message: format!("Failed to fetch page HTML from {}: {}", url_str, response.status()),
-        });
+            headers: response.headers().clone(),
+            body: response.text().await?,
+        })
+    }).await
+}
+
+/// Sends a request relayed for another program, once and as built: no page headers, retries or
+/// redirects (with a `relay` session). The response comes back with any status and its body unread.
+pub async fn send_relayed(session: &Session, request: RequestBuilder) -> Result<Response, NetworkError> {
+    let request = request.build()?;
+    let url = request.url().to_string();
+    session.bounded(&url, async {
+        let start_time = Instant::now();
+        let response = session.client.execute(request).await?;
+        debug!("[TIMING] relayed request to {} answered {} in {:.2?}", url, response.status(), start_time.elapsed());
+        Ok(response)
+    }).await
+}
+
+/// The outcome of `check_clearance`.
+#[derive(Debug, Clone)]
+pub struct ClearanceCheck {
+    pub status: ClearanceStatus,
+    /// The HTTP status of the test fetch.
+    pub http_status: StatusCode,
+    /// The `sssg_clearance` value that was sent, if the jar had an unexpired one for the URL.
+    pub value: Option<String>,
+    /// When that cookie expires; `None` for a session cookie or when none was sent.
+    pub expires_at: Option<SystemTime>,
+}
+
+impl ClearanceCheck {
+    /// Time left until the clearance expires, if known.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at.map(|at| at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+    }
+}
+
+/// Requests `url` with the clearance in `cookie_store` (which must be the session's jar) and
+/// classifies the response, without solving anything.
+pub async fn check_clearance(session: &Session, cookie_store: &CookieStoreMutex, url: &Url) -> Result<ClearanceCheck, NetworkError> {
+    let (value, expires_at) = match cookie_store.lock() {
+        Ok(store) => match store.matches(url).into_iter().find(|c| c.name() == CLEARANCE_COOKIE) {
+            Some(cookie) => {
+                let expires_at = match cookie.expires {
+                    cookie_store::CookieExpiration::AtUtc(at) => Some(SystemTime::from(at)),
+                    cookie_store::CookieExpiration::SessionEnd => None,
+                };
+                (Some(cookie.value().to_string()), expires_at)
+            }
+            None => (None, None),
+        },
+        Err(_) => (None, None),
+    };
+    if value.is_none() {
+        warn!("No unexpired {} for {} in the cookie jar", CLEARANCE_COOKIE, url);
+    }
+    let (status, http_status) = match fetch_page_html_with_cookies(session, url.as_str()).await {
+        Ok(page) => match html_parser::classify_page(&page.body) {
+            html_parser::PageKind::Content => (ClearanceStatus::Valid, page.status),
+            html_parser::PageKind::Challenge => (ClearanceStatus::Challenged, page.status),
+        },
+        Err(NetworkError::Blocked { status, .. }) => (ClearanceStatus::Blocked, status),
+        Err(e) => return Err(e),
+    };
+    info!("[CLEARANCE] {} for {} ({})", status, url, http_status);
+    Ok(ClearanceCheck { status, http_status, value, expires_at })
+}
+
+/// Reads a page response. A 403 or 451 is reported as `Blocked` unless its body is a challenge,
+/// which is returned like any other page so it can be solved.
+async fn read_page(response: Response, url_str: &str, context: &str) -> Result<FetchedPage, NetworkError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(FetchedPage { status, body: response.text().await? });
     }
-    Ok(response.text().await?)
-}
\ No newline at end of file
+    if status == StatusCode::FORBIDDEN || status == StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS {
+        let body = response.text().await?;
+        if html_parser::classify_page(&body) == html_parser::PageKind::Challenge {
+            debug!("[API] {} answered {} with a challenge", url_str, status);
+            return Ok(FetchedPage { status, body });
+        }
+        return Err(NetworkError::Blocked { status, url: url_str.to_string() });
+    }
+    Err(NetworkError::ApiError {
+        status,
+        message: format!("{}: {}", context, url_str),
+    })
+}
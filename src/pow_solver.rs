@@ -1,10 +1,68 @@
 use sha2::{Digest, Sha256};
 use std::time::Instant;
 use rayon::prelude::*;
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::sync::mpsc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// A solved challenge, with statistics about the search.
+#[derive(Debug, Clone)]
+pub struct Solution {
+    /// The attempt string to submit, formatted as JavaScript would.
+    pub attempt: String,
+    /// Hex-encoded SHA-256 of salt + attempt.
+    pub hash: String,
+    /// Hashes computed across all threads.
+    pub hashes: u64,
+    pub duration: Duration,
+}
+
+impl Solution {
+    /// Hashes per second over the whole search.
+    pub fn hash_rate(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 { self.hashes as f64 / secs } else { 0.0 }
+    }
+}
+
+/// The highest difficulty the solver can meet: only the first 32 bits of the hash are compared.
+pub const MAX_SOLVABLE_DIFFICULTY: u32 = 32;
+
+/// Why the solver did not produce a solution.
+#[derive(Debug)]
+pub enum SolveError {
+    /// The difficulty is above what the caller (or the solver) is willing to attempt.
+    DifficultyTooHigh { difficulty: u32, max: u32 },
+    /// The deadline passed before a solution was found.
+    Timeout { elapsed: Duration, hashes: u64 },
+    /// Every worker stopped without reporting a solution.
+    NoSolution,
+}
+
+impl std::fmt::Display for SolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolveError::DifficultyTooHigh { difficulty, max } => write!(f, "Challenge difficulty {} exceeds the maximum of {}", difficulty, max),
+            SolveError::Timeout { elapsed, hashes } => write!(f, "No solution found within {:.2?} ({} hashes)", elapsed, hashes),
+            SolveError::NoSolution => write!(f, "Solver stopped without finding a solution"),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+/// Hashes `salt` + `attempt` as the solver does. Returns the hex-encoded hash and its number of
+/// leading zero bits, which meets a difficulty if it is at least that high.
+pub fn check_attempt(salt: &str, attempt: &str) -> (String, u32) {
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(attempt.as_bytes());
+    let hash_result = hasher.finalize();
+    let first_word_u32 = u32::from_be_bytes([hash_result[0], hash_result[1], hash_result[2], hash_result[3]]);
+    (hex::encode(hash_result), first_word_u32.leading_zeros())
+}
 
 /// Solves the SSSG Proof-of-Work challenge.
 ///
@@ -13,26 +71,34 @@ use std::sync::Arc;
 /// * `difficulty` - The required number of leading zero bits.
 /// * `initial_attempt_base` - A base value for starting attempt nonces. Each thread will start from this base + its thread index.
 /// * `num_threads` - The number of threads to use for solving.
+/// * `timeout` - Give up after this long. `None` searches until a solution is found.
 ///
 /// # Returns
-/// An `Option` containing a tuple of `(successful_attempt_string, hex_encoded_hash_solution)` if a solution is found,
-/// otherwise `None` (though in the current parallel implementation, it will block until a solution is found by one thread).
-pub fn solve_challenge(salt_str: &str, difficulty: u32, initial_attempt_base: f64, num_threads: usize) -> Option<(String, String)> {
+/// The `Solution` (attempt string, hex-encoded hash and search statistics), or a `SolveError` if the
+/// difficulty cannot be met or the timeout passed first.
+pub fn solve_challenge(salt_str: &str, difficulty: u32, initial_attempt_base: f64, num_threads: usize, timeout: Option<Duration>) -> Result<Solution, SolveError> {
     // The debug! macro was already here from a previous attempt, which is good.
     // Ensuring the function signature is correct and suppress_logging is removed.
     debug!("[PoW Solver] Received Salt: \"{}\", Difficulty: {}", salt_str, difficulty);
+    if difficulty > MAX_SOLVABLE_DIFFICULTY {
+        return Err(SolveError::DifficultyTooHigh { difficulty, max: MAX_SOLVABLE_DIFFICULTY });
+    }
     let start_time = Instant::now();
+    let deadline = timeout.map(|t| start_time + t);
     let (tx, rx) = mpsc::channel();
-    let solution_found_flag = Arc::new(AtomicBool::new(false));
+    let solution_found_flag = Arc::new(AtomicBool::new(false)); // Also set on timeout, to stop every thread
+    let timed_out = AtomicBool::new(false);
+    let total_hashes = AtomicU64::new(0);
 
     (0..num_threads).into_par_iter().for_each_with(tx, |tx_clone, thread_idx| {
         // Each thread starts its attempt numbers from a slightly different base to reduce overlap,
         // and then increments by the total number of threads to ensure unique attempt spaces.
         let mut current_attempt_val = initial_attempt_base + thread_idx as f64;
-        let mut iteration_count: u64 = 0; // Iteration counter for yield logic
+        let mut iteration_count: u64 = 0; // Hashes computed by this thread; also drives the yield logic
         
         loop {
             if solution_found_flag.load(Ordering::Relaxed) {
+                total_hashes.fetch_add(iteration_count, Ordering::Relaxed);
                 return; // Another thread found the solution
             }
 
@@ -51,6 +117,7 @@ pub fn solve_challenge(salt_str: &str, difficulty: u32, initial_attempt_base: f6
             hasher.update(salt_str.as_bytes());
             hasher.update(attempt_str.as_bytes());
             let hash_result = hasher.finalize(); // This is GenericArray<u8, U32>
+            iteration_count += 1;
 
             // 4. Extract the first 32 bits (4 bytes) of the hash.
             //    SHA-256 output is big-endian.
@@ -69,6 +136,7 @@ pub fn solve_challenge(salt_str: &str, difficulty: u32, initial_attempt_base: f6
                         error!("Solver: Error sending solution: {}",e);
                     });
                 }
+                total_hashes.fetch_add(iteration_count, Ordering::Relaxed);
                 return; // Solution found by this thread
             }
             
@@ -79,28 +147,35 @@ pub fn solve_challenge(salt_str: &str, difficulty: u32, initial_attempt_base: f6
             // Basic yield to prevent a single thread from hogging CPU completely if running on a system
             // where Rayon's work-stealing isn't perfectly balancing very tight loops.
             // Consider removing if performance is impacted and not needed.
-            iteration_count += 1;
-            if iteration_count % 10000 == 0 { // Periodically yield, e.g., every 10000 iterations
+            if iteration_count.is_multiple_of(10000) { // Periodically yield, e.g., every 10000 iterations
                  std::thread::yield_now();
+                 // Checking the clock this rarely keeps it out of the hot loop
+                 if deadline.is_some_and(|d| Instant::now() >= d) {
+                     timed_out.store(true, Ordering::Relaxed);
+                     solution_found_flag.store(true, Ordering::Relaxed);
+                 }
             }
         }
     });
 
     // Wait for the first solution from any thread.
-    // `rx.recv()` will block until a message is sent or the channel is closed.
-    // If all sender threads exit without sending (e.g., if solution_found_flag was set by an external mechanism not shown here),
-    // recv() would error. In this setup, one thread is expected to send.
+    // `for_each_with` has returned, so every sender is gone: `recv()` yields a sent solution
+    // or fails if the threads stopped without one (timeout).
+    let duration = start_time.elapsed();
+    // Every thread has added its count by now.
+    let hashes = total_hashes.load(Ordering::Relaxed);
     match rx.recv() {
-        Ok(solution_opt) => {
-            if solution_opt.is_some() {
-                let duration = start_time.elapsed();
-                info!("[TIMING] PoW solve_challenge took {:.2?}", duration);
-            }
-            solution_opt
+        Ok(Some((attempt, hash))) => {
+            info!("[TIMING] PoW solve_challenge took {:.2?}", duration);
+            Ok(Solution { attempt, hash, hashes, duration })
+        }
+        Ok(None) | Err(_) if timed_out.load(Ordering::Relaxed) => {
+            warn!("Solver: timed out after {:.2?} ({} hashes)", duration, hashes);
+            Err(SolveError::Timeout { elapsed: duration, hashes })
         }
-        Err(e) => {
-            error!("Solver: Error receiving solution from worker threads: {}. This might happen if all threads exited prematurely.", e);
-            None
+        Ok(None) | Err(_) => {
+            error!("Solver: worker threads stopped without a solution.");
+            Err(SolveError::NoSolution)
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,187 @@
+//! Relaying other programs' requests to SSSG-protected origins, for `proxy` and `reverse-proxy`.
+//! The clearance is added to each request, and a request answered with an interstitial is
+//! replayed once a clearance has been obtained, so the program only ever sees the real page.
+
+use crate::acquire::{self, AcquireError};
+use crate::clearance::CLEARANCE_COOKIE;
+use crate::html_parser;
+use crate::network_client::{self, Session};
+use crate::server::{ApiError, ClearanceResponse, ClearanceService};
+use axum::body::{Body, Bytes};
+use axum::http::header::{ACCEPT_ENCODING, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, USER_AGENT};
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// Headers that only concern one connection, and are not passed on in either direction.
+const HOP_BY_HOP: [&str; 9] = ["connection", "keep-alive", "proxy-authenticate", "proxy-authorization", "proxy-connection", "te", "trailer", "transfer-encoding", "upgrade"];
+
+/// Relays requests through a session per origin, with clearances from a `ClearanceService`.
+pub struct Relay {
+    service: Arc<ClearanceService>,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+}
+
+impl Relay {
+    pub fn new(service: Arc<ClearanceService>) -> Relay {
+        Relay { service, sessions: Mutex::new(HashMap::new()) }
+    }
+
+    pub fn service(&self) -> &Arc<ClearanceService> {
+        &self.service
+    }
+
+    /// Relays `request` to `url`. With `intercept`, the clearance for `url`'s origin is added and
+    /// an interstitial is answered by solving and replaying the request; otherwise the request is
+    /// passed on as it is. Failures are answered with a plain-text error page.
+    pub async fn relay(&self, request: Request<Body>, url: Url, intercept: bool) -> Response<Body> {
+        match self.try_relay(request, &url, intercept).await {
+            Ok(response) => response,
+            Err(e) => {
+                warn!("Relaying {} failed: {}", url, e);
+                let error = ApiError::from(e);
+                let mut response = Response::new(Body::from(format!("{}\n", error.message)));
+                *response.status_mut() = error.status;
+                response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"));
+                response
+            }
+        }
+    }
+
+    async fn try_relay(&self, request: Request<Body>, url: &Url, intercept: bool) -> Result<Response<Body>, AcquireError> {
+        let (parts, body) = request.into_parts();
+        // Kept whole, since the request may have to be sent again
+        let body = axum::body::to_bytes(body, usize::MAX).await.map_err(|e| AcquireError::Internal(format!("Cannot read the request body: {}", e)))?;
+        let session = self.session(url, intercept)?;
+
+        let mut sent = if intercept { self.service.cached_clearance(url) } else { None };
+        let mut upstream = send(&session, &parts, &body, url, intercept, sent.as_ref()).await?;
+        // Solve when challenged, and solve for a new clearance if even that one is refused
+        for _ in 0..2 {
+            if !intercept || !upstream.is_challenge() {
+                break;
+            }
+            let force = sent.is_some();
+            info!("{} answered with an interstitial, {}", url, if force { "solving for a new clearance" } else { "getting a clearance" });
+            let clearance = match self.service.clearance(url, force).await {
+                Ok(clearance) => clearance,
+                Err(e) => {
+                    warn!("Cannot clear {}: {}; passing the interstitial on", url, e);
+                    break;
+                }
+            };
+            upstream = send(&session, &parts, &body, url, intercept, Some(&clearance)).await?;
+            sent = Some(clearance);
+        }
+        if intercept && upstream.is_challenge() {
+            warn!("{} still presents a challenge, passing it on", url);
+        }
+        Ok(upstream.into_response())
+    }
+
+    /// The session requests to `url`'s origin are relayed through, built from the origin's settings
+    /// when intercepting, otherwise one session for all other origins.
+    fn session(&self, url: &Url, intercept: bool) -> Result<Arc<Session>, AcquireError> {
+        let origin = if intercept { url.origin().unicode_serialization() } else { String::new() };
+        let mut sessions = self.sessions.lock().map_err(|e| AcquireError::Internal(e.to_string()))?;
+        if let Some(session) = sessions.get(&origin) {
+            return Ok(Arc::clone(session));
+        }
+        let settings = self.service.settings(&origin)?;
+        let session = Arc::new(acquire::session_builder(&settings, url, &Arc::default())?.relay(true).build()?);
+        sessions.insert(origin, Arc::clone(&session));
+        Ok(session)
+    }
+}
+
+/// Whether `name` only concerns one connection: a standard hop-by-hop header or one listed in
+/// `Connection`.
+fn is_hop_by_hop(name: &HeaderName, headers: &HeaderMap) -> bool {
+    HOP_BY_HOP.contains(&name.as_str())
+        || headers.get_all(CONNECTION).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|listed| listed.trim().eq_ignore_ascii_case(name.as_str()))
+}
+
+/// Sends the request described by `parts` and `body` to `url`, with `clearance` in place of any
+/// `sssg_clearance` the client sent.
+async fn send(session: &Session, parts: &Parts, body: &Bytes, url: &Url, intercept: bool, clearance: Option<&ClearanceResponse>) -> Result<Upstream, AcquireError> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in &parts.headers {
+        if !is_hop_by_hop(name, &parts.headers) && name != HOST && name != CONTENT_LENGTH {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+    if intercept {
+        // Bodies are read to look for interstitials, which compression would hide
+        headers.remove(ACCEPT_ENCODING);
+    }
+    if let Some(clearance) = clearance {
+        let mut pairs: Vec<&str> = parts.headers.get_all(COOKIE).iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty() && pair.split('=').next() != Some(CLEARANCE_COOKIE))
+            .collect();
+        let clearance_pair = format!("{}={}", CLEARANCE_COOKIE, clearance.clearance);
+        pairs.push(&clearance_pair);
+        let cookie = HeaderValue::from_str(&pairs.join("; ")).map_err(|e| AcquireError::Internal(format!("Invalid Cookie header: {}", e)))?;
+        headers.insert(COOKIE, cookie);
+        // SSSG may tie a clearance to the User-Agent it was obtained with
+        if let Some(user_agent) = clearance.user_agent.as_deref().and_then(|ua| HeaderValue::from_str(ua).ok()) {
+            headers.insert(USER_AGENT, user_agent);
+        }
+    }
+
+    let request = session.client.request(parts.method.clone(), url.clone()).headers(headers).body(body.clone());
+    let response = network_client::send_relayed(session, request).await?;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let is_html = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_none_or(|ctype| ctype.starts_with("text/html"));
+    let body = if intercept && is_html && !headers.contains_key(CONTENT_ENCODING) {
+        UpstreamBody::Read(response.bytes().await.map_err(network_client::NetworkError::from)?)
+    } else {
+        UpstreamBody::Streaming(response)
+    };
+    Ok(Upstream { status, headers, body })
+}
+
+/// An upstream response, with the body read if it could be an interstitial.
+struct Upstream {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: UpstreamBody,
+}
+
+enum UpstreamBody {
+    Read(Bytes),
+    Streaming(reqwest::Response),
+}
+
+impl Upstream {
+    fn is_challenge(&self) -> bool {
+        match &self.body {
+            UpstreamBody::Read(body) => html_parser::classify_page(&String::from_utf8_lossy(body)) == html_parser::PageKind::Challenge,
+            UpstreamBody::Streaming(_) => false,
+        }
+    }
+
+    fn into_response(self) -> Response<Body> {
+        let body = match self.body {
+            UpstreamBody::Read(body) => Body::from(body),
+            UpstreamBody::Streaming(response) => Body::new(reqwest::Body::from(response)),
+        };
+        let mut response = Response::new(body);
+        *response.status_mut() = self.status;
+        for (name, value) in &self.headers {
+            if !is_hop_by_hop(name, &self.headers) {
+                response.headers_mut().append(name.clone(), value.clone());
+            }
+        }
+        response
+    }
+}
@@ -0,0 +1,341 @@
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use cookie_store::CookieExpiration;
+use crate::cache::CacheEntry;
+use url::Url;
+use crate::clearance::{Clearance, ClearanceStatus, CookieAttributes, CLEARANCE_COOKIE};
+use crate::html_parser::Challenge;
+use crate::network_client::{ClearanceCheck, RequestTiming, SssgEndpoints};
+use crate::pow_solver::Solution;
+
+/// Everything a run did, for `--output-format json`.
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub url: String,
+    pub origin: String,
+    /// Origins tried before `origin`, in the order given, and why each was passed over.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub failed_mirrors: Vec<MirrorFailure>,
+    /// True if a clearance already in the cookie jar was accepted and nothing had to be solved.
+    pub reused_clearance: bool,
+    /// True if the page came without a challenge and there was no clearance to reuse.
+    pub nothing_to_clear: bool,
+    pub rounds: Vec<RoundReport>,
+    pub clearance: Option<ClearanceReport>,
+    pub requests: Vec<RequestReport>,
+    pub page: Option<PageReport>,
+}
+
+/// An origin that could not be used for a run.
+#[derive(Debug, Serialize)]
+pub struct MirrorFailure {
+    pub origin: String,
+    pub error: String,
+}
+
+/// One solve/answer cycle.
+#[derive(Debug, Serialize)]
+pub struct RoundReport {
+    pub round: u32,
+    pub challenge: ChallengeReport,
+    pub solve: SolveReport,
+    pub check: CheckReport,
+}
+
+/// How the /check policy played out in a round.
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    /// `never`, `always` or `auto`.
+    pub policy: String,
+    /// Whether /check was called, and so issued the clearance.
+    pub called: bool,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChallengeReport {
+    pub salt: String,
+    pub difficulty: u32,
+    pub timeout: u64,
+    pub answer_path: String,
+    pub check_path: String,
+}
+
+impl ChallengeReport {
+    pub fn new(challenge: &Challenge, endpoints: &SssgEndpoints) -> ChallengeReport {
+        ChallengeReport {
+            salt: challenge.salt.clone(),
+            difficulty: challenge.difficulty,
+            timeout: challenge.timeout,
+            answer_path: endpoints.answer_path.clone(),
+            check_path: endpoints.check_path.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SolveReport {
+    pub attempt: String,
+    pub hash: String,
+    pub hashes: u64,
+    pub threads: usize,
+    pub duration_ms: f64,
+    pub hash_rate: f64,
+}
+
+impl SolveReport {
+    pub fn new(solution: &Solution, threads: usize) -> SolveReport {
+        SolveReport {
+            attempt: solution.attempt.clone(),
+            hash: solution.hash.clone(),
+            hashes: solution.hashes,
+            threads,
+            duration_ms: millis(solution.duration),
+            hash_rate: solution.hash_rate(),
+        }
+    }
+}
+
+/// What a failed run prints with `--output-format json`, in place of its usual document.
+#[derive(Debug, Serialize)]
+pub struct ErrorReport {
+    pub error: String,
+    /// The process exit code, as listed in the README.
+    pub exit_code: u8,
+}
+
+/// Result of `bench`.
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub difficulty: u32,
+    pub threads: usize,
+    pub rounds: Vec<SolveReport>,
+    pub total_hashes: u64,
+    pub total_ms: f64,
+    pub hash_rate: f64,
+}
+
+impl BenchReport {
+    pub fn new(difficulty: u32, threads: usize, solutions: &[Solution]) -> BenchReport {
+        let total_hashes = solutions.iter().map(|s| s.hashes).sum();
+        let total: Duration = solutions.iter().map(|s| s.duration).sum();
+        let secs = total.as_secs_f64();
+        BenchReport {
+            difficulty,
+            threads,
+            rounds: solutions.iter().map(|s| SolveReport::new(s, threads)).collect(),
+            total_hashes,
+            total_ms: millis(total),
+            hash_rate: if secs > 0.0 { total_hashes as f64 / secs } else { 0.0 },
+        }
+    }
+}
+
+/// Result of `verify`.
+#[derive(Debug, Serialize)]
+pub struct VerifyReport {
+    pub salt: String,
+    pub attempt: String,
+    pub hash: String,
+    pub leading_zeros: u32,
+    pub difficulty: u32,
+    pub valid: bool,
+}
+
+/// One origin in the clearance cache, for `cookies list`.
+#[derive(Debug, Serialize)]
+pub struct CachedOriginReport {
+    pub origin: String,
+    /// When the clearance was obtained, as seconds since the Unix epoch; `None` for imported cookies.
+    pub obtained_at: Option<u64>,
+    pub cookies: usize,
+    /// Expiry of the cached `sssg_clearance`, as seconds since the Unix epoch; `None` if there is none
+    /// or it is a session cookie.
+    pub clearance_expires: Option<u64>,
+    /// False if there is no `sssg_clearance` or it has expired.
+    pub clearance_valid: bool,
+}
+
+impl From<&CacheEntry> for CachedOriginReport {
+    fn from(entry: &CacheEntry) -> CachedOriginReport {
+        let clearance = entry.cookies.iter().find(|c| c.name() == CLEARANCE_COOKIE);
+        let clearance_expires = clearance.and_then(|c| match &c.expires {
+            CookieExpiration::AtUtc(at) => Some(unix_secs(SystemTime::from(*at))),
+            CookieExpiration::SessionEnd => None,
+        });
+        CachedOriginReport {
+            origin: entry.origin.clone(),
+            obtained_at: entry.obtained_at,
+            cookies: entry.cookies.len(),
+            clearance_expires,
+            clearance_valid: clearance.is_some_and(|c| !c.is_expired()),
+        }
+    }
+}
+
+/// Result of `check-clearance`.
+#[derive(Debug, Serialize)]
+pub struct ClearanceCheckReport {
+    pub url: String,
+    pub origin: String,
+    /// Where the clearance came from: `argument`, `file` or `cache`.
+    pub source: String,
+    /// `valid`, `challenged` or `blocked`.
+    pub status: String,
+    pub http_status: u16,
+    /// The `sssg_clearance` value sent, if there was one.
+    pub value: Option<String>,
+    /// Expiry as seconds since the Unix epoch; `None` if unknown or a session cookie.
+    pub expires_at: Option<u64>,
+    pub expires_in_secs: Option<u64>,
+}
+
+impl ClearanceCheckReport {
+    pub fn new(url: &Url, origin: &str, source: &str, check: &ClearanceCheck) -> ClearanceCheckReport {
+        let status = match check.status {
+            ClearanceStatus::Valid => "valid",
+            ClearanceStatus::Challenged => "challenged",
+            ClearanceStatus::Blocked => "blocked",
+        };
+        ClearanceCheckReport {
+            url: url.to_string(),
+            origin: origin.to_string(),
+            source: source.to_string(),
+            status: status.to_string(),
+            http_status: check.http_status.as_u16(),
+            value: check.value.clone(),
+            expires_at: check.expires_at.map(unix_secs),
+            expires_in_secs: check.remaining().map(|d| d.as_secs()),
+        }
+    }
+}
+
+/// One origin handled by `refresh`, printed as a JSON line.
+#[derive(Debug, Serialize)]
+pub struct RefreshReport {
+    pub url: String,
+    pub origin: String,
+    /// False if the stored clearance still worked and was kept.
+    pub refreshed: bool,
+    pub clearance: ClearanceReport,
+    /// When the clearance is next refreshed, as seconds since the Unix epoch.
+    pub next_refresh: u64,
+}
+
+impl RefreshReport {
+    pub fn new(url: &Url, origin: &str, refreshed: bool, clearance: ClearanceReport, next_refresh: SystemTime) -> RefreshReport {
+        RefreshReport { url: url.to_string(), origin: origin.to_string(), refreshed, clearance, next_refresh: unix_secs(next_refresh) }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClearanceReport {
+    /// The endpoint that issued the clearance (`/answer`, `/check`), or `cookie-jar` if it was reused.
+    pub source: String,
+    /// The `sssg_clearance` value to send.
+    pub value: String,
+    /// The `auth` value from the API response body, if the clearance came from the API.
+    pub token: Option<String>,
+    pub cookie: Option<CookieReport>,
+    /// The server's `Date`, as seconds since the Unix epoch.
+    pub server_date: Option<u64>,
+    /// Expiry in local time, as seconds since the Unix epoch.
+    pub expires_at: Option<u64>,
+    pub expires_in_secs: Option<u64>,
+}
+
+impl ClearanceReport {
+    /// A clearance that was already in the cookie jar and still accepted.
+    pub fn reused(value: &str, expires_at: Option<SystemTime>) -> ClearanceReport {
+        ClearanceReport {
+            source: "cookie-jar".to_string(),
+            value: value.to_string(),
+            token: None,
+            cookie: None,
+            server_date: None,
+            expires_at: expires_at.map(unix_secs),
+            expires_in_secs: expires_at.map(|at| at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO).as_secs()),
+        }
+    }
+}
+
+impl From<&Clearance> for ClearanceReport {
+    fn from(clearance: &Clearance) -> ClearanceReport {
+        ClearanceReport {
+            source: clearance.source.to_string(),
+            value: clearance.value().to_string(),
+            token: Some(clearance.token.clone()),
+            cookie: clearance.cookie.as_ref().map(CookieReport::from),
+            server_date: clearance.server_date.map(unix_secs),
+            expires_at: clearance.expires_at.map(unix_secs),
+            expires_in_secs: clearance.remaining().map(|d| d.as_secs()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CookieReport {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+    pub path: Option<String>,
+    /// The `Expires` attribute as seconds since the Unix epoch (server clock).
+    pub expires: Option<u64>,
+    pub max_age: Option<u64>,
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<String>,
+}
+
+impl From<&CookieAttributes> for CookieReport {
+    fn from(cookie: &CookieAttributes) -> CookieReport {
+        CookieReport {
+            name: cookie.name.clone(),
+            value: cookie.value.clone(),
+            domain: cookie.domain.clone(),
+            path: cookie.path.clone(),
+            expires: cookie.expires.map(unix_secs),
+            max_age: cookie.max_age.map(|d| d.as_secs()),
+            secure: cookie.secure,
+            http_only: cookie.http_only,
+            same_site: cookie.same_site.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RequestReport {
+    pub step: String,
+    pub method: String,
+    pub url: String,
+    pub status: Option<u16>,
+    pub attempts: u32,
+    pub duration_ms: f64,
+}
+
+impl From<&RequestTiming> for RequestReport {
+    fn from(timing: &RequestTiming) -> RequestReport {
+        RequestReport {
+            step: timing.step.clone(),
+            method: timing.method.clone(),
+            url: timing.url.clone(),
+            status: timing.status,
+            attempts: timing.attempts,
+            duration_ms: millis(timing.duration),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageReport {
+    pub status: u16,
+    pub body: String,
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,167 @@
+//! `reverse-proxy`: a local address standing in for one SSSG-protected origin, for tools that
+//! cannot be pointed at a proxy. Every request is relayed to the origin with its clearance (see
+//! `relay`), and the origin's absolute URLs in `Location` headers and text bodies are rewritten to
+//! the local address, so links and redirects keep the client on it.
+
+use crate::relay::Relay;
+use crate::server::ClearanceService;
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION, ORIGIN, REFERER, SET_COOKIE};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::response::Response;
+use axum::Router;
+use log::{debug, warn};
+use std::sync::Arc;
+use url::Url;
+
+/// Relays requests to one origin and rewrites its URLs in the responses.
+pub struct ReverseProxy {
+    relay: Relay,
+    /// The origin requests go to, e.g. `https://kiwifarms.st`.
+    upstream: String,
+    /// The local address, for requests without a `Host` header, e.g. `http://127.0.0.1:8080`.
+    local: String,
+}
+
+impl ReverseProxy {
+    /// A proxy for `upstream`'s origin (its path is ignored), reached at `local`.
+    pub fn new(service: Arc<ClearanceService>, upstream: &Url, local: &Url) -> ReverseProxy {
+        ReverseProxy {
+            relay: Relay::new(service),
+            upstream: upstream.origin().unicode_serialization(),
+            local: local.origin().unicode_serialization(),
+        }
+    }
+
+    /// Every path and method, relayed.
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new().fallback(forward).with_state(self)
+    }
+}
+
+async fn forward(State(proxy): State<Arc<ReverseProxy>>, mut request: Request) -> Response {
+    // The address the client used, so that rewritten URLs work wherever it reached us from
+    let local = match request.headers().get(HOST).and_then(|v| v.to_str().ok()) {
+        Some(host) => format!("http://{}", host),
+        None => proxy.local.clone(),
+    };
+    let target = format!("{}{}", proxy.upstream, request.uri().path_and_query().map_or("/", |pq| pq.as_str()));
+    let url = match Url::parse(&target) {
+        Ok(url) => url,
+        Err(e) => return Response::builder().status(400).body(Body::from(format!("Invalid request target: {}\n", e))).unwrap_or_default(),
+    };
+    debug!("[REVERSE] {} {}", request.method(), url);
+
+    // Sites check these against their own origin, e.g. XenForo's CSRF protection
+    for name in [ORIGIN, REFERER] {
+        rewrite_header(request.headers_mut(), &name, &local, &proxy.upstream);
+    }
+    let response = proxy.relay.relay(request, url, true).await;
+    rewrite_response(response, &proxy.upstream, &local).await
+}
+
+/// Replaces `from` with `to` at the start of each `name` header.
+fn rewrite_header(headers: &mut HeaderMap, name: &HeaderName, from: &str, to: &str) {
+    let Some(rewritten) = headers.get(name).and_then(|v| v.to_str().ok()).and_then(|v| rewrite_url(v, from, to)) else {
+        return;
+    };
+    if let Ok(value) = HeaderValue::from_str(&rewritten) {
+        headers.insert(name.clone(), value);
+    }
+}
+
+/// `value` with the origin `from` replaced by `to`, if it is a URL on `from`.
+fn rewrite_url(value: &str, from: &str, to: &str) -> Option<String> {
+    let rest = value.strip_prefix(from)?;
+    // `https://site.example` must not match `https://site.example.evil`
+    if !(rest.is_empty() || rest.starts_with(['/', '?', '#'])) {
+        return None;
+    }
+    Some(format!("{}{}", to, rest))
+}
+
+/// Points the upstream's URLs in `response` at `local`: the `Location` header, cookies scoped to
+/// the upstream's domain, and absolute URLs in text bodies.
+async fn rewrite_response(response: Response, upstream: &str, local: &str) -> Response {
+    let (mut parts, body) = response.into_parts();
+    rewrite_header(&mut parts.headers, &LOCATION, upstream, local);
+    let cookies: Vec<HeaderValue> = parts.headers.get_all(SET_COOKIE).iter().filter_map(|v| local_cookie(v, local)).collect();
+    parts.headers.remove(SET_COOKIE);
+    for cookie in cookies {
+        parts.headers.append(SET_COOKIE, cookie);
+    }
+
+    let rewritable = parts.headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).is_some_and(is_text) && !parts.headers.contains_key(CONTENT_ENCODING);
+    if !rewritable {
+        return Response::from_parts(parts, body);
+    }
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            warn!("Cannot read the response from {}: {}", upstream, e);
+            return Response::builder().status(502).body(Body::from(format!("Cannot read the response: {}\n", e))).unwrap_or_default();
+        }
+    };
+    let text = rewrite_text(&String::from_utf8_lossy(&body), upstream, local);
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, Body::from(text))
+}
+
+/// Whether a body of `content_type` may hold URLs to rewrite: HTML, CSS, scripts, JSON, feeds...
+fn is_text(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase();
+    essence.starts_with("text/") || ["javascript", "json", "xml"].iter().any(|kind| essence.contains(kind))
+}
+
+/// `text` with absolute and protocol-relative URLs on `upstream` pointed at `local`, including
+/// their JSON-escaped forms (`https:\/\/site`).
+pub fn rewrite_text(text: &str, upstream: &str, local: &str) -> String {
+    let (Some((_, upstream_host)), Some((_, local_host))) = (upstream.split_once("://"), local.split_once("://")) else {
+        return text.to_string();
+    };
+    let mut out = text.to_string();
+    for (from, to) in [
+        (upstream.to_string(), local.to_string()),
+        (upstream.replace('/', "\\/"), local.replace('/', "\\/")),
+        (format!("//{}", upstream_host), format!("//{}", local_host)),
+        (format!("\\/\\/{}", upstream_host), format!("\\/\\/{}", local_host)),
+    ] {
+        out = replace_origin(&out, &from, &to);
+    }
+    out
+}
+
+/// Replaces `from` with `to` wherever it is not followed by more of a host name or port.
+fn replace_origin(text: &str, from: &str, to: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(at) = rest.find(from) {
+        let after = &rest[at + from.len()..];
+        let whole = !after.starts_with(|c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '_'));
+        out.push_str(&rest[..at]);
+        out.push_str(if whole { to } else { from });
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// A `Set-Cookie` value the client will store for `local`: without the upstream's `Domain`, and
+/// without `Secure` when `local` is plain HTTP.
+fn local_cookie(value: &HeaderValue, local: &str) -> Option<HeaderValue> {
+    let Ok(cookie) = value.to_str() else {
+        return Some(value.clone());
+    };
+    let plain = local.starts_with("http://");
+    // The first pair is the cookie itself, the rest its attributes
+    let kept: Vec<&str> = cookie.split(';')
+        .enumerate()
+        .filter(|(i, attribute)| {
+            let name = attribute.split('=').next().unwrap_or("").trim();
+            *i == 0 || !(name.eq_ignore_ascii_case("domain") || plain && name.eq_ignore_ascii_case("secure"))
+        })
+        .map(|(_, attribute)| attribute)
+        .collect();
+    HeaderValue::from_str(&kept.join(";")).ok()
+}
@@ -0,0 +1,313 @@
+//! `serve`: a local HTTP API handing out clearances, so programs in other languages can get one
+//! without bundling the binary. Clearances are kept in memory per origin until shortly before
+//! they expire, so repeated requests for the same origin are answered without touching the site.
+
+use crate::acquire::{self, AcquireError, Target};
+use crate::cache;
+use crate::config::{self, ConfigError};
+use crate::cookies::ExportedCookie;
+use crate::flaresolverr;
+use crate::network_client::NetworkError;
+use crate::single_flight::{FlightStats, SingleFlight};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::{info, warn};
+use reqwest::header::USER_AGENT;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+/// Where the settings for each origin come from: the config file, a profile forced for every
+/// origin, and command-line options on top.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsSource {
+    pub config: config::Config,
+    pub profile: Option<String>,
+    pub overrides: config::Profile,
+}
+
+impl SettingsSource {
+    pub fn settings(&self, origin: &str) -> Result<config::Profile, ConfigError> {
+        let (settings, _) = self.config.effective(Some(origin), self.profile.as_deref(), &self.overrides)?;
+        Ok(settings)
+    }
+}
+
+/// How long clearances are handed out from memory.
+#[derive(Debug, Clone, Copy)]
+pub struct ServiceOptions {
+    /// A clearance expiring sooner than this is replaced instead of handed out.
+    pub margin: Duration,
+    /// How long to hand out a clearance that is a session cookie before checking it again.
+    pub session_ttl: Duration,
+    /// Seed jars from the on-disk clearance cache and write new clearances to it.
+    pub use_cache: bool,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        ServiceOptions { margin: Duration::from_secs(60), session_ttl: Duration::from_secs(1800), use_cache: true }
+    }
+}
+
+/// A request for `POST /v1/clearance`.
+#[derive(Debug, Deserialize)]
+pub struct ClearanceRequest {
+    pub url: String,
+    /// Solve for a new clearance even if a live one is known.
+    #[serde(default)]
+    pub force: bool,
+}
+
+/// What `POST /v1/clearance` returns.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClearanceResponse {
+    pub url: String,
+    pub origin: String,
+    /// The `sssg_clearance` value.
+    pub clearance: String,
+    /// Every cookie in the origin's jar, clearance included.
+    pub cookies: Vec<ExportedCookie>,
+    /// The cookies to send to `url`, as a `Cookie` header value.
+    pub cookie_header: String,
+    /// The `User-Agent` the clearance was obtained with. SSSG may tie clearances to it.
+    pub user_agent: Option<String>,
+    /// Expiry, as seconds since the Unix epoch; `None` for a session cookie.
+    pub expires_at: Option<u64>,
+    pub expires_in_secs: Option<u64>,
+    /// True if the clearance was handed out from memory rather than obtained for this request.
+    pub cached: bool,
+}
+
+/// A clearance held in memory for an origin.
+struct LiveClearance {
+    response: ClearanceResponse,
+    /// When to stop handing it out.
+    until: Instant,
+}
+
+/// Obtains clearances and keeps the live ones in memory, per origin. Concurrent requests for an
+/// origin share one attempt to obtain its clearance.
+pub struct ClearanceService {
+    settings: SettingsSource,
+    options: ServiceOptions,
+    live: Mutex<HashMap<String, LiveClearance>>,
+    solves: SingleFlight<ClearanceResponse, AcquireError>,
+}
+
+impl ClearanceService {
+    pub fn new(settings: SettingsSource, options: ServiceOptions) -> ClearanceService {
+        ClearanceService { settings, options, live: Mutex::new(HashMap::new()), solves: SingleFlight::new() }
+    }
+
+    /// The settings requests to `origin` are made with.
+    pub fn settings(&self, origin: &str) -> Result<config::Profile, ConfigError> {
+        self.settings.settings(origin)
+    }
+
+    /// Number of origins with a clearance held in memory.
+    pub fn live_origins(&self) -> usize {
+        let now = Instant::now();
+        self.live.lock().map(|live| live.values().filter(|entry| entry.until > now).count()).unwrap_or(0)
+    }
+
+    /// How many clearances were obtained, and how many requests waited for one already being
+    /// obtained instead.
+    pub fn solve_stats(&self) -> FlightStats {
+        self.solves.stats()
+    }
+
+    /// A clearance for `url`'s origin: a live one from memory unless `force`, otherwise whatever
+    /// `acquire` gets, reusing the on-disk cache if enabled. While a clearance is being obtained
+    /// for the origin, further callers, forced or not, wait for it and share its outcome.
+    pub async fn clearance(&self, url: &Url, force: bool) -> Result<ClearanceResponse, AcquireError> {
+        let origin = url.origin().unicode_serialization();
+        if !force {
+            if let Some(response) = self.live(&origin) {
+                info!("Handing out the live clearance for {}", origin);
+                return Ok(ClearanceResponse { url: url.to_string(), ..response });
+            }
+        }
+        let response = self.solves.run(&origin, || self.obtain(url, &origin, force)).await
+            .map_err(|err| Arc::try_unwrap(err).unwrap_or_else(AcquireError::Shared))?;
+        Ok(ClearanceResponse { url: url.to_string(), ..response })
+    }
+
+    async fn obtain(&self, url: &Url, origin: &str, force: bool) -> Result<ClearanceResponse, AcquireError> {
+        let settings = self.settings.settings(origin)?;
+        let mut target = Target::new(url.clone(), settings, self.options.use_cache)?;
+        if force || self.expiring(target.cached_expires_at) {
+            target.forget_cached_clearance();
+        }
+        let acquired = acquire::acquire(vec![target], acquire::AcquireOptions::default()).await?;
+        let (value, expires_at) = match (&acquired.reused, &acquired.clearance) {
+            (Some(token), _) => (token.clone(), acquired.target.cached_expires_at),
+            (None, Some(clearance)) => (clearance.value().to_string(), clearance.expires_at),
+            (None, None) if acquired.nothing_to_clear => return Err(AcquireError::Internal(format!("{} serves the page without a challenge; there is no clearance to obtain", origin))),
+            (None, None) => return Err(AcquireError::Internal("no clearance obtained".to_string())),
+        };
+        self.remember(url, &acquired.target, value, expires_at)
+    }
+
+    /// A clearance for `url`'s origin that is already at hand, without touching the site: the live
+    /// one in memory, else an unexpired one in the on-disk cache if enabled.
+    pub fn cached_clearance(&self, url: &Url) -> Option<ClearanceResponse> {
+        let origin = url.origin().unicode_serialization();
+        if let Some(response) = self.live(&origin) {
+            return Some(ClearanceResponse { url: url.to_string(), ..response });
+        }
+        if !self.options.use_cache {
+            return None;
+        }
+        let settings = self.settings.settings(&origin).ok()?;
+        let entry = cache::load(&cache::cache_dir(settings.cache_dir.as_deref()), &origin).ok()??;
+        let target = Target::with_cookies(url.clone(), settings, entry.cookies, true).ok()?;
+        let value = target.cached_clearance.clone()?;
+        if self.expiring(target.cached_expires_at) {
+            return None;
+        }
+        info!("Using the cached clearance for {}", origin);
+        let response = self.remember(url, &target, value, target.cached_expires_at).ok()?;
+        Some(ClearanceResponse { cached: true, ..response })
+    }
+
+    fn expiring(&self, expires_at: Option<SystemTime>) -> bool {
+        expires_at.is_some_and(|at| at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO) <= self.options.margin)
+    }
+
+    /// Holds `value`, the clearance in `target`'s jar, in memory for `url`'s origin and returns it
+    /// as a response.
+    fn remember(&self, url: &Url, target: &Target, value: String, expires_at: Option<SystemTime>) -> Result<ClearanceResponse, AcquireError> {
+        let origin = url.origin().unicode_serialization();
+        let (cookies, cookie_header) = match target.cookie_store.lock() {
+            Ok(store) => {
+                let cookies = store.iter_unexpired().map(ExportedCookie::from_cookie).collect();
+                let pairs: Vec<String> = store.matches(url).into_iter().map(|c| format!("{}={}", c.name(), c.value())).collect();
+                (cookies, pairs.join("; "))
+            }
+            Err(e) => return Err(AcquireError::Internal(e.to_string())),
+        };
+        let response = ClearanceResponse {
+            url: url.to_string(),
+            origin: origin.clone(),
+            clearance: value,
+            cookies,
+            cookie_header,
+            user_agent: target.session.page_headers.get(USER_AGENT).and_then(|v| v.to_str().ok()).map(str::to_string),
+            expires_at: expires_at.map(unix_secs),
+            expires_in_secs: None,
+            cached: false,
+        };
+
+        // Hand it out until the margin before expiry, or for the session TTL if it has no expiry
+        let lifetime = match expires_at {
+            Some(at) => at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO).saturating_sub(self.options.margin),
+            None => self.options.session_ttl,
+        };
+        if let Ok(mut live) = self.live.lock() {
+            live.insert(origin, LiveClearance { response: response.clone(), until: Instant::now() + lifetime });
+        }
+        Ok(with_remaining(response))
+    }
+
+    /// The live clearance for `origin`, if there is one that is not due for replacement.
+    fn live(&self, origin: &str) -> Option<ClearanceResponse> {
+        let mut live = self.live.lock().ok()?;
+        match live.get(origin) {
+            Some(entry) if entry.until > Instant::now() => Some(with_remaining(ClearanceResponse { cached: true, ..entry.response.clone() })),
+            Some(_) => {
+                live.remove(origin);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+fn with_remaining(response: ClearanceResponse) -> ClearanceResponse {
+    let now = unix_secs(SystemTime::now());
+    ClearanceResponse { expires_in_secs: response.expires_at.map(|at| at.saturating_sub(now)), ..response }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// The API's routes:
+/// - `GET /health`: liveness, with the number of origins holding a live clearance.
+/// - `POST /v1/clearance`: `{"url": ..., "force": false}` to a `ClearanceResponse`.
+/// - `GET /` and `POST /v1`: FlareSolverr's protocol (see `flaresolverr`).
+pub fn router(service: Arc<ClearanceService>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/v1/clearance", post(post_clearance))
+        .with_state(Arc::clone(&service))
+        .merge(flaresolverr::router(service))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub status: String,
+    pub version: String,
+    pub live_origins: usize,
+    pub solves: FlightStats,
+}
+
+async fn health(State(service): State<Arc<ClearanceService>>) -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "ok".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        live_origins: service.live_origins(),
+        solves: service.solve_stats(),
+    })
+}
+
+async fn post_clearance(State(service): State<Arc<ClearanceService>>, request: Result<Json<ClearanceRequest>, JsonRejection>) -> Result<Json<ClearanceResponse>, ApiError> {
+    let Json(request) = request.map_err(|e| ApiError { status: e.status(), message: e.body_text() })?;
+    let url = Url::parse(&request.url).map_err(|e| ApiError { status: StatusCode::BAD_REQUEST, message: format!("Invalid url {:?}: {}", request.url, e) })?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(ApiError { status: StatusCode::BAD_REQUEST, message: format!("Unsupported URL scheme: {}", url.scheme()) });
+    }
+    match service.clearance(&url, request.force).await {
+        Ok(response) => Ok(Json(response)),
+        Err(e) => {
+            warn!("Clearance for {} failed: {}", url, e);
+            Err(ApiError::from(e))
+        }
+    }
+}
+
+/// An error answered as `{"error": message}`.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl From<AcquireError> for ApiError {
+    fn from(err: AcquireError) -> Self {
+        ApiError { status: error_status(&err), message: err.to_string() }
+    }
+}
+
+fn error_status(err: &AcquireError) -> StatusCode {
+    match err {
+        AcquireError::Network(NetworkError::Timeout { .. }) => StatusCode::GATEWAY_TIMEOUT,
+        AcquireError::Network(_) | AcquireError::Parse(_) | AcquireError::Solve(_) | AcquireError::NotCleared { .. } => StatusCode::BAD_GATEWAY,
+        AcquireError::UrlParse(_) => StatusCode::BAD_REQUEST,
+        AcquireError::Io(_) | AcquireError::Config(_) | AcquireError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        AcquireError::Shared(err) => error_status(err),
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(serde_json::json!({ "error": self.message }))).into_response()
+    }
+}
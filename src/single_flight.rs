@@ -0,0 +1,123 @@
+//! Coalescing concurrent work per key, so that a burst of requests to an uncleared origin costs
+//! one solve rather than one per request. The first caller for a key runs the work; callers
+//! arriving while it runs wait for its result, success or failure, instead of starting their own.
+
+use log::debug;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// What callers for a key wait on: `None` until the work is done. Errors are shared, since they
+/// go to every caller.
+type Outcome<T, E> = Option<Result<T, Arc<E>>>;
+
+/// Counts of what `SingleFlight::run` did, since it was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FlightStats {
+    /// Work started, i.e. callers that went first for their key.
+    pub started: u64,
+    /// Callers that got the result of work already running instead of starting their own.
+    pub deduplicated: u64,
+    /// Work that failed; each failure went to its waiters as well.
+    pub failed: u64,
+    /// Keys with work running now.
+    pub in_flight: usize,
+}
+
+/// Runs at most one piece of work per key at a time, handing its result to everyone who asked.
+pub struct SingleFlight<T, E> {
+    in_flight: Mutex<HashMap<String, watch::Receiver<Outcome<T, E>>>>,
+    started: AtomicU64,
+    deduplicated: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl<T, E> Default for SingleFlight<T, E> {
+    fn default() -> Self {
+        SingleFlight {
+            in_flight: Mutex::new(HashMap::new()),
+            started: AtomicU64::new(0),
+            deduplicated: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<T: Clone, E> SingleFlight<T, E> {
+    pub fn new() -> SingleFlight<T, E> {
+        SingleFlight::default()
+    }
+
+    /// Runs `work` for `key`, or, if work for `key` is already running, waits for its result. If
+    /// the caller running the work goes away before it finishes (its future is dropped), one of
+    /// the waiters starts it again.
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> Result<T, Arc<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let sender = loop {
+            let mut receiver = {
+                let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+                match in_flight.get(key) {
+                    Some(receiver) => receiver.clone(),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        in_flight.insert(key.to_string(), receiver);
+                        break sender;
+                    }
+                }
+            };
+            debug!("[FLIGHT] Waiting for the work already running for {}", key);
+            let outcome = receiver.wait_for(Option::is_some).await.ok().and_then(|outcome| outcome.clone());
+            if let Some(result) = outcome {
+                self.deduplicated.fetch_add(1, Ordering::Relaxed);
+                return result;
+            }
+            // The work was abandoned; go again, possibly first this time
+        };
+        self.lead(key, sender, work).await
+    }
+
+    async fn lead<F, Fut>(&self, key: &str, sender: watch::Sender<Outcome<T, E>>, work: F) -> Result<T, Arc<E>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        self.started.fetch_add(1, Ordering::Relaxed);
+        // Clears the key even if this future is dropped, so later callers don't wait on nothing
+        let _done = Done { flights: &self.in_flight, key };
+        let result = work().await.map_err(Arc::new);
+        if result.is_err() {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+        sender.send_replace(Some(result.clone()));
+        result
+    }
+
+    pub fn stats(&self) -> FlightStats {
+        FlightStats {
+            started: self.started.load(Ordering::Relaxed),
+            deduplicated: self.deduplicated.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            in_flight: self.in_flight.lock().map(|in_flight| in_flight.len()).unwrap_or(0),
+        }
+    }
+}
+
+/// Removes a key from the running work when dropped.
+struct Done<'a, T, E> {
+    flights: &'a Mutex<HashMap<String, watch::Receiver<Outcome<T, E>>>>,
+    key: &'a str,
+}
+
+impl<T, E> Drop for Done<'_, T, E> {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.flights.lock() {
+            in_flight.remove(self.key);
+        }
+    }
+}
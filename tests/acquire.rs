@@ -0,0 +1,274 @@
+//! Runs `acquire` against an in-process SSSG origin with a throwaway cache directory, and checks
+//! which clearances end up in the cache.
+
+mod common;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post, MethodRouter};
+use axum::{Json, Router};
+use common::Answers;
+use kiwifarms_captchabuster::acquire::{self, AcquireError, AcquireOptions, Target};
+use kiwifarms_captchabuster::{cache, config, report};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use url::Url;
+
+fn target(url: &str, cache_dir: &Path) -> Target {
+    let settings = config::Profile { cache_dir: Some(cache_dir.to_path_buf()), ..Default::default() };
+    Target::new(Url::parse(url).unwrap(), settings, true).unwrap()
+}
+
+#[tokio::test]
+async fn clearances_are_cached_once_they_get_the_page() {
+    let origin = common::spawn(common::origin().with_state(Answers::default())).await;
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("http://{}/", origin);
+
+    let acquired = acquire::acquire(vec![target(&url, dir.path())], AcquireOptions { fetch_page: true, max_rounds: 2 }).await.unwrap();
+    assert_eq!(acquired.page.unwrap().body, common::PAGE);
+    let entry = cache::load(dir.path(), &format!("http://{}", origin)).unwrap().expect("the clearance was not cached");
+    assert!(entry.cookies.iter().any(|c| c.name() == "sssg_clearance" && c.value() == "tok1"));
+
+    // The next run gets the page with the cached clearance
+    let acquired = acquire::acquire(vec![target(&url, dir.path())], AcquireOptions { fetch_page: true, max_rounds: 2 }).await.unwrap();
+    assert_eq!(acquired.reused.as_deref(), Some("tok1"));
+}
+
+/// An origin that hands out clearances, but never lets them through.
+async fn spawn_stubborn() -> std::net::SocketAddr {
+    let stubborn = Router::new()
+        .route("/", get(|| async { common::challenge() }))
+        .route("/.sssg/api/answer", post(common::answer))
+        .route("/.sssg/api/check", post(common::answer));
+    common::spawn(stubborn.with_state(Answers::default())).await
+}
+
+#[tokio::test]
+async fn clearances_still_challenged_are_not_cached() {
+    let origin = spawn_stubborn().await;
+    let dir = tempfile::tempdir().unwrap();
+    let url = format!("http://{}/", origin);
+
+    let result = acquire::acquire(vec![target(&url, dir.path())], AcquireOptions { fetch_page: true, max_rounds: 2 }).await;
+    assert!(matches!(result, Err(AcquireError::NotCleared { rounds: 2 })), "{:?}", result.err());
+    assert!(cache::load(dir.path(), &format!("http://{}", origin)).unwrap().is_none());
+
+    // Without fetching the page there is nothing to confirm it with, so it is cached as handed out
+    acquire::acquire(vec![target(&url, dir.path())], AcquireOptions { fetch_page: false, max_rounds: 2 }).await.unwrap();
+    assert!(cache::load(dir.path(), &format!("http://{}", origin)).unwrap().is_some());
+}
+
+/// An origin refusing every request.
+async fn spawn_blocked() -> std::net::SocketAddr {
+    common::spawn(Router::new().route("/threads/1", get(|| async { axum::http::StatusCode::FORBIDDEN }))).await
+}
+
+/// An origin whose challenge is harder than `max_difficulty` 8 allows.
+async fn spawn_too_hard() -> std::net::SocketAddr {
+    let hard = r#"<html><script>window.sssg_challenge("00ff00ff", 40, 60000);</script></html>"#;
+    common::spawn(Router::new().route("/threads/1", get(move || async move { axum::response::Html(hard) }))).await
+}
+
+/// A `Target` for `/threads/1` on each of `origins`, as `--mirror` would give them.
+fn mirrors(origins: &[std::net::SocketAddr], settings: &config::Profile) -> Vec<Target> {
+    origins.iter().map(|origin| {
+        let url = Url::parse(&format!("http://{}/threads/1", origin)).unwrap();
+        Target::new(url, settings.clone(), true).unwrap()
+    }).collect()
+}
+
+#[tokio::test]
+async fn mirrors_are_failed_over_in_order() {
+    let blocked = spawn_blocked().await;
+    let too_hard = spawn_too_hard().await;
+    let good = common::spawn(common::origin().with_state(Answers::default())).await;
+    let dir = tempfile::tempdir().unwrap();
+    let settings = config::Profile { cache_dir: Some(dir.path().to_path_buf()), max_difficulty: Some(8), ..Default::default() };
+
+    let acquired = acquire::acquire(mirrors(&[blocked, too_hard, good], &settings), AcquireOptions { fetch_page: true, max_rounds: 2 }).await.unwrap();
+    assert_eq!(acquired.target.origin, format!("http://{}", good));
+    assert_eq!(acquired.page.unwrap().body, common::PAGE);
+    let failed: Vec<_> = acquired.failed_mirrors.iter().map(|f| f.origin.clone()).collect();
+    assert_eq!(failed, [format!("http://{}", blocked), format!("http://{}", too_hard)]);
+    assert!(acquired.failed_mirrors[0].error.contains("blocked"), "{}", acquired.failed_mirrors[0].error);
+    assert!(acquired.failed_mirrors[1].error.contains("40"), "{}", acquired.failed_mirrors[1].error);
+
+    // The clearance belongs to the mirror that won, not to the URL's own origin
+    assert!(cache::load(dir.path(), &format!("http://{}", good)).unwrap().is_some());
+    assert!(cache::load(dir.path(), &format!("http://{}", blocked)).unwrap().is_none());
+
+    // With every mirror failing, the last one's error is returned
+    let result = acquire::acquire(mirrors(&[too_hard, blocked], &settings), AcquireOptions { fetch_page: true, max_rounds: 2 }).await;
+    assert!(result.as_ref().err().is_some_and(|e| e.to_string().contains("blocked")), "{:?}", result.err());
+}
+
+#[tokio::test]
+async fn raced_mirrors_use_the_first_to_answer() {
+    // The first mirror is good but slow, so the second wins the race
+    let slow = Router::new()
+        .route("/threads/1", get(|| async {
+            tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            common::challenge()
+        }));
+    let slow = common::spawn(slow).await;
+    let blocked = spawn_blocked().await;
+    let good = common::spawn(common::origin().with_state(Answers::default())).await;
+    let dir = tempfile::tempdir().unwrap();
+    let settings = config::Profile { cache_dir: Some(dir.path().to_path_buf()), race: Some(true), ..Default::default() };
+
+    let acquired = acquire::acquire(mirrors(&[slow, blocked, good], &settings), AcquireOptions { fetch_page: true, max_rounds: 2 }).await.unwrap();
+    assert_eq!(acquired.target.origin, format!("http://{}", good));
+    assert_eq!(acquired.page.unwrap().body, common::PAGE);
+    assert!(cache::load(dir.path(), &format!("http://{}", good)).unwrap().is_some());
+    assert!(cache::load(dir.path(), &format!("http://{}", slow)).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn unprotected_mirrors_have_nothing_to_clear() {
+    let blocked = spawn_blocked().await;
+    let open = common::spawn(Router::new().route("/threads/1", get(|| async { axum::response::Html(common::PAGE) }))).await;
+    let dir = tempfile::tempdir().unwrap();
+    let settings = config::Profile { cache_dir: Some(dir.path().to_path_buf()), ..Default::default() };
+
+    // A challenge that cannot be parsed is not taken for the page
+    let garbled = r#"<html><script>window.sssg_challenge(salt, "4");</script></html>"#;
+    let garbled = common::spawn(Router::new().route("/threads/1", get(move || async move { Html(garbled) }))).await;
+
+    let acquired = acquire::acquire(mirrors(&[blocked, garbled, open], &settings), AcquireOptions { fetch_page: true, max_rounds: 2 }).await.unwrap();
+    assert_eq!(acquired.target.origin, format!("http://{}", open));
+    assert_eq!(acquired.failed_mirrors.len(), 2);
+    assert!(acquired.failed_mirrors[1].error.contains("not found"), "{}", acquired.failed_mirrors[1].error);
+    assert!(acquired.nothing_to_clear);
+    assert!(acquired.clearance.is_none() && acquired.reused.is_none() && acquired.rounds.is_empty());
+    assert_eq!(acquired.page.unwrap().body, common::PAGE);
+    assert!(cache::load(dir.path(), &format!("http://{}", open)).unwrap().is_none());
+}
+
+/// `common::page`, counting the fetches in `fetches`.
+fn counted_page(fetches: &Arc<AtomicUsize>) -> MethodRouter<Answers> {
+    let fetches = Arc::clone(fetches);
+    get(move |state: State<Answers>, headers: HeaderMap| {
+        fetches.fetch_add(1, Ordering::SeqCst);
+        common::page(state, headers)
+    })
+}
+
+/// An origin whose `/answer` sets `answer_cookie` (or no cookie), and whose `/check` hands out a
+/// real clearance. Counts the page fetches and the `/check` calls.
+async fn spawn_check_origin(answer_cookie: Option<&'static str>) -> (std::net::SocketAddr, Arc<AtomicUsize>, Arc<AtomicUsize>) {
+    let (fetches, checks) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+    let answer = move || async move {
+        let mut response = Json(serde_json::json!({ "auth": "answered" })).into_response();
+        if let Some(cookie) = answer_cookie {
+            response.headers_mut().insert(header::SET_COOKIE, HeaderValue::from_static(cookie));
+        }
+        response
+    };
+    let counted = Arc::clone(&checks);
+    let check = move |state: State<Answers>| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        common::answer(state)
+    };
+    let router = Router::new()
+        .route("/", counted_page(&fetches))
+        .route("/.sssg/api/answer", post(answer))
+        .route("/.sssg/api/check", post(check));
+    (common::spawn(router.with_state(Answers::default())).await, fetches, checks)
+}
+
+/// Clears the origin with `--check auto` and returns the round's /check decision.
+async fn auto_check(origin: std::net::SocketAddr) -> report::CheckReport {
+    let settings = config::Profile { check: Some(config::CheckPolicy::Auto), ..Default::default() };
+    let target = Target::new(Url::parse(&format!("http://{}/", origin)).unwrap(), settings, false).unwrap();
+    let mut acquired = acquire::acquire(vec![target], AcquireOptions { fetch_page: true, max_rounds: 1 }).await.unwrap();
+    assert_eq!(acquired.page.unwrap().body, common::PAGE);
+    assert_eq!(acquired.rounds.len(), 1);
+    acquired.rounds.remove(0).check
+}
+
+#[tokio::test]
+async fn auto_check_is_called_without_an_answer_cookie() {
+    let (origin, _, checks) = spawn_check_origin(None).await;
+    let check = auto_check(origin).await;
+    assert!(check.called);
+    assert_eq!(check.reason, "/answer set no sssg_clearance cookie");
+    assert_eq!(checks.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn auto_check_is_called_for_an_expired_answer_cookie() {
+    let (origin, _, checks) = spawn_check_origin(Some("sssg_clearance=stale; Path=/; Max-Age=0")).await;
+    let check = auto_check(origin).await;
+    assert!(check.called);
+    assert_eq!(check.reason, "the /answer cookie is already expired");
+    assert_eq!(checks.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn auto_check_is_called_when_the_answer_cookie_is_challenged() {
+    let (origin, fetches, checks) = spawn_check_origin(Some("sssg_clearance=rejected; Path=/; Max-Age=3600")).await;
+    let check = auto_check(origin).await;
+    assert!(check.called);
+    assert_eq!(check.reason, "a test fetch with the /answer cookie was challenged");
+    assert_eq!(checks.load(Ordering::SeqCst), 1);
+    // The initial page, the test fetch, and the page with the /check clearance
+    assert_eq!(fetches.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn auto_check_is_skipped_when_the_answer_cookie_gets_the_page() {
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let router = Router::new().route("/", counted_page(&fetches)).route("/.sssg/api/answer", post(common::answer));
+    let origin = common::spawn(router.with_state(Answers::default())).await;
+
+    let check = auto_check(origin).await;
+    assert!(!check.called);
+    assert_eq!(check.reason, "a test fetch with the /answer cookie got the page");
+    // The test fetch is the page: it is not fetched a third time
+    assert_eq!(fetches.load(Ordering::SeqCst), 2);
+}
+
+/// Challenges again after the first clearance, and lets only the second through.
+async fn chained(State(answers): State<Answers>, headers: HeaderMap) -> Response {
+    let answered = answers.load(Ordering::SeqCst);
+    let cookies = headers.get(header::COOKIE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    if answered >= 2 && cookies.contains(&format!("sssg_clearance=tok{}", answered)) {
+        return Html(common::PAGE).into_response();
+    }
+    common::challenge()
+}
+
+#[tokio::test]
+async fn chained_challenges_are_solved_in_turn() {
+    let settings = config::Profile { check: Some(config::CheckPolicy::Never), ..Default::default() };
+    // A fresh origin each time, since the clearances it lets through depend on how many it handed out
+    let target = || async {
+        let router = Router::new().route("/", get(chained)).route("/.sssg/api/answer", post(common::answer));
+        let origin = common::spawn(router.with_state(Answers::default())).await;
+        Target::new(Url::parse(&format!("http://{}/", origin)).unwrap(), settings.clone(), false).unwrap()
+    };
+
+    let result = acquire::acquire(vec![target().await], AcquireOptions { fetch_page: true, max_rounds: 1 }).await;
+    assert!(matches!(result, Err(AcquireError::NotCleared { rounds: 1 })), "{:?}", result.err());
+
+    let acquired = acquire::acquire(vec![target().await], AcquireOptions { fetch_page: true, max_rounds: 3 }).await.unwrap();
+    assert_eq!(acquired.rounds.iter().map(|r| r.round).collect::<Vec<_>>(), [1, 2]);
+    assert_eq!(acquired.clearance.unwrap().value(), "tok2");
+    assert_eq!(acquired.page.unwrap().body, common::PAGE);
+}
+
+#[tokio::test]
+async fn endless_challenges_fail_after_max_rounds() {
+    let origin = spawn_stubborn().await;
+    let home = tempfile::tempdir().unwrap();
+    let url = format!("http://{}/", origin);
+
+    let output = common::run(home.path(), &["fetch", &url, "--no-cache", "--max-rounds", "3"]).await;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(5), "{}", stderr);
+    assert!(stderr.contains("after 3 round(s)"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+}
@@ -0,0 +1,86 @@
+//! Writes, imports and purges entries in a throwaway clearance cache directory.
+
+mod common;
+
+use cookie_store::{CookieExpiration, CookieStore};
+use kiwifarms_captchabuster::cache::{self, CacheEntry};
+use kiwifarms_captchabuster::{cookies, report};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const ORIGIN: &str = "https://sssg.test";
+
+fn store() -> CookieStore {
+    let mut store = CookieStore::default();
+    store.parse("sssg_clearance=tok1; Path=/; Max-Age=3600", &Url::parse(ORIGIN).unwrap()).unwrap();
+    store
+}
+
+#[test]
+fn imported_entries_keep_their_expiry_but_have_no_age() {
+    let dir = tempfile::tempdir().unwrap();
+    // As `cookies import` does it
+    let file = dir.path().join("cookies.txt");
+    std::fs::write(&file, "sssg.test\tFALSE\t/\tTRUE\t4102444800\tsssg_clearance\ttok1\n").unwrap();
+    let imported = cookies::store_from(cookies::import_file(&file).unwrap());
+    cache::save(dir.path(), &CacheEntry::imported(ORIGIN, &imported)).unwrap();
+
+    let entry = cache::load(dir.path(), ORIGIN).unwrap().unwrap();
+    assert_eq!(entry.obtained_at, None);
+    assert_eq!(entry.age(), None);
+    let expires = match &entry.cookies[0].expires {
+        CookieExpiration::AtUtc(at) => SystemTime::from(*at),
+        CookieExpiration::SessionEnd => panic!("the expiry was lost"),
+    };
+    assert_eq!(expires, UNIX_EPOCH + Duration::from_secs(4102444800));
+    assert_eq!(report::CachedOriginReport::from(&entry).obtained_at, None);
+
+    // A clearance solved here has an age
+    cache::save(dir.path(), &CacheEntry::new(ORIGIN, &store())).unwrap();
+    let age = cache::load(dir.path(), ORIGIN).unwrap().unwrap().age().unwrap();
+    assert!(age < Duration::from_secs(60), "{:?}", age);
+}
+
+#[test]
+fn purge_removes_unreadable_entries_and_nothing_else() {
+    let dir = tempfile::tempdir().unwrap();
+    cache::save(dir.path(), &CacheEntry::new(ORIGIN, &store())).unwrap();
+    std::fs::write(cache::cache_file(dir.path(), "http://broken.test"), "{ not json").unwrap();
+    std::fs::write(dir.path().join("settings.json"), "{}").unwrap();
+    assert_eq!(cache::list(dir.path()).unwrap().len(), 1);
+
+    assert_eq!(cache::purge(dir.path()).unwrap(), 2);
+    assert!(!cache::cache_file(dir.path(), "http://broken.test").exists());
+    assert!(cache::load(dir.path(), ORIGIN).unwrap().is_none());
+    assert!(dir.path().join("settings.json").exists());
+    assert_eq!(cache::purge(&dir.path().join("missing")).unwrap(), 0);
+}
+
+#[tokio::test]
+async fn importing_other_cookies_keeps_the_clearance_age() {
+    let home = tempfile::tempdir().unwrap();
+    let dir = home.path().join("clearances");
+    let mut entry = CacheEntry::new(ORIGIN, &store());
+    entry.obtained_at = Some(1_700_000_000);
+    cache::save(&dir, &entry).unwrap();
+    let import = |text: &'static str| {
+        let (home, dir) = (home.path().to_path_buf(), dir.clone());
+        async move {
+            let file = home.join("cookies.txt");
+            std::fs::write(&file, text).unwrap();
+            let output = common::run(&home, &["cookies", "import", ORIGIN, file.to_str().unwrap(), "--cache-dir", dir.to_str().unwrap()]).await;
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            cache::load(&dir, ORIGIN).unwrap().unwrap()
+        }
+    };
+
+    // A login cookie joins the clearance, which is as old as it was
+    let entry = import("sssg.test\tFALSE\t/\tTRUE\t4102444800\txf_session\tabc\n").await;
+    assert_eq!(entry.obtained_at, Some(1_700_000_000));
+    assert_eq!(entry.cookies.len(), 2);
+
+    // A clearance from elsewhere replaces it, and its age is unknown
+    let entry = import("sssg.test\tFALSE\t/\tTRUE\t4102444800\tsssg_clearance\ttok2\n").await;
+    assert_eq!(entry.obtained_at, None);
+    assert!(entry.cookies.iter().any(|c| c.name() == "sssg_clearance" && c.value() == "tok2"));
+}
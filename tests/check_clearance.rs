@@ -0,0 +1,88 @@
+//! Runs `check-clearance` against an in-process SSSG origin with clearances from the command line,
+//! a cookie file and the cache, and checks the status and exit code each one gets.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use common::Answers;
+use serde_json::Value;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// Runs `check-clearance` on `url` with `args` and JSON output, and returns the exit code and the
+/// check's report.
+async fn check(home: &Path, url: &str, args: &[&str]) -> (Option<i32>, Value) {
+    let mut all = vec!["check-clearance", url, "--output-format", "json"];
+    all.extend_from_slice(args);
+    let output = common::run(home, &all).await;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let report = serde_json::Deserializer::from_slice(&output.stdout).into_iter::<Value>().next();
+    (output.status.code(), report.unwrap_or_else(|| panic!("no report: {}", stderr)).unwrap())
+}
+
+/// A Netscape cookie file with one `sssg_clearance` for 127.0.0.1, expiring at `expires` (0 for
+/// a session cookie).
+fn cookie_file(dir: &Path, value: &str, expires: u64) -> String {
+    let path = dir.join("cookies.txt");
+    std::fs::write(&path, format!("127.0.0.1\tFALSE\t/\tFALSE\t{}\tsssg_clearance\t{}\n", expires, value)).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn statuses_map_to_exit_codes() {
+    let answers = Answers::default();
+    answers.store(1, Ordering::SeqCst); // tok1 was handed out
+    let origin = common::spawn(common::origin().with_state(answers)).await;
+    let url = format!("http://{}/", origin);
+    let home = tempfile::tempdir().unwrap();
+
+    let (code, report) = check(home.path(), &url, &["--clearance", "tok1"]).await;
+    assert_eq!((code, report["status"].as_str()), (Some(0), Some("valid")), "{}", report);
+    assert_eq!(report["value"], "tok1");
+
+    let (code, report) = check(home.path(), &url, &["--clearance", "tok9"]).await;
+    assert_eq!((code, report["status"].as_str()), (Some(11), Some("challenged")), "{}", report);
+
+    // An expired clearance is not sent at all, and the page is challenged without it
+    let cookies = cookie_file(home.path(), "tok1", 1);
+    let (code, report) = check(home.path(), &url, &["--cookies", &cookies]).await;
+    assert_eq!((code, report["status"].as_str()), (Some(11), Some("challenged")), "{}", report);
+    assert_eq!(report["value"], Value::Null);
+
+    let blocked = common::spawn(Router::new().route("/", get(|| async { StatusCode::FORBIDDEN }))).await;
+    let (code, report) = check(home.path(), &format!("http://{}/", blocked), &["--clearance", "tok1"]).await;
+    assert_eq!((code, report["status"].as_str()), (Some(5), Some("blocked")), "{}", report);
+    assert_eq!(report["http_status"], 403);
+}
+
+#[tokio::test]
+async fn clearance_sources_take_precedence_over_the_cache() {
+    let origin = common::spawn(common::origin().with_state(Answers::default())).await;
+    let url = format!("http://{}/", origin);
+    let home = tempfile::tempdir().unwrap();
+    let cache_dir = home.path().join("clearances");
+    let cache_dir = cache_dir.to_str().unwrap();
+
+    // Without a clearance anywhere there is nothing to send
+    let (code, report) = check(home.path(), &url, &["--cache-dir", cache_dir]).await;
+    assert_eq!((code, report["source"].as_str(), &report["value"]), (Some(11), Some("cache"), &Value::Null), "{}", report);
+
+    // `clear` caches tok1, which the cache then provides
+    let output = common::run(home.path(), &["clear", &url, "--cache-dir", cache_dir]).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let (code, report) = check(home.path(), &url, &["--cache-dir", cache_dir]).await;
+    assert_eq!((code, report["source"].as_str(), report["value"].as_str()), (Some(0), Some("cache"), Some("tok1")), "{}", report);
+
+    // --clearance and --cookies are used instead of the cache, even when it holds a better one
+    let (code, report) = check(home.path(), &url, &["--cache-dir", cache_dir, "--clearance", "tok9"]).await;
+    assert_eq!((code, report["source"].as_str(), report["value"].as_str()), (Some(11), Some("argument"), Some("tok9")), "{}", report);
+    let cookies = cookie_file(home.path(), "tok9", 0);
+    let (code, report) = check(home.path(), &url, &["--cache-dir", cache_dir, "--cookies", &cookies]).await;
+    assert_eq!((code, report["source"].as_str(), report["value"].as_str()), (Some(11), Some("file"), Some("tok9")), "{}", report);
+
+    // Only one of them can be given
+    let output = common::run(home.path(), &["check-clearance", &url, "--clearance", "tok1", "--cookies", &cookies]).await;
+    assert_eq!(output.status.code(), Some(2));
+}
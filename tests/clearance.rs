@@ -0,0 +1,79 @@
+//! Builds clearances from `Set-Cookie` headers like the ones `/answer` and `/check` send, and
+//! checks the attributes and expiry worked out from them.
+
+use kiwifarms_captchabuster::clearance::{Clearance, ClearanceSource, CookieAttributes};
+use reqwest::header::{HeaderMap, HeaderValue, DATE, SET_COOKIE};
+use std::time::{Duration, SystemTime};
+
+fn at(secs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+fn headers(set_cookies: &[&str], date: Option<SystemTime>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for set_cookie in set_cookies {
+        headers.append(SET_COOKIE, HeaderValue::from_str(set_cookie).unwrap());
+    }
+    if let Some(date) = date {
+        headers.insert(DATE, HeaderValue::from_str(&httpdate::fmt_http_date(date)).unwrap());
+    }
+    headers
+}
+
+#[test]
+fn max_age_wins_over_expires() {
+    let received_at = at(1_700_000_000);
+    let expires = httpdate::fmt_http_date(received_at + Duration::from_secs(86400));
+    let set_cookie = format!("sssg_clearance=tok1; Expires={}; Max-Age=3600; Path=/; Secure; HttpOnly; SameSite=Lax", expires);
+    let clearance = Clearance::from_response(&headers(&[&set_cookie], Some(received_at)), "tok1".to_string(), ClearanceSource::Answer, received_at);
+
+    let cookie = clearance.cookie.as_ref().unwrap();
+    assert_eq!(cookie.max_age, Some(Duration::from_secs(3600)));
+    assert_eq!(cookie.expires, Some(received_at + Duration::from_secs(86400)));
+    assert!(cookie.secure && cookie.http_only);
+    assert_eq!((cookie.path.as_deref(), cookie.same_site.as_deref()), (Some("/"), Some("Lax")));
+    assert_eq!(clearance.expires_at, Some(received_at + Duration::from_secs(3600)));
+}
+
+#[test]
+fn expires_is_measured_against_the_server_clock() {
+    // The server's clock runs 10 minutes ahead of ours
+    let received_at = at(1_700_000_000);
+    let server_date = received_at + Duration::from_secs(600);
+    let set_cookie = format!("sssg_clearance=tok1; Expires={}", httpdate::fmt_http_date(server_date + Duration::from_secs(1800)));
+    let clearance = Clearance::from_response(&headers(&[&set_cookie], Some(server_date)), "tok1".to_string(), ClearanceSource::Check, received_at);
+    assert_eq!(clearance.server_date, Some(server_date));
+    assert_eq!(clearance.expires_at, Some(received_at + Duration::from_secs(1800)));
+
+    // A negative Max-Age means already expired
+    let attributes = CookieAttributes::parse("sssg_clearance=tok1; Max-Age=-1").unwrap();
+    assert_eq!(attributes.lifetime(None, received_at), Some(Duration::ZERO));
+}
+
+#[test]
+fn malformed_or_missing_attributes_make_a_session_cookie() {
+    let received_at = at(1_700_000_000);
+    for set_cookie in ["sssg_clearance=tok1; Expires=next tuesday; Path=/", "sssg_clearance=tok1"] {
+        let clearance = Clearance::from_response(&headers(&[set_cookie], None), "tok1".to_string(), ClearanceSource::Answer, received_at);
+        let cookie = clearance.cookie.as_ref().unwrap();
+        assert_eq!((cookie.expires, cookie.max_age), (None, None), "{}", set_cookie);
+        assert!(!cookie.secure && !cookie.http_only && cookie.domain.is_none() && cookie.same_site.is_none(), "{}", set_cookie);
+        assert_eq!(clearance.expires_at, None, "{}", set_cookie);
+        assert_eq!(clearance.remaining(), None, "{}", set_cookie);
+    }
+    assert!(CookieAttributes::parse("no equals sign").is_none());
+}
+
+#[test]
+fn responses_without_the_clearance_cookie_fall_back_to_the_token() {
+    let received_at = at(1_700_000_000);
+    let clearance = Clearance::from_response(&headers(&["xf_session=abc; Max-Age=60", "garbage"], None), "auth-token".to_string(), ClearanceSource::Answer, received_at);
+    assert!(clearance.cookie.is_none());
+    assert_eq!(clearance.value(), "auth-token");
+    assert_eq!(clearance.expires_at, None);
+
+    // With several cookies, the clearance is the one picked
+    let clearance = Clearance::from_response(&headers(&["xf_session=abc; Max-Age=60", "sssg_clearance=tok2; Max-Age=120"], None), "auth-token".to_string(), ClearanceSource::Answer, received_at);
+    assert_eq!(clearance.value(), "tok2");
+    assert_eq!(clearance.expires_at, Some(received_at + Duration::from_secs(120)));
+}
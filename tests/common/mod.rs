@@ -0,0 +1,180 @@
+//! An in-process SSSG origin for the tests that need something to clear: pages are challenged
+//! until the request carries a clearance the origin handed out, and `/.sssg/api/answer` hands out
+//! `tok1`, `tok2`... for any solution.
+
+// Each test file uses its own part of this
+#![allow(dead_code)]
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+pub const PAGE: &str = "<html><body>page</body></html>";
+
+/// Counts the solutions the origin accepted; the n-th is answered with clearance `tokn`.
+pub type Answers = Arc<AtomicUsize>;
+
+/// Whether `headers` carry a clearance the origin handed out.
+pub fn cleared(answers: &Answers, headers: &HeaderMap) -> bool {
+    let cookies = headers.get(header::COOKIE).and_then(|v| v.to_str().ok()).unwrap_or("");
+    (1..=answers.load(Ordering::SeqCst)).any(|n| cookies.contains(&format!("sssg_clearance=tok{}", n)))
+}
+
+pub fn challenge() -> Response {
+    ([(header::CONTENT_TYPE, "text/html")], r#"<html><script>window.sssg_challenge("00ff00ff", 4, 60000);</script></html>"#).into_response()
+}
+
+/// `PAGE` once cleared, the challenge before.
+pub async fn page(State(answers): State<Answers>, headers: HeaderMap) -> Response {
+    if cleared(&answers, &headers) {
+        return ([(header::CONTENT_TYPE, "text/html")], PAGE).into_response();
+    }
+    challenge()
+}
+
+/// Accepts any solution with the next clearance.
+pub async fn answer(State(answers): State<Answers>) -> Response {
+    let token = format!("tok{}", answers.fetch_add(1, Ordering::SeqCst) + 1);
+    let cookie = format!("sssg_clearance={}; Path=/; Max-Age=3600; HttpOnly", token);
+    ([(header::CONTENT_TYPE, "application/json".to_string()), (header::SET_COOKIE, cookie)], format!(r#"{{"auth":"{}"}}"#, token)).into_response()
+}
+
+/// The origin's routes: `page` at `/` and `/threads/1`, and the answer endpoint. Tests add their
+/// own routes before giving it its state.
+pub fn origin() -> Router<Answers> {
+    Router::new()
+        .route("/", get(page))
+        .route("/threads/1", get(page))
+        .route("/.sssg/api/answer", post(answer))
+}
+
+/// Serves `router` over plain HTTP on a free local port.
+pub async fn spawn(router: Router) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    addr
+}
+
+/// What a test proxy was asked for, in order.
+pub type Log = Arc<Mutex<Vec<String>>>;
+
+/// A SOCKS5 proxy (no authentication, CONNECT only) that logs the requested destination as
+/// `host:port` and tunnels every connection to `target`, whatever was asked for.
+pub async fn spawn_socks5_proxy(target: SocketAddr) -> (SocketAddr, Log) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log = Log::default();
+    let destinations = Arc::clone(&log);
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let destinations = Arc::clone(&destinations);
+            tokio::spawn(async move {
+                // Greeting: version, method count, methods. Pick "no authentication".
+                let mut greeting = [0u8; 2];
+                client.read_exact(&mut greeting).await.unwrap();
+                let mut methods = vec![0u8; greeting[1] as usize];
+                client.read_exact(&mut methods).await.unwrap();
+                client.write_all(&[5, 0]).await.unwrap();
+
+                // Request: version, CONNECT, reserved, address type, address, port.
+                let mut request = [0u8; 4];
+                client.read_exact(&mut request).await.unwrap();
+                assert_eq!(request[1], 1, "only CONNECT is supported");
+                let host = match request[3] {
+                    1 => {
+                        let mut ip = [0u8; 4];
+                        client.read_exact(&mut ip).await.unwrap();
+                        std::net::Ipv4Addr::from(ip).to_string()
+                    }
+                    3 => {
+                        let len = client.read_u8().await.unwrap();
+                        let mut name = vec![0u8; len as usize];
+                        client.read_exact(&mut name).await.unwrap();
+                        String::from_utf8(name).unwrap()
+                    }
+                    4 => {
+                        let mut ip = [0u8; 16];
+                        client.read_exact(&mut ip).await.unwrap();
+                        std::net::Ipv6Addr::from(ip).to_string()
+                    }
+                    other => panic!("unknown address type {}", other),
+                };
+                let port = client.read_u16().await.unwrap();
+                destinations.lock().unwrap().push(format!("{}:{}", host, port));
+
+                let mut upstream = TcpStream::connect(target).await.unwrap();
+                client.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]).await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    });
+    (addr, log)
+}
+
+/// An HTTP proxy that only does `CONNECT`: it logs each request line and tunnels the connection
+/// to `target`, whatever was asked for.
+pub async fn spawn_connect_proxy(target: SocketAddr) -> (SocketAddr, Log) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log = Log::default();
+    let requests = Arc::clone(&log);
+    tokio::spawn(async move {
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            let requests = Arc::clone(&requests);
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    head.push(client.read_u8().await.unwrap());
+                }
+                let line = String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string();
+                requests.lock().unwrap().push(line);
+
+                let mut upstream = TcpStream::connect(target).await.unwrap();
+                client.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    });
+    (addr, log)
+}
+
+/// A listener that accepts connections and never answers, holding them open until the test ends.
+pub async fn spawn_silent() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            held.push(stream);
+        }
+    });
+    addr
+}
+
+/// Runs the command-line tool with `args`, its config and cache directories in `home` and no
+/// proxy from the environment.
+pub async fn run(home: &std::path::Path, args: &[&str]) -> std::process::Output {
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_kiwifarms-captchabuster"));
+    command
+        .args(args)
+        .env("HOME", home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_CONFIG_DIRS", home.join("config-dirs"))
+        .env("XDG_CACHE_HOME", home.join("cache"))
+        .env("XDG_DATA_HOME", home.join("data"));
+    for var in ["HTTP_PROXY", "HTTPS_PROXY", "ALL_PROXY", "NO_PROXY", "http_proxy", "https_proxy", "all_proxy", "no_proxy", "RUST_LOG"] {
+        command.env_remove(var);
+    }
+    tokio::task::spawn_blocking(move || command.output().unwrap()).await.unwrap()
+}
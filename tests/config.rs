@@ -0,0 +1,111 @@
+//! Runs `config show` on config files written to a throwaway directory, and checks how
+//! `[defaults]`, the matching profile and command-line options are merged.
+
+mod common;
+
+use serde_json::{json, Value};
+use std::path::Path;
+
+const CONFIG: &str = r#"
+[defaults]
+threads = 4
+max_attempts = 6
+timeout = 60
+headers = { "User-Agent" = "defaults", "Accept-Language" = "en-GB" }
+resolve = ["sssg.test:443:127.0.0.1", "other.test:443:127.0.0.2"]
+
+[profile.kiwifarms]
+origins = ["https://kiwifarms.st/"]
+max_attempts = 8
+check = "always"
+headers = { "user-agent" = "profile", "X-Profile" = "1" }
+resolve = ["SSSG.test:443:127.0.0.3"]
+
+[profile.other]
+origins = ["https://example.com"]
+threads = 2
+"#;
+
+/// Runs `config show` with `args` and JSON output, failing the test if it does not succeed.
+async fn show(home: &Path, args: &[&str]) -> Value {
+    let mut all = vec!["config", "show", "--output-format", "json"];
+    all.extend_from_slice(args);
+    let output = common::run(home, &all).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+fn write_config(home: &Path, text: &str) -> String {
+    let path = home.join("config.toml");
+    std::fs::write(&path, text).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn defaults_then_profile_then_command_line() {
+    let home = tempfile::tempdir().unwrap();
+    let config = write_config(home.path(), CONFIG);
+
+    // Another origin only gets the defaults
+    let shown = show(home.path(), &["--config", &config, "https://unlisted.example/"]).await;
+    assert_eq!(shown["profile"], Value::Null);
+    let settings = &shown["settings"];
+    assert_eq!((&settings["threads"], &settings["max_attempts"], &settings["check"]), (&json!(4), &json!(6), &Value::Null));
+
+    // The profile listing the origin overrides the defaults, and the command line overrides both
+    let shown = show(home.path(), &["--config", &config, "https://kiwifarms.st/threads/1", "--max-attempts", "9"]).await;
+    assert_eq!(shown["profile"], "kiwifarms");
+    let settings = &shown["settings"];
+    assert_eq!((&settings["threads"], &settings["max_attempts"], &settings["timeout"]), (&json!(4), &json!(9), &json!(60)));
+    assert_eq!(settings["check"], "always");
+    assert_eq!(settings["origins"], Value::Null);
+}
+
+#[tokio::test]
+async fn headers_merge_by_name_and_resolve_by_host() {
+    let home = tempfile::tempdir().unwrap();
+    let config = write_config(home.path(), CONFIG);
+
+    let args = ["--config", &config, "https://kiwifarms.st/", "-H", "X-Profile: cli", "-H", "Accept-Language:", "--resolve", "other.test:443:127.0.0.4"];
+    let settings = show(home.path(), &args).await["settings"].clone();
+    // Names are matched case-insensitively, the later layer's spelling and value winning
+    assert_eq!(settings["headers"], json!({ "user-agent": "profile", "X-Profile": "cli", "Accept-Language": "" }));
+    assert_eq!(settings["resolve"], json!(["SSSG.test:443:127.0.0.3", "other.test:443:127.0.0.4"]));
+}
+
+#[tokio::test]
+async fn named_profiles_override_origin_matching() {
+    let home = tempfile::tempdir().unwrap();
+    let config = write_config(home.path(), CONFIG);
+
+    let shown = show(home.path(), &["--config", &config, "--profile", "other", "https://kiwifarms.st/"]).await;
+    assert_eq!(shown["profile"], "other");
+    assert_eq!((&shown["settings"]["threads"], &shown["settings"]["check"]), (&json!(2), &Value::Null));
+
+    let output = common::run(home.path(), &["config", "show", "--config", &config, "--profile", "missing"]).await;
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("No profile named 'missing'"));
+}
+
+#[tokio::test]
+async fn config_files_are_found_in_the_config_directory() {
+    let home = tempfile::tempdir().unwrap();
+    let dir = home.path().join("config/kiwifarms-captchabuster");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("config.toml"), CONFIG).unwrap();
+
+    let shown = show(home.path(), &["https://kiwifarms.st/"]).await;
+    assert_eq!(shown["file"], dir.join("config.toml").to_str().unwrap());
+    assert_eq!(shown["profile"], "kiwifarms");
+}
+
+#[tokio::test]
+async fn unknown_keys_are_rejected() {
+    let home = tempfile::tempdir().unwrap();
+    for text in ["[defaults]\nthread = 4\n", "[profile.kiwifarms]\norigin = [\"https://kiwifarms.st\"]\n", "[default]\nthreads = 4\n"] {
+        let config = write_config(home.path(), text);
+        let output = common::run(home.path(), &["config", "show", "--config", &config]).await;
+        assert_eq!(output.status.code(), Some(2), "{}", text);
+        assert!(String::from_utf8_lossy(&output.stderr).contains("unknown field"), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}
@@ -0,0 +1,150 @@
+//! Exports a cookie jar in every format and reads it back the way the consuming tool would,
+//! checking that host-only cookies stay host-only and domain cookies keep their subdomains, and
+//! imports hand-written cookie files, well-formed or not. Also runs `fetch --export-cookies`
+//! against an in-process SSSG origin.
+
+mod common;
+
+use common::Answers;
+use cookie_store::{CookieDomain, CookieStore};
+use kiwifarms_captchabuster::cookies::{self, CookieFormat};
+use url::Url;
+
+const HOST: &str = "https://sssg.test/";
+const SUBDOMAIN: &str = "https://forum.sssg.test/";
+
+/// A jar with a host-only clearance and a domain-wide session cookie, both from `HOST`.
+fn jar() -> CookieStore {
+    let url = Url::parse(HOST).unwrap();
+    let mut store = CookieStore::default();
+    store.parse("sssg_clearance=tok1; Path=/; Max-Age=3600; Secure; HttpOnly", &url).unwrap();
+    store.parse("xf_session=abc; Domain=sssg.test; Path=/", &url).unwrap();
+    store
+}
+
+/// Which of the jar's cookies `store` sends to `url`.
+fn sent(store: &CookieStore, url: &str) -> Vec<String> {
+    let mut names: Vec<String> = store.matches(&Url::parse(url).unwrap()).iter().map(|c| c.name().to_string()).collect();
+    names.sort();
+    names
+}
+
+fn assert_scopes(store: &CookieStore, format: &str) {
+    assert_eq!(sent(store, HOST), ["sssg_clearance", "xf_session"], "{}", format);
+    assert_eq!(sent(store, SUBDOMAIN), ["xf_session"], "{}", format);
+    let clearance = store.get("sssg.test", "/", "sssg_clearance").unwrap_or_else(|| panic!("{}: no clearance", format));
+    assert!(matches!(clearance.domain, CookieDomain::HostOnly(_)), "{}: {:?}", format, clearance.domain);
+    assert!(clearance.secure().unwrap_or(false) && clearance.http_only().unwrap_or(false), "{}", format);
+}
+
+/// Writes `format` to a file and imports it again.
+fn reimport(store: &CookieStore, format: CookieFormat) -> CookieStore {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookies");
+    std::fs::write(&path, cookies::export(store, format, &Url::parse(HOST).unwrap())).unwrap();
+    cookies::store_from(cookies::import_file(&path).unwrap())
+}
+
+#[test]
+fn netscape_and_json_round_trip() {
+    let store = jar();
+    assert_scopes(&store, "original");
+    assert_scopes(&reimport(&store, CookieFormat::Netscape), "netscape");
+    assert_scopes(&reimport(&store, CookieFormat::Json), "json");
+}
+
+#[test]
+fn curl_keeps_host_only_cookies_to_their_host() {
+    let exported = cookies::export(&jar(), CookieFormat::Curl, &Url::parse(HOST).unwrap());
+    let clearance = exported.lines().find(|line| line.contains("sssg_clearance=")).unwrap();
+    assert!(!clearance.contains("Domain="), "{}", clearance);
+    assert!(exported.lines().any(|line| line.contains("xf_session=") && line.contains("Domain=sssg.test")), "{}", exported);
+
+    // Read back as curl does, as if `HOST` had sent the lines
+    let mut store = CookieStore::default();
+    for line in exported.lines() {
+        store.parse(line.strip_prefix("Set-Cookie: ").unwrap(), &Url::parse(HOST).unwrap()).unwrap();
+    }
+    assert_scopes(&store, "curl");
+}
+
+#[test]
+fn header_holds_the_cookies_for_the_url() {
+    let store = jar();
+    let header = cookies::export(&store, CookieFormat::Header, &Url::parse(&format!("{}threads/1", HOST)).unwrap());
+    assert!(header.starts_with("Cookie: ") && header.contains("sssg_clearance=tok1") && header.contains("xf_session=abc"), "{}", header);
+    assert_eq!(cookies::export(&store, CookieFormat::Header, &Url::parse(SUBDOMAIN).unwrap()), "Cookie: xf_session=abc\n");
+}
+
+fn import(text: &str) -> std::io::Result<Vec<cookie_store::Cookie<'static>>> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookies.txt");
+    std::fs::write(&path, text).unwrap();
+    cookies::import_file(&path)
+}
+
+#[test]
+fn netscape_files_keep_their_flags() {
+    let text = "# Netscape HTTP Cookie File\n\n\
+        #HttpOnly_sssg.test\tFALSE\t/\tTRUE\t4102444800\tsssg_clearance\ttok1\n\
+        .sssg.test\tTRUE\t/\tFALSE\t0\txf_session\tabc\r\n\
+        # a comment\n";
+    let imported = import(text).unwrap();
+    assert_eq!(imported.len(), 2);
+    let clearance = imported.iter().find(|c| c.name() == "sssg_clearance").unwrap();
+    assert!(matches!(clearance.domain, CookieDomain::HostOnly(_)), "{:?}", clearance.domain);
+    assert!(clearance.http_only().unwrap_or(false) && clearance.secure().unwrap_or(false));
+    assert!(clearance.expires_datetime().is_some());
+
+    let session = imported.iter().find(|c| c.name() == "xf_session").unwrap();
+    assert!(matches!(session.domain, CookieDomain::Suffix(_)), "{:?}", session.domain);
+    assert!(!session.http_only().unwrap_or(false) && !session.secure().unwrap_or(false));
+    // Expiry 0 marks a session cookie
+    assert!(!session.is_persistent());
+
+    let store = cookies::store_from(imported);
+    assert_eq!(sent(&store, SUBDOMAIN), ["xf_session"]);
+}
+
+#[test]
+fn cookies_without_secure_still_go_to_http_origins() {
+    // Converted through an https URL on import, but that must not make them https-only
+    let imported = import("sssg.test\tFALSE\t/\tFALSE\t0\tsssg_clearance\ttok1\n").unwrap();
+    let store = cookies::store_from(imported);
+    assert_eq!(sent(&store, "http://sssg.test/threads/1"), ["sssg_clearance"]);
+    assert!(sent(&store, "http://other.test/").is_empty());
+
+    let imported = import(r#"[{"name": "sssg_clearance", "value": "tok1", "domain": "sssg.test", "host_only": true, "path": "/", "secure": false, "http_only": false, "expires": null}]"#).unwrap();
+    assert_eq!(sent(&cookies::store_from(imported), "http://sssg.test/"), ["sssg_clearance"]);
+}
+
+#[test]
+fn malformed_netscape_lines_are_reported() {
+    for (text, expected) in [
+        ("sssg.test\tFALSE\t/\tFALSE\tsssg_clearance\ttok1\n", "line 1: expected 7 tab-separated fields, found 6"),
+        ("# header\nsssg.test\tFALSE\t/\tFALSE\tsoon\tsssg_clearance\ttok1\n", "line 2: bad expiry 'soon'"),
+        ("sssg.test FALSE / FALSE 0 sssg_clearance tok1\n", "line 1: expected 7 tab-separated fields, found 1"),
+    ] {
+        let error = import(text).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        assert!(error.to_string().ends_with(expected), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn fetch_keeps_stdout_for_the_page() {
+    let origin = common::spawn(common::origin().with_state(Answers::default())).await;
+    let url = format!("http://{}/", origin);
+    let home = tempfile::tempdir().unwrap();
+
+    // The cookies would end up next to the page
+    let output = common::run(home.path(), &["fetch", &url, "--no-cache", "--export-cookies", "header"]).await;
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--export-cookies needs --output"));
+
+    let path = home.path().join("cookie.txt");
+    let output = common::run(home.path(), &["fetch", &url, "--no-cache", "--export-cookies", "header", "--output", path.to_str().unwrap()]).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8_lossy(&output.stdout).trim_end(), common::PAGE);
+    assert_eq!(std::fs::read_to_string(&path).unwrap().trim_end(), "Cookie: sssg_clearance=tok1");
+}
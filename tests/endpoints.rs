@@ -0,0 +1,81 @@
+//! Runs endpoint discovery on challenge pages and scripts shaped like SSSG's, and checks what it
+//! picks up, what it leaves to the defaults, and that it never leaves the target's origin.
+
+use kiwifarms_captchabuster::html_parser;
+use kiwifarms_captchabuster::network_client::SssgEndpoints;
+use url::Url;
+
+const CHALLENGE: &str = r#"window.sssg_challenge("00ff00ff", 4, 60000);"#;
+
+const SCRIPT: &str = r#"
+    async function submit(salt, attempt) {
+        const r = await fetch("/.guard/v2/answer", { method: "POST", body: "s=" + salt + "&n=" + attempt });
+        const { auth } = await r.json();
+        const body = new URLSearchParams();
+        body.append("tok", auth);
+        return fetch('/.guard/v2/check', { method: "POST", body });
+    }
+"#;
+
+fn page(scripts: &str) -> String {
+    format!("<html><head>{}</head><body><script>{}</script></body></html>", scripts, CHALLENGE)
+}
+
+fn base() -> Url {
+    Url::parse("https://sssg.test/threads/1").unwrap()
+}
+
+#[test]
+fn inline_paths_and_fields_are_found() {
+    let html = page(&format!("<script>{}</script>", SCRIPT));
+    let discovered = html_parser::discover_endpoints(&html).same_origin(&base());
+    assert_eq!(discovered.answer_path.as_deref(), Some("/.guard/v2/answer"));
+    assert_eq!(discovered.check_path.as_deref(), Some("/.guard/v2/check"));
+    assert_eq!((discovered.salt_field.as_deref(), discovered.attempt_field.as_deref()), (Some("s"), Some("n")));
+    assert_eq!(discovered.token_field.as_deref(), Some("tok"));
+    // Nothing to fetch when the page gives everything away
+    assert!(discovered.has_paths());
+}
+
+#[test]
+fn scripts_are_found_when_the_page_only_references_them() {
+    let html = page(r#"<script src="/js/analytics.js"></script><script src="/.sssg/sssg-challenge.js?v=3"></script>"#);
+    let discovered = html_parser::discover_endpoints(&html);
+    assert!(discovered.answer_path.is_none() && discovered.check_path.is_none());
+    assert_eq!(html_parser::extract_challenge_script_srcs(&html), vec!["/.sssg/sssg-challenge.js?v=3".to_string()]);
+
+    let discovered = html_parser::discover_endpoints_in_script(SCRIPT).same_origin(&base());
+    assert_eq!(discovered.answer_path.as_deref(), Some("/.guard/v2/answer"));
+    assert_eq!(discovered.token_field.as_deref(), Some("tok"));
+}
+
+#[test]
+fn pages_without_hints_use_the_defaults() {
+    let html = page("");
+    let discovered = html_parser::discover_endpoints(&html);
+    assert!(discovered.answer_path.is_none() && discovered.check_path.is_none() && discovered.salt_field.is_none() && discovered.token_field.is_none());
+    assert!(html_parser::extract_challenge_script_srcs(&html).is_empty());
+
+    let mut endpoints = SssgEndpoints::default();
+    endpoints.merge(&discovered);
+    assert_eq!(endpoints.answer_url(&base()).unwrap().as_str(), "https://sssg.test/.sssg/api/answer");
+    assert_eq!(endpoints.check_url(&base()).unwrap().as_str(), "https://sssg.test/.sssg/api/check");
+}
+
+#[test]
+fn endpoints_on_other_origins_are_dropped() {
+    let script = r#"
+        fetch("https://collector.example/api/answer", { body: "a=" + salt + "&b=" + attempt });
+        fetch("//collector.example/api/check", { body: "f=" + token });
+    "#;
+    let discovered = html_parser::discover_endpoints_in_script(script);
+    assert!(discovered.answer_path.is_some() && discovered.check_path.is_some());
+    let discovered = discovered.same_origin(&base());
+    assert!(discovered.answer_path.is_none() && discovered.check_path.is_none());
+    assert!(discovered.salt_field.is_none() && discovered.attempt_field.is_none() && discovered.token_field.is_none());
+
+    // The target's own origin, spelled out in full, is kept
+    let script = r#"fetch("https://sssg.test/.sssg/api/answer", { body: "a=" + salt + "&b=" + attempt });"#;
+    let discovered = html_parser::discover_endpoints_in_script(script).same_origin(&base());
+    assert_eq!(discovered.answer_path.as_deref(), Some("https://sssg.test/.sssg/api/answer"));
+}
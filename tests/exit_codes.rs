@@ -0,0 +1,68 @@
+//! Runs the command-line tool into each failure in the README's exit code table, against
+//! in-process origins, and checks the code it exits with.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::Router;
+use common::Answers;
+
+fn page(html: &'static str) -> Router {
+    Router::new().route("/", get(move || async move { Html(html) }))
+}
+
+/// A challenge of `difficulty` 32, which takes far longer than a test to solve.
+const HARD: &str = r#"<html><script>window.sssg_challenge("00ff00ff", 32, 60000);</script></html>"#;
+
+#[tokio::test]
+async fn exit_codes_match_the_readme() {
+    let rejecting = || Router::new()
+        .route("/", get(|| async { common::challenge() }))
+        .route("/.sssg/api/answer", post(|| async { (StatusCode::BAD_REQUEST, "wrong answer") }));
+    let unchecked = Router::new()
+        .route("/", get(common::page))
+        .route("/.sssg/api/answer", post(common::answer))
+        .route("/.sssg/api/check", post(|| async { (StatusCode::BAD_REQUEST, "bad token") }))
+        .with_state(Answers::default());
+    let stubborn = Router::new()
+        .route("/", get(|| async { common::challenge() }))
+        .route("/.sssg/api/answer", post(common::answer))
+        .with_state(Answers::default());
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    // `URL` is replaced with the origin's URL; without an origin, nothing listens there
+    let cases: Vec<(&str, Option<Router>, Vec<&str>, i32)> = vec![
+        ("cleared", Some(common::origin().with_state(Answers::default())), vec!["clear", "URL"], 0),
+        ("json export without --output", None, vec!["clear", "URL", "--output-format", "json", "--export-cookies", "json"], 1),
+        ("fetch export without --output", None, vec!["fetch", "URL", "--export-cookies", "json"], 1),
+        ("bad URL", None, vec!["clear", "not a url"], 2),
+        ("bad header", None, vec!["clear", "URL", "-H", "no colon"], 2),
+        ("unknown option", None, vec!["clear", "URL", "--no-such-option"], 2),
+        ("connection refused", None, vec!["clear", "URL"], 3),
+        ("garbled challenge", Some(page(r#"<script>window.sssg_challenge(salt);</script>"#)), vec!["clear", "URL"], 4),
+        ("blocked", Some(Router::new().route("/", get(|| async { StatusCode::FORBIDDEN }))), vec!["clear", "URL"], 5),
+        ("still challenged", Some(stubborn), vec!["fetch", "URL", "--check=never", "--max-rounds", "2"], 5),
+        ("too hard", Some(page(HARD)), vec!["clear", "URL", "--max-difficulty", "16"], 6),
+        ("solve timeout", Some(page(HARD)), vec!["clear", "URL", "--threads", "1", "--solve-timeout", "1"], 7),
+        ("answer rejected", Some(rejecting()), vec!["clear", "URL"], 8),
+        ("invalid solution", None, vec!["verify", "00ff00ff", "0", "32"], 8),
+        ("check rejected", Some(unchecked), vec!["clear", "URL", "--check=always"], 9),
+        ("missing cookie file", Some(common::origin().with_state(Answers::default())), vec!["clear", "URL", "--cookies", "/nonexistent/cookies.txt"], 10),
+        ("clearance challenged", Some(common::origin().with_state(Answers::default())), vec!["check-clearance", "URL", "--clearance", "tok9"], 11),
+    ];
+
+    for (name, router, args, expected) in cases {
+        let addr = match router {
+            Some(router) => common::spawn(router).await,
+            None => closed,
+        };
+        let url = format!("http://{}/", addr);
+        let mut args: Vec<&str> = args.into_iter().map(|arg| if arg == "URL" { url.as_str() } else { arg }).collect();
+        args.extend(["--no-cache", "--max-attempts", "1"]);
+        let home = tempfile::tempdir().unwrap();
+        let output = common::run(home.path(), &args).await;
+        assert_eq!(output.status.code(), Some(expected), "{}: {}", name, String::from_utf8_lossy(&output.stderr));
+    }
+}
@@ -0,0 +1,177 @@
+//! Sends requests through `proxy` to in-process SSSG origins, over plain HTTP and over HTTPS, and
+//! checks that configured origins come back cleared while everything else passes through as is.
+
+mod common;
+
+use axum::extract::State;
+use axum::http::{header, HeaderMap};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use common::{Answers, PAGE};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::service::TowerToHyperService;
+use kiwifarms_captchabuster::config;
+use kiwifarms_captchabuster::forward_proxy::ForwardProxy;
+use kiwifarms_captchabuster::mitm::CertificateAuthority;
+use kiwifarms_captchabuster::server::{ClearanceService, ServiceOptions, SettingsSource};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+
+/// Echoes the `Cookie` header the origin received, once cleared.
+async fn echo(State(answers): State<Answers>, headers: HeaderMap) -> Response {
+    if common::cleared(&answers, &headers) {
+        let cookies = headers.get(header::COOKIE).and_then(|v| v.to_str().ok()).unwrap_or("").to_string();
+        return ([(header::CONTENT_TYPE, "text/plain")], cookies).into_response();
+    }
+    common::challenge()
+}
+
+fn origin_router(answers: &Answers) -> Router {
+    common::origin().route("/echo", get(echo)).with_state(Arc::clone(answers))
+}
+
+/// Serves `router` over HTTPS with a certificate for 127.0.0.1 from `ca`.
+async fn spawn_https(router: Router, ca: &CertificateAuthority) -> SocketAddr {
+    let acceptor = TlsAcceptor::from(ca.server_config("127.0.0.1").unwrap());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (acceptor, router) = (acceptor.clone(), router.clone());
+            tokio::spawn(async move {
+                let Ok(tls) = acceptor.accept(stream).await else { return };
+                let _ = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(tls), TowerToHyperService::new(router))
+                    .await;
+            });
+        }
+    });
+    addr
+}
+
+/// A proxy intercepting `origins` with `settings`, trusting `origin_ca` for the HTTPS origins, and
+/// a client using it that trusts both the proxy's CA and `origin_ca`.
+async fn spawn_proxy(origins: &[String], origin_ca: &CertificateAuthority, settings: config::Profile) -> (reqwest::Client, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let cacert = dir.path().join("origin-ca.pem");
+    std::fs::write(&cacert, origin_ca.cert_pem()).unwrap();
+    let overrides = config::Profile { cacert: Some(cacert), ..settings };
+    let options = ServiceOptions { use_cache: false, ..Default::default() };
+    let service = Arc::new(ClearanceService::new(SettingsSource { overrides, ..Default::default() }, options));
+
+    let (ca_cert, ca_key) = (dir.path().join("ca.pem"), dir.path().join("ca-key.pem"));
+    let (ca, created) = CertificateAuthority::load_or_create(&ca_cert, &ca_key).unwrap();
+    assert!(created);
+    let client_roots = [ca.cert_pem(), origin_ca.cert_pem()].map(|pem| reqwest::Certificate::from_pem(pem.as_bytes()).unwrap());
+    let proxy = Arc::new(ForwardProxy::new(service, ca, origins));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(proxy.serve(listener));
+
+    let mut client = reqwest::Client::builder().proxy(reqwest::Proxy::all(format!("http://{}", addr)).unwrap());
+    for root in client_roots {
+        client = client.add_root_certificate(root);
+    }
+    (client.build().unwrap(), dir)
+}
+
+async fn fetch(client: &reqwest::Client, url: String, cookie: Option<&str>) -> String {
+    let mut request = client.get(url);
+    if let Some(cookie) = cookie {
+        request = request.header(header::COOKIE, cookie);
+    }
+    request.send().await.unwrap().text().await.unwrap()
+}
+
+#[tokio::test]
+async fn plain_http_to_configured_origins_is_cleared() {
+    let answers = Answers::default();
+    let origin = common::spawn(origin_router(&answers)).await;
+    let other = common::spawn(origin_router(&Answers::default())).await;
+    let origin_ca = CertificateAuthority::generate().unwrap();
+    let (client, _dir) = spawn_proxy(&[format!("http://{}", origin)], &origin_ca, Default::default()).await;
+
+    assert_eq!(fetch(&client, format!("http://{}/threads/1", origin), None).await, PAGE);
+    // The clearance replaces a stale one, and the client's other cookies go along with it
+    let echoed = fetch(&client, format!("http://{}/echo", origin), Some("xf_session=abc; sssg_clearance=stale")).await;
+    assert_eq!(echoed, "xf_session=abc; sssg_clearance=tok1");
+    assert_eq!(answers.load(Ordering::SeqCst), 1);
+
+    // Origins that are not configured get the request as it is
+    assert!(fetch(&client, format!("http://{}/", other), None).await.contains("sssg_challenge"));
+}
+
+#[tokio::test]
+async fn https_to_configured_origins_is_intercepted() {
+    let answers = Answers::default();
+    let origin_ca = CertificateAuthority::generate().unwrap();
+    let origin = spawn_https(origin_router(&answers), &origin_ca).await;
+    let other = spawn_https(origin_router(&Answers::default()), &origin_ca).await;
+    let (client, _dir) = spawn_proxy(&[format!("https://{}", origin)], &origin_ca, Default::default()).await;
+
+    assert_eq!(fetch(&client, format!("https://{}/threads/1", origin), None).await, PAGE);
+    assert_eq!(fetch(&client, format!("https://{}/", origin), None).await, PAGE);
+    assert_eq!(answers.load(Ordering::SeqCst), 1);
+
+    // Tunnels to other hosts are connected through, with the host's own certificate
+    assert!(fetch(&client, format!("https://{}/", other), None).await.contains("sssg_challenge"));
+}
+
+#[tokio::test]
+async fn tunnels_to_other_hosts_go_through_the_configured_proxy() {
+    let origin_ca = CertificateAuthority::generate().unwrap();
+    let other = spawn_https(origin_router(&Answers::default()), &origin_ca).await;
+
+    let (upstream, log) = common::spawn_connect_proxy(other).await;
+    let settings = config::Profile { proxy: Some(format!("http://{}", upstream)), ..Default::default() };
+    let (client, _dir) = spawn_proxy(&[], &origin_ca, settings).await;
+    assert!(fetch(&client, format!("https://{}/", other), None).await.contains("sssg_challenge"));
+    assert_eq!(*log.lock().unwrap(), [format!("CONNECT {} HTTP/1.1", other)]);
+
+    let (upstream, log) = common::spawn_socks5_proxy(other).await;
+    let settings = config::Profile { proxy: Some(format!("socks5h://{}", upstream)), ..Default::default() };
+    let (client, _dir) = spawn_proxy(&[], &origin_ca, settings).await;
+    assert!(fetch(&client, format!("https://{}/", other), None).await.contains("sssg_challenge"));
+    assert_eq!(*log.lock().unwrap(), [other.to_string()]);
+
+    // Hosts exempted by --no-proxy are connected to directly
+    let (upstream, log) = common::spawn_socks5_proxy(other).await;
+    let settings = config::Profile { proxy: Some(format!("socks5h://{}", upstream)), no_proxy: Some("127.0.0.0/8".to_string()), ..Default::default() };
+    let (client, _dir) = spawn_proxy(&[], &origin_ca, settings).await;
+    assert!(fetch(&client, format!("https://{}/", other), None).await.contains("sssg_challenge"));
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn unreachable_tunnels_are_refused() {
+    let origin_ca = CertificateAuthority::generate().unwrap();
+    let settings = config::Profile { proxy: Some("http://127.0.0.1:1".to_string()), ..Default::default() };
+    let (client, _dir) = spawn_proxy(&[], &origin_ca, settings).await;
+
+    // The proxy cannot be reached, so neither can the host, and the CONNECT says so
+    let error = client.get("https://sssg.test/").send().await.unwrap_err();
+    assert!(error.is_connect(), "{:?}", error);
+}
+
+#[test]
+fn interception_ca_is_kept_on_disk() {
+    let dir = tempfile::tempdir().unwrap();
+    let (cert, key) = (dir.path().join("ca/ca.pem"), dir.path().join("ca/ca-key.pem"));
+
+    let (first, created) = CertificateAuthority::load_or_create(&cert, &key).unwrap();
+    assert!(created);
+    let (second, created) = CertificateAuthority::load_or_create(&cert, &key).unwrap();
+    assert!(!created);
+    assert_eq!(first.cert_pem(), second.cert_pem());
+    second.server_config("sssg.test").unwrap();
+
+    // A key that does not match the certificate is refused
+    let (other, _) = CertificateAuthority::load_or_create(&dir.path().join("other.pem"), &dir.path().join("other-key.pem")).unwrap();
+    std::fs::write(&cert, other.cert_pem()).unwrap();
+    assert!(CertificateAuthority::load_or_create(&cert, &key).is_err());
+}
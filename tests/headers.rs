@@ -0,0 +1,90 @@
+//! Runs `clear` against an in-process SSSG origin that records the headers of every request, and
+//! checks the page and API header sets it receives with and without `-H`, `--page-header` and
+//! `--api-header`.
+
+mod common;
+
+use axum::extract::State;
+use axum::http::{HeaderMap, Method};
+use axum::routing::{get, post};
+use axum::Router;
+use common::Answers;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The method and headers of each request, in order.
+type Requests = Arc<Mutex<Vec<(Method, HeaderMap)>>>;
+
+async fn spawn_recording() -> (std::net::SocketAddr, Requests) {
+    let requests = Requests::default();
+    let (pages, answers) = (Arc::clone(&requests), Arc::clone(&requests));
+    let router = Router::new()
+        .route("/", get(move |state: State<Answers>, headers: HeaderMap| {
+            pages.lock().unwrap().push((Method::GET, headers.clone()));
+            common::page(state, headers)
+        }))
+        .route("/.sssg/api/answer", post(move |state: State<Answers>, headers: HeaderMap| {
+            answers.lock().unwrap().push((Method::POST, headers));
+            common::answer(state)
+        }));
+    (common::spawn(router.with_state(Answers::default())).await, requests)
+}
+
+/// Clears a recording origin with `args` and returns the headers of its first page GET and of its
+/// /answer POST.
+async fn clear(home: &Path, args: &[&str]) -> (HeaderMap, HeaderMap) {
+    let (origin, requests) = spawn_recording().await;
+    let url = format!("http://{}/", origin);
+    let mut all = vec!["clear", &url, "--no-cache", "--check=never"];
+    all.extend_from_slice(args);
+    let output = common::run(home, &all).await;
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let requests = requests.lock().unwrap();
+    let first = |method: Method| requests.iter().find(|(m, _)| *m == method).unwrap().1.clone();
+    (first(Method::GET), first(Method::POST))
+}
+
+fn value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).map(|v| v.to_str().unwrap())
+}
+
+#[tokio::test]
+async fn browser_headers_are_sent_by_default() {
+    let home = tempfile::tempdir().unwrap();
+    let (page, api) = clear(home.path(), &[]).await;
+
+    for headers in [&page, &api] {
+        assert!(value(headers, "user-agent").unwrap().contains("Chrome/"));
+        assert_eq!(value(headers, "accept-language"), Some("en-US,en;q=0.5"));
+        assert_eq!(value(headers, "sec-fetch-mode"), Some("cors"));
+        assert_eq!(value(headers, "sec-gpc"), Some("1"));
+        assert!(value(headers, "referer").is_some() && value(headers, "origin").is_some());
+    }
+    // Only the POST has a body to describe
+    assert_eq!(value(&page, "content-type"), None);
+    assert_eq!(value(&api, "content-type"), Some("application/x-www-form-urlencoded"));
+}
+
+#[tokio::test]
+async fn header_options_replace_and_remove_headers() {
+    let home = tempfile::tempdir().unwrap();
+    let file = home.path().join("headers.txt");
+    std::fs::write(&file, "# Headers for the test\n\nX-From-File: yes\n   \n# Accept-Language: ignored\nSec-GPC:\n").unwrap();
+    let file = format!("@{}", file.display());
+    let args = ["-H", "User-Agent: custom", "-H", &file, "-H", "X-Both: header", "--page-header", "X-Both: page", "--page-header", "X-Page: 1", "--api-header", "X-Api: 1", "--api-header", "Cache-Control:"];
+    let (page, api) = clear(home.path(), &args).await;
+
+    for headers in [&page, &api] {
+        assert_eq!(value(headers, "user-agent"), Some("custom"));
+        assert_eq!(value(headers, "x-from-file"), Some("yes"));
+        assert_eq!(value(headers, "accept-language"), Some("en-US,en;q=0.5"));
+        assert_eq!(value(headers, "sec-gpc"), None);
+    }
+    // --page-header and --api-header apply after -H, each to its own requests
+    assert_eq!((value(&page, "x-both"), value(&api, "x-both")), (Some("page"), Some("header")));
+    assert_eq!((value(&page, "x-page"), value(&api, "x-page")), (Some("1"), None));
+    assert_eq!((value(&page, "x-api"), value(&api, "x-api")), (None, Some("1")));
+    assert_eq!(value(&page, "cache-control"), Some("no-cache"));
+    assert_eq!(value(&api, "cache-control"), None);
+}
@@ -0,0 +1,77 @@
+//! Runs the command-line tool with `--output-format json` against in-process origins, and checks
+//! that stdout holds exactly one JSON document, whether the run succeeds or fails.
+
+mod common;
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use common::Answers;
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Runs the tool with `args` and JSON output, and returns the exit code and the one document on
+/// stdout. Parsing all of stdout as one value fails the test if there is anything besides it.
+async fn run_json(home: &Path, args: &[&str]) -> (Option<i32>, Value) {
+    let mut all = args.to_vec();
+    all.extend(["--output-format", "json", "--no-cache", "--max-attempts", "1"]);
+    let output = common::run(home, &all).await;
+    let document = serde_json::from_slice(&output.stdout)
+        .unwrap_or_else(|e| panic!("{}: {}\n{}", e, String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)));
+    (output.status.code(), document)
+}
+
+#[tokio::test]
+async fn successful_runs_print_one_report() {
+    let origin = common::spawn(common::origin().with_state(Answers::default())).await;
+    let url = format!("http://{}/", origin);
+    let home = tempfile::tempdir().unwrap();
+
+    let (code, report) = run_json(home.path(), &["fetch", &url, "--check=never"]).await;
+    assert_eq!(code, Some(0));
+    assert_eq!((&report["url"], &report["origin"]), (&json!(url), &json!(format!("http://{}", origin))));
+    assert_eq!((&report["reused_clearance"], &report["nothing_to_clear"]), (&json!(false), &json!(false)));
+
+    let rounds = report["rounds"].as_array().unwrap();
+    assert_eq!(rounds.len(), 1);
+    assert_eq!((&rounds[0]["round"], &rounds[0]["challenge"]["difficulty"]), (&json!(1), &json!(4)));
+    assert_eq!(rounds[0]["check"], json!({ "policy": "never", "called": false, "reason": "the policy is never" }));
+    assert!(rounds[0]["solve"]["hashes"].as_u64().unwrap() > 0);
+
+    let steps: Vec<_> = report["requests"].as_array().unwrap().iter().map(|r| (r["method"].as_str().unwrap(), r["status"].as_u64())).collect();
+    assert_eq!(steps.first(), Some(&("GET", Some(200))));
+    assert!(steps.contains(&("POST", Some(200))), "{:?}", steps);
+
+    assert_eq!((&report["clearance"]["source"], &report["clearance"]["value"]), (&json!("/answer"), &json!("tok1")));
+    assert_eq!(report["clearance"]["cookie"]["max_age"], 3600);
+    assert_eq!(report["page"], json!({ "status": 200, "body": common::PAGE }));
+}
+
+#[tokio::test]
+async fn failed_runs_print_only_the_error() {
+    let blocked = common::spawn(Router::new().route("/", get(|| async { StatusCode::FORBIDDEN }))).await;
+    let home = tempfile::tempdir().unwrap();
+
+    let (code, report) = run_json(home.path(), &["clear", &format!("http://{}/", blocked)]).await;
+    assert_eq!(code, Some(5));
+    let report = report.as_object().unwrap();
+    assert_eq!(report.keys().collect::<Vec<_>>(), ["error", "exit_code"]);
+    assert_eq!(report["exit_code"], 5);
+    assert!(report["error"].as_str().unwrap().contains("blocked"), "{:?}", report);
+
+    // Failures outside the network get the same document
+    let (code, report) = run_json(home.path(), &["clear", "not a url"]).await;
+    assert_eq!((code, &report["exit_code"]), (Some(2), &json!(2)));
+}
+
+#[tokio::test]
+async fn failed_checks_print_only_their_report() {
+    let origin = common::spawn(common::origin().with_state(Answers::default())).await;
+    let home = tempfile::tempdir().unwrap();
+
+    let (code, report) = run_json(home.path(), &["check-clearance", &format!("http://{}/", origin), "--clearance", "tok9"]).await;
+    assert_eq!((code, &report["status"], &report["value"]), (Some(11), &json!("challenged"), &json!("tok9")));
+
+    let (code, report) = run_json(home.path(), &["verify", "00ff00ff", "0", "32"]).await;
+    assert_eq!((code, &report["valid"]), (Some(8), &json!(false)));
+}
@@ -0,0 +1,113 @@
+//! Fetches through in-process HTTP and SOCKS5 proxies to check that proxied traffic really goes
+//! through them, and that `socks5h` leaves name resolution to the proxy.
+
+mod common;
+
+use common::{Log, PAGE};
+use kiwifarms_captchabuster::network_client::{self, NetworkError, Session};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Reads an HTTP request head and answers it with `PAGE`. Returns the request line.
+async fn serve_page(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = stream.read(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", PAGE.len(), PAGE);
+    stream.write_all(response.as_bytes()).await.unwrap();
+    String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string()
+}
+
+/// A web server answering every request with `PAGE`, logging request lines.
+async fn spawn_origin() -> (SocketAddr, Log) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log = Log::default();
+    let requests = Arc::clone(&log);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let line = serve_page(&mut stream).await;
+            requests.lock().unwrap().push(line);
+        }
+    });
+    (addr, log)
+}
+
+/// A plain-HTTP forward proxy. Clients send it absolute-form requests (`GET http://host/ HTTP/1.1`);
+/// it logs the request line and answers itself rather than forwarding.
+async fn spawn_http_proxy() -> (SocketAddr, Log) {
+    spawn_origin().await
+}
+
+fn session(proxy: String, no_proxy: Option<&str>) -> Result<Session, NetworkError> {
+    Session::builder()
+        .proxy(Some(proxy))
+        .no_proxy(Some(no_proxy.unwrap_or_default().to_string()))
+        .build()
+}
+
+#[tokio::test]
+async fn http_proxy_receives_absolute_form_requests() {
+    let (proxy, log) = spawn_http_proxy().await;
+    let session = session(format!("http://{}", proxy), None).unwrap();
+
+    let page = network_client::fetch_initial_page_html(&session, "http://sssg.test/threads/1").await.unwrap();
+
+    assert_eq!(page.body, PAGE);
+    assert_eq!(*log.lock().unwrap(), ["GET http://sssg.test/threads/1 HTTP/1.1"]);
+}
+
+#[tokio::test]
+async fn socks5h_resolves_names_on_the_proxy() {
+    let (origin, requests) = spawn_origin().await;
+    let (proxy, log) = common::spawn_socks5_proxy(origin).await;
+    let session = session(format!("socks5h://{}", proxy), None).unwrap();
+
+    // Not resolvable locally; only works if the name is handed to the proxy.
+    let page = network_client::fetch_initial_page_html(&session, "http://kiwifarmsaaaaaaa.onion/").await.unwrap();
+
+    assert_eq!(page.body, PAGE);
+    assert_eq!(*log.lock().unwrap(), ["kiwifarmsaaaaaaa.onion:80"]);
+    assert_eq!(*requests.lock().unwrap(), ["GET / HTTP/1.1"]);
+}
+
+#[tokio::test]
+async fn socks5_sends_addresses() {
+    let (origin, _) = spawn_origin().await;
+    let (proxy, log) = common::spawn_socks5_proxy(origin).await;
+    let session = session(format!("socks5://{}", proxy), None).unwrap();
+
+    let page = network_client::fetch_initial_page_html(&session, &format!("http://{}/", origin)).await.unwrap();
+
+    assert_eq!(page.body, PAGE);
+    assert_eq!(*log.lock().unwrap(), [origin.to_string()]);
+}
+
+#[tokio::test]
+async fn no_proxy_hosts_bypass_the_proxy() {
+    let (origin, requests) = spawn_origin().await;
+    let (proxy, log) = spawn_http_proxy().await;
+    let session = session(format!("http://{}", proxy), Some("127.0.0.1")).unwrap();
+
+    let page = network_client::fetch_initial_page_html(&session, &format!("http://{}/", origin)).await.unwrap();
+
+    assert_eq!(page.body, PAGE);
+    assert!(log.lock().unwrap().is_empty());
+    assert_eq!(*requests.lock().unwrap(), ["GET / HTTP/1.1"]);
+}
+
+#[test]
+fn unsupported_proxy_schemes_are_rejected() {
+    let result = session("ftp://127.0.0.1:21".to_string(), None);
+
+    assert!(matches!(result, Err(NetworkError::InvalidProxy(_))));
+}
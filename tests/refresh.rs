@@ -0,0 +1,53 @@
+//! The pieces `refresh` is built from: when to replace a clearance, dropping the one a jar holds,
+//! and replacing an exported cookie file in place.
+
+use kiwifarms_captchabuster::acquire::Target;
+use kiwifarms_captchabuster::{clearance, config, cookies};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+const MINUTE: Duration = Duration::from_secs(60);
+
+#[test]
+fn refresh_is_scheduled_ahead_of_expiry() {
+    let now = UNIX_EPOCH + Duration::from_secs(1_800_000_000);
+
+    assert_eq!(clearance::refresh_time(Some(now + 60 * MINUTE), now, 5 * MINUTE, 30 * MINUTE), now + 55 * MINUTE);
+    // A clearance living no longer than the margin is replaced halfway through its life
+    assert_eq!(clearance::refresh_time(Some(now + 4 * MINUTE), now, 5 * MINUTE, 30 * MINUTE), now + 2 * MINUTE);
+    assert_eq!(clearance::refresh_time(Some(now - MINUTE), now, 5 * MINUTE, 30 * MINUTE), now);
+    // Session cookies have no expiry to go by
+    assert_eq!(clearance::refresh_time(None, now, 5 * MINUTE, 30 * MINUTE), now + 30 * MINUTE);
+}
+
+#[test]
+fn cached_clearance_can_be_forgotten() {
+    let url = Url::parse("https://sssg.test/threads/1").unwrap();
+    let expires = httpdate::fmt_http_date(SystemTime::now() + 10 * MINUTE);
+    let seed = ["sssg_clearance=abc; Path=/", "xf_session=def; Path=/"]
+        .map(|cookie| cookie_store::Cookie::parse(format!("{}; Expires={}", cookie, expires), &url).unwrap().into_owned());
+    let mut target = Target::with_cookies(url.clone(), config::Profile::default(), seed.to_vec(), false).unwrap();
+
+    assert_eq!(target.cached_clearance.as_deref(), Some("abc"));
+    let remaining = target.cached_expires_at.unwrap().duration_since(SystemTime::now()).unwrap();
+    assert!(remaining > 9 * MINUTE && remaining <= 10 * MINUTE, "{:?}", remaining);
+
+    target.forget_cached_clearance();
+    assert_eq!(target.cached_clearance, None);
+    let store = target.cookie_store.lock().unwrap();
+    let names: Vec<&str> = store.iter_unexpired().map(|c| c.name()).collect();
+    assert_eq!(names, ["xf_session"]);
+}
+
+#[test]
+fn cookie_files_are_replaced_whole() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("cookies.txt");
+
+    cookies::write_file(&path, "first\n").unwrap();
+    cookies::write_file(&path, "second\n").unwrap();
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "second\n");
+    let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+    assert_eq!(files, ["cookies.txt"]);
+}
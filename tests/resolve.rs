@@ -0,0 +1,112 @@
+//! Points a made-up hostname at a local server with `SessionBuilder::resolve` and a custom
+//! resolver, and checks that the request still looks like it went to that hostname.
+
+use kiwifarms_captchabuster::network_client::{self, NetworkError, ResolveOverride, Session};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest_cookie_store::CookieStoreMutex;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+const PAGE: &str = "<html><body>page</body></html>";
+const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+type Log = Arc<Mutex<Vec<String>>>;
+
+/// A web server answering every request with `PAGE` and a cookie scoped to `sssg.test`,
+/// logging each request head.
+async fn spawn_origin() -> (SocketAddr, Log) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let log = Log::default();
+    let requests = Arc::clone(&log);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut buf).await.unwrap();
+                if n == 0 {
+                    break;
+                }
+                head.extend_from_slice(&buf[..n]);
+            }
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nSet-Cookie: sssg_clearance=abc; Domain=sssg.test; Path=/\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                PAGE.len(),
+                PAGE
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            requests.lock().unwrap().push(String::from_utf8_lossy(&head).to_string());
+        }
+    });
+    (addr, log)
+}
+
+/// Header `name` of a logged request head.
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// Resolves every name to localhost and logs the names asked for.
+struct LocalResolver(Log);
+
+impl Resolve for LocalResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        self.0.lock().unwrap().push(name.as_str().to_string());
+        let addrs: Addrs = Box::new(std::iter::once(SocketAddr::new(LOCALHOST, 0)));
+        Box::pin(async move { Ok(addrs) })
+    }
+}
+
+#[tokio::test]
+async fn resolve_overrides_keep_host_and_cookie_domain() {
+    let (origin, requests) = spawn_origin().await;
+    let cookie_store = Arc::new(CookieStoreMutex::default());
+    let session = Session::builder().cookie_store(Arc::clone(&cookie_store)).resolve("sssg.test", &[LOCALHOST]).build().unwrap();
+    let url = format!("http://sssg.test:{}/threads/1", origin.port());
+
+    let page = network_client::fetch_initial_page_html(&session, &url).await.unwrap();
+    network_client::fetch_page_html_with_cookies(&session, &url).await.unwrap();
+
+    assert_eq!(page.body, PAGE);
+    let requests = requests.lock().unwrap();
+    assert_eq!(header(&requests[0], "Host"), Some(format!("sssg.test:{}", origin.port()).as_str()));
+    assert_eq!(header(&requests[1], "Cookie"), Some("sssg_clearance=abc"));
+    let store = cookie_store.lock().unwrap();
+    let cookie = store.get("sssg.test", "/", "sssg_clearance").expect("cookie stored for sssg.test");
+    assert_eq!(cookie.value(), "abc");
+    assert!(store.get_request_values(&Url::parse("http://127.0.0.1/").unwrap()).next().is_none());
+}
+
+#[tokio::test]
+async fn custom_resolver_is_used() {
+    let (origin, requests) = spawn_origin().await;
+    let names = Log::default();
+    let session = Session::builder().dns_resolver(Arc::new(LocalResolver(Arc::clone(&names)))).build().unwrap();
+
+    let page = network_client::fetch_initial_page_html(&session, &format!("http://sssg.test:{}/", origin.port())).await.unwrap();
+
+    assert_eq!(page.body, PAGE);
+    assert_eq!(*names.lock().unwrap(), ["sssg.test"]);
+    assert_eq!(requests.lock().unwrap().len(), 1);
+}
+
+#[test]
+fn resolve_overrides_parse_like_curl() {
+    let entry: ResolveOverride = "SSSG.test:443:127.0.0.1,[::1]".parse().unwrap();
+    assert_eq!(entry.host, "sssg.test");
+    assert_eq!(entry.port, Some(443));
+    assert_eq!(entry.addrs, [LOCALHOST, "::1".parse::<IpAddr>().unwrap()]);
+
+    assert_eq!("sssg.test:*:10.0.0.1".parse::<ResolveOverride>().unwrap().port, None);
+    for bad in ["sssg.test:443", "sssg.test:https:127.0.0.1", "sssg.test:443:localhost", ":443:127.0.0.1"] {
+        assert!(matches!(bad.parse::<ResolveOverride>(), Err(NetworkError::InvalidResolve(_))), "{}", bad);
+    }
+}
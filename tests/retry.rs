@@ -0,0 +1,100 @@
+//! Checks the retry policy's delays, `Retry-After` parsing, and which request failures count as
+//! transient: refused and dropped connections do, certificate failures do not.
+
+use kiwifarms_captchabuster::network_client::{self, RetryPolicy};
+use rcgen::{CertificateParams, KeyPair};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncReadExt;
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{self, pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer}};
+use tokio_rustls::TlsAcceptor;
+
+#[test]
+fn backoff_doubles_up_to_the_cap_with_jitter_below_it() {
+    let policy = RetryPolicy { max_attempts: 10, base_delay: Duration::from_millis(100), max_delay: Duration::from_millis(1000) };
+    for (attempt, cap) in [(1, 100), (2, 200), (3, 400), (4, 800), (5, 1000), (12, 1000), (u32::MAX, 1000)] {
+        let cap = Duration::from_millis(cap);
+        let delays: Vec<Duration> = (0..200).map(|_| policy.backoff(attempt)).collect();
+        assert!(delays.iter().all(|delay| *delay <= cap), "attempt {}: {:?} over {:?}", attempt, delays.iter().max(), cap);
+        // Full jitter: spread over the whole range rather than pinned at the cap
+        assert!(delays.iter().any(|delay| *delay < cap / 2), "attempt {}: no delay under half the cap", attempt);
+    }
+}
+
+#[test]
+fn retry_after_accepts_seconds_and_dates() {
+    let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+    assert_eq!(network_client::parse_retry_after("120", now), Some(Duration::from_secs(120)));
+    assert_eq!(network_client::parse_retry_after(" 0 ", now), Some(Duration::ZERO));
+
+    let later = httpdate::fmt_http_date(now + Duration::from_secs(90));
+    assert_eq!(network_client::parse_retry_after(&later, now), Some(Duration::from_secs(90)));
+    // A date already past means retrying right away
+    let earlier = httpdate::fmt_http_date(now - Duration::from_secs(90));
+    assert_eq!(network_client::parse_retry_after(&earlier, now), Some(Duration::ZERO));
+
+    assert_eq!(network_client::parse_retry_after("soon", now), None);
+    assert_eq!(network_client::parse_retry_after("-5", now), None);
+}
+
+async fn request_error(url: String) -> reqwest::Error {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(10)).build().unwrap();
+    client.get(url).send().await.unwrap_err()
+}
+
+#[tokio::test]
+async fn refused_and_dropped_connections_are_transient() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    let refused = request_error(format!("http://{}/", addr)).await;
+    assert!(network_client::is_transient_error(&refused), "{:?}", refused);
+
+    // Reads the request, then hangs up without answering
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read(&mut [0u8; 1024]).await;
+        }
+    });
+    let dropped = request_error(format!("http://{}/", addr)).await;
+    assert!(network_client::is_transient_error(&dropped), "{:?}", dropped);
+}
+
+/// An HTTPS server with a self-signed certificate no client trusts.
+async fn spawn_untrusted_server() -> SocketAddr {
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".to_string()]).unwrap().self_signed(&key).unwrap();
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.der().clone()], PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())))
+        .unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let _ = acceptor.accept(stream).await;
+            });
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn certificate_failures_are_not_transient() {
+    let addr = spawn_untrusted_server().await;
+    let error = request_error(format!("https://localhost:{}/", addr.port())).await;
+    assert!(error.is_connect(), "{:?}", error);
+    assert!(!network_client::is_transient_error(&error), "{:?}", error);
+}
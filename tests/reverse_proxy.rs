@@ -0,0 +1,119 @@
+//! Runs `reverse-proxy` in front of an in-process SSSG origin and checks that pages come back
+//! cleared, with the origin's URLs pointed at the local address.
+
+mod common;
+
+use axum::extract::{FromRef, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use common::Answers;
+use kiwifarms_captchabuster::reverse_proxy::{self, ReverseProxy};
+use kiwifarms_captchabuster::server::{ClearanceService, ServiceOptions, SettingsSource};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use tokio::net::TcpListener;
+use url::Url;
+
+/// The origin's state: the solutions it accepted, its own address, and the `Referer` of the last
+/// request.
+#[derive(Clone, Default)]
+struct Origin {
+    answers: Answers,
+    addr: Arc<Mutex<Option<SocketAddr>>>,
+    referer: Arc<Mutex<Option<String>>>,
+}
+
+impl Origin {
+    fn base(&self) -> String {
+        format!("http://{}", self.addr.lock().unwrap().unwrap())
+    }
+
+    fn cleared(&self, headers: &HeaderMap) -> bool {
+        *self.referer.lock().unwrap() = headers.get(header::REFERER).and_then(|v| v.to_str().ok()).map(str::to_string);
+        common::cleared(&self.answers, headers)
+    }
+}
+
+impl FromRef<Origin> for Answers {
+    fn from_ref(origin: &Origin) -> Answers {
+        Arc::clone(&origin.answers)
+    }
+}
+
+async fn page(State(origin): State<Origin>, headers: HeaderMap) -> axum::response::Response {
+    if !origin.cleared(&headers) {
+        return common::challenge();
+    }
+    let base = origin.base();
+    let host = base.trim_start_matches("http://");
+    let body = format!(
+        r#"<a href="{base}/threads/1">thread</a> <img src="//{host}/logo.png"> <a href="{base}5/">lookalike</a> <script>var api = "{escaped}\/api";</script>"#,
+        escaped = base.replace('/', "\\/"),
+    );
+    ([(header::CONTENT_TYPE, "text/html; charset=utf-8")], body).into_response()
+}
+
+async fn redirect(State(origin): State<Origin>, headers: HeaderMap) -> axum::response::Response {
+    if !origin.cleared(&headers) {
+        return common::challenge();
+    }
+    let location = format!("{}/threads/1", origin.base());
+    (StatusCode::SEE_OTHER, [(header::LOCATION, location), (header::SET_COOKIE, "xf_session=abc; Domain=127.0.0.1; Path=/; Secure; HttpOnly".to_string())]).into_response()
+}
+
+/// An SSSG origin and the reverse proxy in front of it.
+async fn setup() -> (Origin, SocketAddr, SocketAddr) {
+    let origin = Origin::default();
+    let origin_addr = common::spawn(
+        Router::new()
+            .route("/", get(page))
+            .route("/redirect", get(redirect))
+            .route("/.sssg/api/answer", post(common::answer))
+            .with_state(origin.clone()),
+    )
+    .await;
+    *origin.addr.lock().unwrap() = Some(origin_addr);
+
+    let options = ServiceOptions { use_cache: false, ..Default::default() };
+    let service = Arc::new(ClearanceService::new(SettingsSource::default(), options));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let local = listener.local_addr().unwrap();
+    let upstream = Url::parse(&format!("http://{}", origin_addr)).unwrap();
+    let proxy = Arc::new(ReverseProxy::new(service, &upstream, &Url::parse(&format!("http://{}", local)).unwrap()));
+    tokio::spawn(async move { axum::serve(listener, proxy.router()).await.unwrap() });
+    (origin, origin_addr, local)
+}
+
+#[tokio::test]
+async fn pages_are_cleared_and_point_at_the_local_address() {
+    let (origin, origin_addr, local) = setup().await;
+    let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+
+    let body = client.get(format!("http://{}/", local)).header(header::REFERER, format!("http://{}/threads/1", local)).send().await.unwrap().text().await.unwrap();
+    assert!(body.contains(&format!(r#"href="http://{}/threads/1""#, local)), "{}", body);
+    assert!(body.contains(&format!(r#"src="//{}/logo.png""#, local)), "{}", body);
+    assert!(body.contains(&format!(r#""http:\/\/{}\/api""#, local)), "{}", body);
+    // Another port on the same host is another origin
+    assert!(body.contains(&format!(r#"href="http://{}5/""#, origin_addr)), "{}", body);
+    assert_eq!(origin.referer.lock().unwrap().clone(), Some(format!("http://{}/threads/1", origin_addr)));
+    assert_eq!(origin.answers.load(Ordering::SeqCst), 1);
+
+    let response = client.get(format!("http://{}/redirect", local)).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[header::LOCATION], format!("http://{}/threads/1", local).as_str());
+    assert_eq!(response.headers()[header::SET_COOKIE], "xf_session=abc; Path=/; HttpOnly");
+    assert_eq!(origin.answers.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn only_whole_origins_are_rewritten() {
+    let rewritten = reverse_proxy::rewrite_text(
+        r#"https://sssg.test/a https://sssg.test.evil/ https://sssg.test:8443/ //sssg.test/b https:\/\/sssg.test\/c"#,
+        "https://sssg.test",
+        "http://127.0.0.1:8080",
+    );
+    assert_eq!(rewritten, r#"http://127.0.0.1:8080/a https://sssg.test.evil/ https://sssg.test:8443/ //127.0.0.1:8080/b http:\/\/127.0.0.1:8080\/c"#);
+}
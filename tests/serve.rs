@@ -0,0 +1,156 @@
+//! Runs the `serve` API against an in-process SSSG origin and checks what other programs get
+//! back: the clearance cookies, user agent and expiry, the in-memory reuse per origin, and the
+//! same through FlareSolverr's `/v1`.
+
+mod common;
+
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::{get, post};
+use axum::Router;
+use common::{Answers, PAGE};
+use kiwifarms_captchabuster::flaresolverr::V1Response;
+use kiwifarms_captchabuster::server::{self, ClearanceResponse, ClearanceService, HealthResponse, ServiceOptions, SettingsSource};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+async fn open() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/html")], PAGE)
+}
+
+/// An SSSG origin and the API in front of it.
+async fn setup() -> (SocketAddr, SocketAddr, Answers) {
+    let answers = Answers::default();
+    let origin = common::spawn(common::origin().route("/open", get(open)).with_state(Arc::clone(&answers))).await;
+    let options = ServiceOptions { use_cache: false, ..Default::default() };
+    let api = common::spawn(server::router(Arc::new(ClearanceService::new(SettingsSource::default(), options)))).await;
+    (origin, api, answers)
+}
+
+#[tokio::test]
+async fn concurrent_requests_share_one_solve() {
+    let (origin, api, answers) = setup().await;
+
+    let requests: Vec<_> = (0..10)
+        .map(|_| tokio::spawn(request(api, serde_json::json!({ "url": format!("http://{}/", origin), "force": true }))))
+        .collect();
+    for response in requests {
+        let response: ClearanceResponse = response.await.unwrap().json().await.unwrap();
+        assert_eq!(response.clearance, "tok1");
+    }
+    assert_eq!(answers.load(Ordering::SeqCst), 1);
+
+    let health: HealthResponse = reqwest::get(format!("http://{}/health", api)).await.unwrap().json().await.unwrap();
+    assert_eq!((health.solves.started, health.solves.deduplicated, health.solves.in_flight), (1, 9, 0));
+}
+
+async fn request(api: SocketAddr, body: serde_json::Value) -> reqwest::Response {
+    reqwest::Client::new().post(format!("http://{}/v1/clearance", api)).json(&body).send().await.unwrap()
+}
+
+#[tokio::test]
+async fn clearances_are_reused_per_origin() {
+    let (origin, api, answers) = setup().await;
+
+    let first: ClearanceResponse = request(api, serde_json::json!({ "url": format!("http://{}/threads/1", origin) })).await.json().await.unwrap();
+    assert_eq!(first.clearance, "tok1");
+    assert!(!first.cached);
+    assert_eq!(first.cookie_header, "sssg_clearance=tok1");
+    assert!(first.user_agent.as_deref().is_some_and(|ua| ua.starts_with("Mozilla/5.0")));
+    assert!(first.expires_in_secs.is_some_and(|secs| secs > 3500 && secs <= 3600));
+    assert_eq!(first.cookies.len(), 1);
+
+    let second: ClearanceResponse = request(api, serde_json::json!({ "url": format!("http://{}/", origin) })).await.json().await.unwrap();
+    assert_eq!(second.clearance, "tok1");
+    assert!(second.cached);
+    assert_eq!(second.url, format!("http://{}/", origin));
+
+    let forced: ClearanceResponse = request(api, serde_json::json!({ "url": format!("http://{}/", origin), "force": true })).await.json().await.unwrap();
+    assert_eq!(forced.clearance, "tok2");
+    assert_eq!(answers.load(Ordering::SeqCst), 2);
+
+    let health: HealthResponse = reqwest::get(format!("http://{}/health", api)).await.unwrap().json().await.unwrap();
+    assert_eq!(health.status, "ok");
+    assert_eq!(health.live_origins, 1);
+}
+
+#[tokio::test]
+async fn errors_are_reported_as_json() {
+    let (_, api, _) = setup().await;
+
+    let bad_url = request(api, serde_json::json!({ "url": "not a url" })).await;
+    assert_eq!(bad_url.status(), StatusCode::BAD_REQUEST);
+    let body: serde_json::Value = bad_url.json().await.unwrap();
+    assert!(body["error"].as_str().unwrap().contains("Invalid url"));
+
+    let unreachable = request(api, serde_json::json!({ "url": "http://127.0.0.1:1/" })).await;
+    assert_eq!(unreachable.status(), StatusCode::BAD_GATEWAY);
+}
+
+async fn v1(api: SocketAddr, body: serde_json::Value) -> (StatusCode, V1Response) {
+    let response = reqwest::Client::new().post(format!("http://{}/v1", api)).json(&body).send().await.unwrap();
+    (response.status(), response.json().await.unwrap())
+}
+
+#[tokio::test]
+async fn flaresolverr_requests_return_the_page_and_cookies() {
+    let (origin, api, answers) = setup().await;
+
+    let index: serde_json::Value = reqwest::get(format!("http://{}/", api)).await.unwrap().json().await.unwrap();
+    assert_eq!(index["msg"], "FlareSolverr is ready!");
+
+    let (status, response) = v1(api, serde_json::json!({ "cmd": "request.get", "url": format!("http://{}/threads/1", origin), "maxTimeout": 60000 })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!((response.status.as_str(), response.message.as_str()), ("ok", "Challenge solved!"));
+    let solution = response.solution.unwrap();
+    assert_eq!((solution.status, solution.response.as_str()), (200, PAGE));
+    assert!(solution.user_agent.starts_with("Mozilla/5.0"));
+    let cookie = solution.cookies.iter().find(|c| c.name == "sssg_clearance").unwrap();
+    assert_eq!(cookie.value, "tok1");
+    assert!(cookie.http_only && !cookie.session && cookie.expires > 0.0);
+
+    let (_, response) = v1(api, serde_json::json!({ "cmd": "request.get", "url": format!("http://{}/open", origin), "returnOnlyCookies": true })).await;
+    assert_eq!(response.message, "Challenge not detected!");
+    assert_eq!(response.solution.unwrap().response, "");
+    assert_eq!(answers.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn flaresolverr_sessions_keep_their_cookies() {
+    let (origin, api, answers) = setup().await;
+
+    let (_, created) = v1(api, serde_json::json!({ "cmd": "sessions.create", "session": "crawl" })).await;
+    assert_eq!(created.session.as_deref(), Some("crawl"));
+    let request = serde_json::json!({ "cmd": "request.get", "url": format!("http://{}/", origin), "session": "crawl" });
+    let (_, first) = v1(api, request.clone()).await;
+    assert_eq!(first.message, "Challenge solved!");
+    // The session's jar already holds the clearance, so the page is not challenged again
+    let (_, second) = v1(api, request).await;
+    assert_eq!(second.message, "Challenge not detected!");
+    assert_eq!(answers.load(Ordering::SeqCst), 1);
+
+    let (_, listed) = v1(api, serde_json::json!({ "cmd": "sessions.list" })).await;
+    assert_eq!(listed.sessions.unwrap(), ["crawl"]);
+    let (status, _) = v1(api, serde_json::json!({ "cmd": "sessions.destroy", "session": "crawl" })).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, gone) = v1(api, serde_json::json!({ "cmd": "sessions.destroy", "session": "crawl" })).await;
+    assert_eq!((status, gone.status.as_str()), (StatusCode::INTERNAL_SERVER_ERROR, "error"));
+}
+
+#[tokio::test]
+async fn flaresolverr_posts_are_sent_once() {
+    let posts = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&posts);
+    let busy = Router::new().route("/form", post(move || {
+        counted.fetch_add(1, Ordering::SeqCst);
+        async { (StatusCode::SERVICE_UNAVAILABLE, "busy") }
+    }));
+    let origin = common::spawn(busy).await;
+    let (_, api, _) = setup().await;
+
+    let (_, response) = v1(api, serde_json::json!({ "cmd": "request.post", "url": format!("http://{}/form", origin), "postData": "message=hi" })).await;
+    assert_eq!(response.solution.unwrap().status, 503);
+    // A GET answered with 503 would be retried, but the form may have been acted on
+    assert_eq!(posts.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,79 @@
+//! Runs concurrent callers through `SingleFlight` and checks that they share one run of the work,
+//! its failures included, and that abandoned work is picked up again.
+
+use kiwifarms_captchabuster::single_flight::{FlightStats, SingleFlight};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+async fn slow<T>(runs: &AtomicUsize, result: Result<T, String>) -> Result<T, String> {
+    runs.fetch_add(1, Ordering::SeqCst);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    result
+}
+
+#[tokio::test]
+async fn concurrent_callers_share_one_run() {
+    let flights = Arc::new(SingleFlight::<String, String>::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let callers: Vec<_> = (0..10)
+        .map(|_| {
+            let (flights, runs) = (Arc::clone(&flights), Arc::clone(&runs));
+            tokio::spawn(async move { flights.run("https://sssg.test", || slow(&runs, Ok("tok1".to_string()))).await })
+        })
+        .collect();
+    for caller in callers {
+        assert_eq!(caller.await.unwrap().unwrap(), "tok1");
+    }
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert_eq!(flights.stats(), FlightStats { started: 1, deduplicated: 9, failed: 0, in_flight: 0 });
+
+    // Once done, the next caller runs the work again, and other keys never waited on it
+    flights.run("https://sssg.test", || slow(&runs, Ok("tok2".to_string()))).await.unwrap();
+    flights.run("https://other.test", || slow(&runs, Ok("tok1".to_string()))).await.unwrap();
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn failures_go_to_every_waiter() {
+    let flights = Arc::new(SingleFlight::<String, String>::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let callers: Vec<_> = (0..5)
+        .map(|_| {
+            let (flights, runs) = (Arc::clone(&flights), Arc::clone(&runs));
+            tokio::spawn(async move { flights.run("https://sssg.test", || slow(&runs, Err("blocked".to_string()))).await })
+        })
+        .collect();
+    let mut errors = Vec::new();
+    for caller in callers {
+        errors.push(caller.await.unwrap().unwrap_err());
+    }
+    assert!(errors.iter().all(|err| Arc::ptr_eq(err, &errors[0]) && err.as_str() == "blocked"));
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+    assert_eq!(flights.stats(), FlightStats { started: 1, deduplicated: 4, failed: 1, in_flight: 0 });
+}
+
+#[tokio::test]
+async fn abandoned_work_is_taken_over() {
+    let flights = Arc::new(SingleFlight::<String, String>::new());
+    let runs = Arc::new(AtomicUsize::new(0));
+
+    let leader = {
+        let (flights, runs) = (Arc::clone(&flights), Arc::clone(&runs));
+        tokio::spawn(async move { flights.run("https://sssg.test", || slow(&runs, Ok("tok1".to_string()))).await })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let waiter = {
+        let (flights, runs) = (Arc::clone(&flights), Arc::clone(&runs));
+        tokio::spawn(async move { flights.run("https://sssg.test", || slow(&runs, Ok("tok2".to_string()))).await })
+    };
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    leader.abort();
+
+    assert_eq!(waiter.await.unwrap().unwrap(), "tok2");
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+    // The waiter ran the work itself, so it was not deduplicated
+    assert_eq!(flights.stats(), FlightStats { started: 2, deduplicated: 0, failed: 0, in_flight: 0 });
+}
@@ -0,0 +1,65 @@
+//! Runs the command-line tool against local listeners that accept connections and never answer,
+//! and checks which timeout each limit is reported as.
+
+mod common;
+
+use axum::response::Html;
+use axum::routing::get;
+use axum::Router;
+use std::time::{Duration, Instant};
+
+/// Runs `clear` on `url` with a single attempt per step, and returns the exit code and what was
+/// printed to stderr.
+async fn clear(url: &str, options: &[&str]) -> (Option<i32>, String) {
+    let home = tempfile::tempdir().unwrap();
+    let mut args = vec!["clear", url, "--no-cache", "--max-attempts", "1"];
+    args.extend_from_slice(options);
+    let output = common::run(home.path(), &args).await;
+    (output.status.code(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[tokio::test]
+async fn a_silent_handshake_is_a_connect_timeout() {
+    // The TLS handshake is part of connecting, and the listener never answers it
+    let silent = common::spawn_silent().await;
+    let (code, stderr) = clear(&format!("https://{}/", silent), &["--connect-timeout", "1", "--read-timeout", "0", "--timeout", "0"]).await;
+    assert_eq!(code, Some(3), "{}", stderr);
+    assert!(stderr.contains("(connect timeout)"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn a_silent_response_is_a_read_timeout() {
+    let silent = common::spawn_silent().await;
+    let (code, stderr) = clear(&format!("http://{}/", silent), &["--read-timeout", "1", "--timeout", "0"]).await;
+    assert_eq!(code, Some(3), "{}", stderr);
+    assert!(stderr.contains("(read timeout)"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn a_step_running_too_long_is_an_operation_timeout() {
+    let silent = common::spawn_silent().await;
+    let (code, stderr) = clear(&format!("http://{}/", silent), &["--read-timeout", "0", "--timeout", "1"]).await;
+    assert_eq!(code, Some(3), "{}", stderr);
+    assert!(stderr.contains("(operation timeout) during"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn the_deadline_ends_a_step_before_its_operation_timeout() {
+    let silent = common::spawn_silent().await;
+    let (code, stderr) = clear(&format!("http://{}/", silent), &["--read-timeout", "0", "--timeout", "60", "--deadline", "1"]).await;
+    assert_eq!(code, Some(3), "{}", stderr);
+    assert!(stderr.contains("(deadline timeout) during"), "{}", stderr);
+}
+
+#[tokio::test]
+async fn the_deadline_shortens_the_solve_timeout() {
+    // Far too hard to solve in a second, and the challenge itself would allow a minute
+    let hard = r#"<html><script>window.sssg_challenge("00ff00ff", 32, 60000);</script></html>"#;
+    let origin = common::spawn(Router::new().route("/", get(move || async move { Html(hard) }))).await;
+
+    let started = Instant::now();
+    let (code, stderr) = clear(&format!("http://{}/", origin), &["--threads", "1", "--solve-timeout", "60", "--deadline", "1"]).await;
+    assert_eq!(code, Some(3), "{}", stderr);
+    assert!(stderr.contains("(deadline timeout) during solving"), "{}", stderr);
+    assert!(started.elapsed() < Duration::from_secs(30), "solving went on for {:.2?}", started.elapsed());
+}
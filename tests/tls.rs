@@ -0,0 +1,166 @@
+//! Fetches from an in-process HTTPS server whose certificate comes from a throwaway CA, to check
+//! custom CA bundles, client certificates and `danger_accept_invalid_certs`.
+
+use kiwifarms_captchabuster::network_client::{self, NetworkError, Session, SessionBuilder};
+use rcgen::{BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa, KeyPair};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{self, pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer}, RootCertStore};
+use tokio_rustls::TlsAcceptor;
+
+const PAGE: &str = "<html><body>page</body></html>";
+const HOST: &str = "sssg.test";
+
+/// A CA, a server certificate for `HOST` and a client certificate, all written out as PEM.
+struct Pki {
+    dir: tempfile::TempDir,
+    ca: CertificateDer<'static>,
+    server: (CertificateDer<'static>, PrivateKeyDer<'static>),
+}
+
+impl Pki {
+    fn new() -> Pki {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        ca_params.distinguished_name.push(DnType::CommonName, "Test CA");
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let issue = |names: Vec<String>, usage: ExtendedKeyUsagePurpose| {
+            let key = KeyPair::generate().unwrap();
+            let mut params = CertificateParams::new(names).unwrap();
+            params.extended_key_usages = vec![usage];
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            (cert, key)
+        };
+        let (server_cert, server_key) = issue(vec![HOST.to_string()], ExtendedKeyUsagePurpose::ServerAuth);
+        let (client_cert, client_key) = issue(vec!["client".to_string()], ExtendedKeyUsagePurpose::ClientAuth);
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("ca.pem"), ca.pem()).unwrap();
+        std::fs::write(dir.path().join("client.pem"), client_cert.pem()).unwrap();
+        std::fs::write(dir.path().join("client.key"), client_key.serialize_pem()).unwrap();
+        std::fs::write(dir.path().join("client-with-key.pem"), client_cert.pem() + &client_key.serialize_pem()).unwrap();
+        Pki {
+            dir,
+            ca: ca.der().clone(),
+            server: (server_cert.der().clone(), PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(server_key.serialize_der()))),
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+}
+
+/// An HTTPS server answering every request with `PAGE`. With `require_client_cert`, clients must
+/// present a certificate issued by the test CA.
+async fn spawn_server(pki: &Pki, require_client_cert: bool) -> SocketAddr {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(Arc::clone(&provider)).with_safe_default_protocol_versions().unwrap();
+    let builder = if require_client_cert {
+        let mut roots = RootCertStore::empty();
+        roots.add(pki.ca.clone()).unwrap();
+        let verifier = rustls::server::WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build().unwrap();
+        builder.with_client_cert_verifier(verifier)
+    } else {
+        builder.with_no_client_auth()
+    };
+    let config = builder.with_single_cert(vec![pki.server.0.clone()], pki.server.1.clone_key()).unwrap();
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let acceptor = acceptor.clone();
+            tokio::spawn(async move {
+                let Ok(mut stream) = acceptor.accept(stream).await else {
+                    return;
+                };
+                let mut head = Vec::new();
+                let mut buf = [0u8; 1024];
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", PAGE.len(), PAGE);
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+fn builder() -> SessionBuilder {
+    Session::builder().resolve(HOST, &[IpAddr::V4(Ipv4Addr::LOCALHOST)])
+}
+
+async fn fetch(session: &Session, addr: SocketAddr) -> Result<String, NetworkError> {
+    let url = format!("https://{}:{}/", HOST, addr.port());
+    network_client::fetch_initial_page_html(session, &url).await.map(|page| page.body)
+}
+
+fn ca(pki: &Pki) -> Vec<reqwest::Certificate> {
+    network_client::load_certificates(&pki.path("ca.pem")).unwrap()
+}
+
+#[tokio::test]
+async fn untrusted_certificates_are_rejected() {
+    let pki = Pki::new();
+    let addr = spawn_server(&pki, false).await;
+    let session = builder().retry(network_client::RetryPolicy { max_attempts: 1, ..Default::default() }).build().unwrap();
+
+    assert!(fetch(&session, addr).await.is_err());
+}
+
+#[tokio::test]
+async fn custom_ca_bundle_is_trusted() {
+    let pki = Pki::new();
+    let addr = spawn_server(&pki, false).await;
+    let session = builder().root_certificates(ca(&pki)).build().unwrap();
+
+    assert_eq!(fetch(&session, addr).await.unwrap(), PAGE);
+}
+
+#[tokio::test]
+async fn insecure_skips_verification() {
+    let pki = Pki::new();
+    let addr = spawn_server(&pki, false).await;
+    let session = builder().danger_accept_invalid_certs(true).build().unwrap();
+
+    assert_eq!(fetch(&session, addr).await.unwrap(), PAGE);
+}
+
+#[tokio::test]
+async fn client_certificates_are_presented() {
+    let pki = Pki::new();
+    let addr = spawn_server(&pki, true).await;
+
+    let anonymous = builder().root_certificates(ca(&pki)).retry(network_client::RetryPolicy { max_attempts: 1, ..Default::default() }).build().unwrap();
+    assert!(fetch(&anonymous, addr).await.is_err());
+
+    let separate_key = network_client::load_identity(&pki.path("client.pem"), Some(&pki.path("client.key"))).unwrap();
+    let session = builder().root_certificates(ca(&pki)).identity(Some(separate_key)).build().unwrap();
+    assert_eq!(fetch(&session, addr).await.unwrap(), PAGE);
+
+    let combined = network_client::load_identity(&pki.path("client-with-key.pem"), None).unwrap();
+    let session = builder().root_certificates(ca(&pki)).identity(Some(combined)).build().unwrap();
+    assert_eq!(fetch(&session, addr).await.unwrap(), PAGE);
+}
+
+#[test]
+fn unreadable_tls_files_are_reported() {
+    let pki = Pki::new();
+
+    assert!(matches!(network_client::load_certificates(&pki.path("client.key")), Err(NetworkError::InvalidTls(_))));
+    assert!(matches!(network_client::load_certificates(Path::new("/nonexistent/ca.pem")), Err(NetworkError::InvalidTls(_))));
+    assert!(matches!(network_client::load_identity(&pki.path("client.pem"), None), Err(NetworkError::InvalidTls(_))));
+}